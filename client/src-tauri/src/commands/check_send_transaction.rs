@@ -1,11 +1,12 @@
 use nexium::{
-    blockchain::consts::estimate_classic_transaction_fee, gitlab::GitlabClient,
+    blockchain::{amount::Amount, consts::estimate_classic_transaction_fee},
+    gitlab::GitlabClient,
     login::Login,
 };
 
 use crate::core::{
     config::Config,
-    nexium_api::{get_balance, ClassicTransactionSent, NexiumAPIError},
+    nexium_api::{get_balance_amount, ClassicTransactionSent, NexiumAPIError},
 };
 
 #[tauri::command]
@@ -14,9 +15,9 @@ pub async fn check_send_transaction(
     config: Config,
 ) -> Result<(), String> {
     let result = tauri::async_runtime::spawn_blocking(move || {
-        let amount = match transaction.amount.parse::<f64>() {
+        let amount = match transaction.amount.parse::<Amount>() {
             Ok(amount) => {
-                if amount <= 0.0 {
+                if amount.is_zero() {
                     return Err(NexiumAPIError::InvalidAmount.to_string());
                 }
                 amount
@@ -35,16 +36,15 @@ pub async fn check_send_transaction(
         let fee_cost = estimate_classic_transaction_fee(fees, has_description);
 
         // Total cost = amount + fees
-        let total_cost = amount + fee_cost;
+        let total_cost = match amount.checked_add(fee_cost) {
+            Some(c) => c,
+            None => return Err(NexiumAPIError::InvalidAmount.to_string()),
+        };
 
         let available_balance =
-            match get_balance(config.user_login.clone(), config.clone()) {
-                Ok(balance_info) => format!(
-                    "{}.{}",
-                    balance_info.integer_part, balance_info.decimal_part
-                )
-                .parse::<f64>()
-                .unwrap_or(0.0),
+            match get_balance_amount(config.user_login.clone(), config.clone())
+            {
+                Ok(balance) => balance,
                 Err(_) => {
                     return Err(NexiumAPIError::BalanceFetchError.to_string())
                 }
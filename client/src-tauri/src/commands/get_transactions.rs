@@ -2,16 +2,16 @@ use crate::core::config::Config;
 use crate::core::nexium_api::{
     get_transactions as get_transactions_api, NexiumAPIError,
 };
-use crate::types::classic_tr_received::ClassicTransactionReceived;
+use crate::types::transaction_page::{HistoryQuery, TransactionPage};
 
 #[tauri::command]
 pub async fn get_transactions(
     config: Config,
     login: String,
-    n: u32,
-) -> Result<Vec<ClassicTransactionReceived>, String> {
+    query: HistoryQuery,
+) -> Result<TransactionPage, String> {
     tauri::async_runtime::spawn_blocking(move || {
-        get_transactions_api(config, login, n)
+        get_transactions_api(config, login, query)
     })
     .await
     .map_err(|_| NexiumAPIError::UnknownError.to_string())?
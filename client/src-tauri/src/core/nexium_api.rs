@@ -2,15 +2,20 @@ use crate::types::balance::BalanceInfo;
 use crate::types::classic_tr_received::ClassicTransactionReceived;
 use crate::types::classic_tr_received::ClassicTransactionReceivedType;
 use crate::types::server_infos::ServerInfos;
+use crate::types::transaction_page::{HistoryQuery, TransactionPage};
 
 use super::config::*;
 use chrono::DateTime;
 use json;
+use nexium::api::error::{ApiError, ErrorCode};
+use nexium::blockchain::amount::Amount;
 use nexium::blockchain::transaction::*;
 use nexium::blockchain::transaction_data::*;
 use nexium::defaults::*;
 use nexium::gitlab::*;
+use nexium::rsa::request::*;
 use nexium::rsa::*;
+use nexium::utils::time::current_time;
 use num_bigint::BigUint;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
@@ -40,6 +45,20 @@ pub enum NexiumAPIError {
     ReceiverNotFound,
     InvalidReceiver,
     SenderAndReceiverSame,
+    InvalidNonce,
+    InvalidRequest,
+    AuthenticationFailed,
+    TooManyRequests,
+    MalformedTransaction,
+    UnknownEmitter,
+    InvalidTransactionSignature,
+    NonceConflict,
+    DuplicateTransaction,
+    FeesTooLow,
+    TransactionNotFound,
+    NotFound,
+    PeerBanned,
+    ServerUnavailable,
 }
 
 impl fmt::Display for NexiumAPIError {
@@ -69,6 +88,9 @@ impl fmt::Display for NexiumAPIError {
                 "Impossible de récupérer le champ de solde."
             }
             NexiumAPIError::InvalidBalanceFormat => "Format de solde invalide.",
+            NexiumAPIError::InvalidNonce => {
+                "Nonce du compte absent ou invalide dans la réponse du serveur."
+            }
             NexiumAPIError::NegativeOrZeroAmount => {
                 "Le montant doit être supérieur à zéro."
             }
@@ -94,11 +116,111 @@ impl fmt::Display for NexiumAPIError {
             NexiumAPIError::SenderAndReceiverSame => {
                 "Le destinataire et l'expéditeur de la transaction sont identiques."
             }
+            NexiumAPIError::InvalidRequest => {
+                "Requête refusée par le serveur."
+            }
+            NexiumAPIError::AuthenticationFailed => {
+                "Échec de l'authentification auprès du serveur."
+            }
+            NexiumAPIError::TooManyRequests => {
+                "Trop de requêtes, réessayez plus tard."
+            }
+            NexiumAPIError::MalformedTransaction => {
+                "Transaction mal formée."
+            }
+            NexiumAPIError::UnknownEmitter => {
+                "L'émetteur de la transaction est inconnu du serveur."
+            }
+            NexiumAPIError::InvalidTransactionSignature => {
+                "Signature de la transaction invalide."
+            }
+            NexiumAPIError::NonceConflict => {
+                "Nonce de la transaction déjà utilisé ou inattendu."
+            }
+            NexiumAPIError::DuplicateTransaction => {
+                "Cette transaction a déjà été envoyée."
+            }
+            NexiumAPIError::FeesTooLow => {
+                "Le serveur est saturé, augmentez les frais de la transaction."
+            }
+            NexiumAPIError::TransactionNotFound => "Transaction introuvable.",
+            NexiumAPIError::NotFound => "Ressource introuvable sur le serveur.",
+            NexiumAPIError::PeerBanned => "Pair banni par le serveur.",
+            NexiumAPIError::ServerUnavailable => {
+                "Le serveur est temporairement indisponible."
+            }
         };
         write!(f, "{}", msg)
     }
 }
 
+impl From<ErrorCode> for NexiumAPIError {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::MalformedRequest
+            | ErrorCode::HeadersTooLarge
+            | ErrorCode::PayloadTooLarge
+            | ErrorCode::RequestTimeout
+            | ErrorCode::InvalidParameter
+            | ErrorCode::InvalidTransactionId => NexiumAPIError::InvalidRequest,
+            ErrorCode::MissingHeader
+            | ErrorCode::InvalidTimestamp
+            | ErrorCode::RequestExpired
+            | ErrorCode::InvalidNonce
+            | ErrorCode::InvalidSignature => {
+                NexiumAPIError::AuthenticationFailed
+            }
+            ErrorCode::TooManyChallenges => NexiumAPIError::TooManyRequests,
+            ErrorCode::InvalidEnvelope | ErrorCode::MalformedTransaction => {
+                NexiumAPIError::MalformedTransaction
+            }
+            ErrorCode::UnknownEmitter => NexiumAPIError::UnknownEmitter,
+            ErrorCode::InvalidTransactionSignature => {
+                NexiumAPIError::InvalidTransactionSignature
+            }
+            ErrorCode::MissingNonce
+            | ErrorCode::NonceAlreadyUsed
+            | ErrorCode::NonceGap => NexiumAPIError::NonceConflict,
+            ErrorCode::DuplicateTransaction => {
+                NexiumAPIError::DuplicateTransaction
+            }
+            ErrorCode::InsufficientBalance => NexiumAPIError::InsufficientFunds,
+            ErrorCode::MempoolFull => NexiumAPIError::FeesTooLow,
+            ErrorCode::TransactionNotFound => {
+                NexiumAPIError::TransactionNotFound
+            }
+            ErrorCode::NotFound => NexiumAPIError::NotFound,
+            ErrorCode::PeerBanned => NexiumAPIError::PeerBanned,
+            ErrorCode::InternalError
+            | ErrorCode::KeysUnavailable
+            | ErrorCode::AccountUnavailable
+            | ErrorCode::ChainUnavailable => NexiumAPIError::ServerUnavailable,
+            ErrorCode::InvalidPeer
+            | ErrorCode::MalformedBlock
+            | ErrorCode::UnknownBlock
+            | ErrorCode::BlockDoesNotConnect
+            | ErrorCode::BlockRejected
+            | ErrorCode::Unknown => NexiumAPIError::InvalidResponseFromServer,
+        }
+    }
+}
+
+/// Error message of a failed response, from its `ApiError` body when the
+/// server sent one
+fn error_from_response(response: reqwest::blocking::Response) -> String {
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+
+    match ApiError::from_json(&body) {
+        Some(e) => NexiumAPIError::from(e.code).to_string(),
+        None => format!(
+            "{}: {}",
+            NexiumAPIError::InvalidResponseFromServer.to_string(),
+            status
+        ),
+    }
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct ClassicTransactionSent {
@@ -120,20 +242,41 @@ pub struct UserStats {
     pub rank: u64,
 }
 
+fn header_value(value: &str) -> Result<reqwest::header::HeaderValue, String> {
+    match reqwest::header::HeaderValue::from_str(value) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Ask the server for a nonce to sign in the next request
+fn get_challenge(config: &Config) -> Result<String, String> {
+    let url = build_url(config, "/challenge");
+
+    let client = Client::new();
+    let response = match client.get(&url).send() {
+        Ok(r) => r,
+        Err(_) => return Err(NexiumAPIError::NoServerResponse.to_string()),
+    };
+
+    if !response.status().is_success() {
+        return Err(error_from_response(response));
+    }
+
+    match response.text() {
+        Ok(t) => Ok(t.trim().to_string()),
+        Err(_) => Err(NexiumAPIError::NoServerResponse.to_string()),
+    }
+}
+
+/// Headers authenticating a `method` request to `endpoint` with `body`,
+/// see `nexium::rsa::request`
 fn build_headers(
     config: &Config,
+    method: &str,
+    endpoint: &str,
+    body: &str,
 ) -> Result<reqwest::header::HeaderMap, String> {
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        "Login",
-        match reqwest::header::HeaderValue::from_str(&config.user_login) {
-            Ok(l) => l,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        },
-    );
-
     let private_key = match KeyPair::priv_from_pem(
         &config.priv_key,
         &config.password,
@@ -145,23 +288,24 @@ fn build_headers(
         }
     };
 
-    let signature = match private_key.sign(SIG_SAMPLE) {
+    let nonce = get_challenge(config)?;
+    let timestamp = current_time();
+    let path = format!("/{}", endpoint.trim_start_matches('/'));
+    let message =
+        request_message(method, &path, timestamp, &nonce, body.as_bytes());
+
+    let signature = match private_key.sign_pkcs1_v15(&message) {
         Ok(sig) => sig,
         Err(e) => {
             return Err(e.to_string());
         }
     };
 
-    headers.insert(
-        "Sig-Sample",
-        match reqwest::header::HeaderValue::from_str(&signature.to_string()) {
-            Ok(sig) => sig,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        },
-    );
-
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(LOGIN_HEADER, header_value(&config.user_login)?);
+    headers.insert(NONCE_HEADER, header_value(&nonce)?);
+    headers.insert(TIMESTAMP_HEADER, header_value(&timestamp.to_string())?);
+    headers.insert(SIGNATURE_HEADER, header_value(&signature.to_string())?);
     headers.insert(
         "Content-Type",
         reqwest::header::HeaderValue::from_static("text/plain"),
@@ -179,7 +323,7 @@ fn build_url(config: &Config, endpoint: &str) -> String {
 }
 
 pub fn get_server_key_login(config: Config) -> Result<ServerInfos, String> {
-    let headers = match build_headers(&config) {
+    let headers = match build_headers(&config, "GET", "/nexium", "") {
         Ok(h) => h,
         Err(e) => return Err(e.to_string()),
     };
@@ -201,15 +345,8 @@ pub fn get_server_key_login(config: Config) -> Result<ServerInfos, String> {
         Err(e) => return Err(e.to_string()),
     };
 
-    match response.status() {
-        reqwest::StatusCode::OK => {}
-        e => {
-            return Err(format!(
-                "{}: {}",
-                NexiumAPIError::InvalidResponseFromServer.to_string(),
-                e.to_string()
-            ));
-        }
+    if response.status() != reqwest::StatusCode::OK {
+        return Err(error_from_response(response));
     }
 
     let response_text = match response.text() {
@@ -217,7 +354,7 @@ pub fn get_server_key_login(config: Config) -> Result<ServerInfos, String> {
         Err(_) => return Err(NexiumAPIError::NoServerResponse.to_string()),
     };
 
-    let decrypted_response = match client_key.decrypt_split(&response_text) {
+    let decrypted_response = match client_key.open(&response_text) {
         Ok(d) => d,
         Err(e) => return Err(e.to_string()),
     };
@@ -281,8 +418,6 @@ pub fn send_transaction(
     transaction: ClassicTransactionSent,
     config: Config,
 ) -> Result<(), String> {
-    let headers = build_headers(&config);
-
     let client_key = match KeyPair::priv_from_pem(
         &config.priv_key,
         &config.password,
@@ -292,9 +427,9 @@ pub fn send_transaction(
         Err(e) => return Err(e.to_string()),
     };
 
-    let amount = match transaction.amount.parse::<f32>() {
+    let amount = match transaction.amount.parse::<Amount>() {
         Ok(n) => {
-            if n <= 0.0 {
+            if n.is_zero() {
                 return Err(NexiumAPIError::NegativeOrZeroAmount.to_string());
             }
             n
@@ -309,12 +444,15 @@ pub fn send_transaction(
         Err(_) => return Err(NexiumAPIError::InvalidFees.to_string()),
     };
 
+    let nonce = get_next_nonce(config.user_login.clone(), config.clone())?;
+
     let transaction = match Transaction::new_classic(
         &transaction.receiver,
         amount,
         &transaction.description,
         fees,
         &config.user_login,
+        nonce,
         &client_key,
     ) {
         Ok(t) => t,
@@ -332,11 +470,14 @@ pub fn send_transaction(
             Err(e) => return Err(e.to_string()),
         };
 
-    let encrypted_body = match server_pubkey.crypt_split(&body) {
+    let encrypted_body = match server_pubkey.seal(&body) {
         Ok(e) => e,
         Err(e) => return Err(e.to_string()),
     };
 
+    let headers =
+        build_headers(&config, "POST", "/new_transaction", &encrypted_body)?;
+
     let url = build_url(&config, "/new_transaction");
     let client = Client::new();
     let mut retries = 0;
@@ -344,24 +485,15 @@ pub fn send_transaction(
     let response = loop {
         let resp = client
             .post(&url)
-            .headers(headers.clone().unwrap_or_default())
+            .headers(headers.clone())
             .body(encrypted_body.clone())
             .send();
         match resp {
-            Ok(r) => {
-                if r.status().is_success() {
-                    break r;
-                } else if retries < max_retries {
-                    retries += 1;
-                    continue;
-                } else {
-                    return Err(format!(
-                        "{}: {}",
-                        NexiumAPIError::InvalidResponseFromServer.to_string(),
-                        r.status()
-                    ));
-                }
-            }
+            // The server answered, retrying would only send the same
+            // transaction again
+            Ok(r) => break r,
+            // The nonce makes the server reject a transaction sent twice if
+            // the first attempt actually went through
             Err(e) => {
                 if retries < max_retries {
                     retries += 1;
@@ -374,25 +506,32 @@ pub fn send_transaction(
     };
 
     if !response.status().is_success() {
-        return Err(format!(
-            "{}: {}",
-            NexiumAPIError::InvalidResponseFromServer.to_string(),
-            response.status()
-        ));
+        return Err(error_from_response(response));
     };
     return Ok(());
 }
 
-pub fn get_balance(
+/// Read an amount sent by the server, either as a decimal string or, for
+/// older servers, as a JSON number
+fn json_amount(value: &json::JsonValue) -> Option<Amount> {
+    match value.as_str() {
+        Some(s) => s.parse::<Amount>().ok(),
+        None => value.as_f64().and_then(Amount::from_nex_f64),
+    }
+}
+
+/// Fetch and decrypt the account info (balance and next nonce) of `login`
+fn get_account_json(
     login: String,
     config: Config,
-) -> Result<BalanceInfo, String> {
-    let headers = match build_headers(&config) {
+) -> Result<json::JsonValue, String> {
+    let endpoint = format!("/balance/{}", login);
+    let headers = match build_headers(&config, "GET", &endpoint, "") {
         Ok(h) => h,
         Err(e) => return Err(e),
     };
 
-    let url = build_url(&config, &format!("/balance/{}", login));
+    let url = build_url(&config, &endpoint);
 
     let client = Client::new();
     let response = match client.get(&url).headers(headers).send() {
@@ -401,11 +540,7 @@ pub fn get_balance(
     };
 
     if !response.status().is_success() {
-        return Err(format!(
-            "{}: {}",
-            NexiumAPIError::InvalidResponseFromServer.to_string(),
-            response.status()
-        ));
+        return Err(error_from_response(response));
     }
 
     let response_text = match response.text() {
@@ -421,67 +556,98 @@ pub fn get_balance(
         Err(e) => return Err(e.to_string()),
     };
 
-    let uncrypted_response = match client_key.decrypt_split(&response_text) {
+    let uncrypted_response = match client_key.open(&response_text) {
         Ok(d) => d,
         Err(e) => return Err(e.to_string()),
     };
 
-    let json = match json::parse(&uncrypted_response) {
-        Ok(j) => j,
-        Err(_) => return Err(NexiumAPIError::InvalidJsonResponse.to_string()),
-    };
+    match json::parse(&uncrypted_response) {
+        Ok(j) => Ok(j),
+        Err(_) => Err(NexiumAPIError::InvalidJsonResponse.to_string()),
+    }
+}
 
-    let balance_str = match json["balance"].as_str() {
-        Some(b) => b.to_string(),
-        None => {
-            if let Some(num) = json["balance"].as_i64() {
-                num.to_string()
-            } else if let Some(num) = json["balance"].as_f64() {
-                num.to_string()
-            } else {
-                return Err(NexiumAPIError::NoBalanceField.to_string());
-            }
-        }
-    };
+pub fn get_balance_amount(
+    login: String,
+    config: Config,
+) -> Result<Amount, String> {
+    let json = get_account_json(login, config)?;
 
-    let parts: Vec<&str> = balance_str.split('.').collect();
+    if json["balance"].is_null() {
+        return Err(NexiumAPIError::NoBalanceField.to_string());
+    }
 
-    let part0 = match parts.get(0) {
-        Some(p) => p.to_string(),
-        None => "0".to_string(),
-    };
-    let part1 = match parts.get(1) {
-        Some(p) => p.to_string(),
-        None => "0".to_string(),
-    };
+    match json_amount(&json["balance"]) {
+        Some(b) => Ok(b),
+        None => Err(NexiumAPIError::InvalidBalanceFormat.to_string()),
+    }
+}
+
+/// Nonce to put in the next transaction of `login`
+pub fn get_next_nonce(login: String, config: Config) -> Result<u64, String> {
+    let json = get_account_json(login, config)?;
 
-    if parts.len() > 2 {
-        return Err(NexiumAPIError::InvalidBalanceFormat.to_string());
+    match json["nonce"].as_u64() {
+        Some(n) => Ok(n),
+        None => Err(NexiumAPIError::InvalidNonce.to_string()),
     }
+}
+
+pub fn get_balance(
+    login: String,
+    config: Config,
+) -> Result<BalanceInfo, String> {
+    let balance = get_balance_amount(login, config)?;
 
-    let part1 = if part1.len() > 2 {
-        part1.chars().take(2).collect::<String>()
-    } else {
-        part1
+    // Only two decimals are displayed, "0" hides them
+    let decimals = format!("{:.2}", balance);
+    let decimal_part = match decimals.split_once('.') {
+        Some((_, d)) if d.trim_end_matches('0').is_empty() => "0".to_string(),
+        Some((_, d)) => d.trim_end_matches('0').to_string(),
+        None => "0".to_string(),
     };
 
     Ok(BalanceInfo {
-        integer_part: part0,
-        decimal_part: part1,
+        integer_part: balance.integer_part().to_string(),
+        decimal_part,
     })
 }
 
+/// Page of the confirmed transactions of `login`, the newest first. The
+/// next page is read with the `next_cursor` of the page.
 pub fn get_transactions(
     config: Config,
     login: String,
-    n: u32,
-) -> Result<Vec<ClassicTransactionReceived>, String> {
-    let headers = match build_headers(&config) {
+    query: HistoryQuery,
+) -> Result<TransactionPage, String> {
+    let mut endpoint = format!("/transactions/{}?limit={}", login, query.limit);
+    if let Some(cursor) = &query.cursor {
+        endpoint.push_str(&format!("&cursor={}", cursor));
+    }
+    match query.direction {
+        Some(ClassicTransactionReceivedType::Sent) => {
+            endpoint.push_str("&direction=sent")
+        }
+        Some(ClassicTransactionReceivedType::Received) => {
+            endpoint.push_str("&direction=received")
+        }
+        None => {}
+    }
+    if let Some(counterparty) = &query.counterparty {
+        endpoint.push_str(&format!("&counterparty={}", counterparty));
+    }
+    if let Some(from) = query.from {
+        endpoint.push_str(&format!("&from={}", from));
+    }
+    if let Some(to) = query.to {
+        endpoint.push_str(&format!("&to={}", to));
+    }
+    let headers = match build_headers(&config, "GET", &endpoint, "") {
         Ok(h) => h,
         Err(e) => return Err(e),
     };
 
-    let url = build_url(&config, &format!("/transactions/{}?n={}", login, n));
+    let url = build_url(&config, &endpoint);
 
     let client = Client::new();
     let response = match client.get(&url).headers(headers).send() {
@@ -490,11 +656,7 @@ pub fn get_transactions(
     };
 
     if !response.status().is_success() {
-        return Err(format!(
-            "{}: {}",
-            NexiumAPIError::InvalidResponseFromServer.to_string(),
-            response.status()
-        ));
+        return Err(error_from_response(response));
     }
 
     let response_text = match response.text() {
@@ -510,7 +672,7 @@ pub fn get_transactions(
         Err(e) => return Err(e.to_string()),
     };
 
-    let uncrypted_response = match client_key.decrypt_split(&response_text) {
+    let uncrypted_response = match client_key.open(&response_text) {
         Ok(d) => d,
         Err(e) => return Err(e.to_string()),
     };
@@ -522,7 +684,7 @@ pub fn get_transactions(
 
     let mut transactions: Vec<ClassicTransactionReceived> = vec![];
 
-    for tr_json in json.members() {
+    for tr_json in json["transactions"].members() {
         let tr_str = match tr_json.as_str() {
             Some(s) => s,
             None => continue,
@@ -595,19 +757,23 @@ pub fn get_transactions(
         }
     }
 
-    Ok(transactions)
+    Ok(TransactionPage {
+        transactions,
+        next_cursor: json["next_cursor"].as_str().map(|c| c.to_string()),
+    })
 }
 
 pub fn get_user_stats(
     login: String,
     config: Config,
 ) -> Result<UserStats, String> {
-    let headers = match build_headers(&config) {
+    let endpoint = format!("/stats/{}", login);
+    let headers = match build_headers(&config, "GET", &endpoint, "") {
         Ok(h) => h,
         Err(e) => return Err(e),
     };
 
-    let url = build_url(&config, &format!("/stats/{}", login));
+    let url = build_url(&config, &endpoint);
 
     let client = Client::new();
     let response = match client.get(&url).headers(headers).send() {
@@ -616,11 +782,7 @@ pub fn get_user_stats(
     };
 
     if !response.status().is_success() {
-        return Err(format!(
-            "{}: {}",
-            NexiumAPIError::InvalidResponseFromServer.to_string(),
-            response.status()
-        ));
+        return Err(error_from_response(response));
     }
 
     let response_text = match response.text() {
@@ -637,7 +799,7 @@ pub fn get_user_stats(
         Err(e) => return Err(e.to_string()),
     };
 
-    let uncrypted_response = match client_key.decrypt_split(&response_text) {
+    let uncrypted_response = match client_key.open(&response_text) {
         Ok(d) => d,
         Err(e) => return Err(e.to_string()),
     };
@@ -647,12 +809,16 @@ pub fn get_user_stats(
         Err(_) => return Err(NexiumAPIError::InvalidJsonResponse.to_string()),
     };
 
+    let amount_f64 = |value: &json::JsonValue| {
+        json_amount(value).map(|a| a.to_nex_f64()).unwrap_or(0.0)
+    };
+
     let stats = UserStats {
-        balance: json["balance"].as_f64().unwrap_or(0.0),
+        balance: amount_f64(&json["balance"]),
         sent_count: json["sent_count"].as_u64().unwrap_or(0),
         received_count: json["received_count"].as_u64().unwrap_or(0),
-        total_sent: json["total_sent"].as_f64().unwrap_or(0.0),
-        total_received: json["total_received"].as_f64().unwrap_or(0.0),
+        total_sent: amount_f64(&json["total_sent"]),
+        total_received: amount_f64(&json["total_received"]),
         total_transactions: json["total_transactions"].as_u64().unwrap_or(0),
         rank: json["rank"].as_u64().unwrap_or(0),
     };
@@ -669,25 +835,16 @@ pub struct PeerInfo {
 
 /// Fetch the list of peers from the server
 pub fn get_peers(config: Config) -> Result<Vec<PeerInfo>, String> {
-    let headers = match build_headers(&config) {
-        Ok(h) => h,
-        Err(e) => return Err(e),
-    };
-
     let url = build_url(&config, "/peers");
 
     let client = Client::new();
-    let response = match client.get(&url).headers(headers).send() {
+    let response = match client.get(&url).send() {
         Ok(r) => r,
         Err(e) => return Err(e.to_string()),
     };
 
     if !response.status().is_success() {
-        return Err(format!(
-            "{}: {}",
-            NexiumAPIError::InvalidResponseFromServer.to_string(),
-            response.status()
-        ));
+        return Err(error_from_response(response));
     }
 
     let response_text = match response.text() {
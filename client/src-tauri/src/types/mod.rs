@@ -2,3 +2,4 @@ pub mod balance;
 pub mod classic_tr_received;
 pub mod constants;
 pub mod server_infos;
+pub mod transaction_page;
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::classic_tr_received::{
+    ClassicTransactionReceived, ClassicTransactionReceivedType,
+};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct HistoryQuery {
+    pub cursor: Option<String>,
+    pub limit: u32,
+    pub direction: Option<ClassicTransactionReceivedType>,
+    pub counterparty: Option<String>,
+    pub from: Option<u32>,
+    pub to: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct TransactionPage {
+    pub transactions: Vec<ClassicTransactionReceived>,
+    pub next_cursor: Option<String>,
+}
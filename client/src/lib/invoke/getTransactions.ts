@@ -1,14 +1,26 @@
 import { invoke } from "@tauri-apps/api/core";
 import { ResultAsync } from "neverthrow";
-import type { ClassicTransactionReceived, Config } from "@bindings";
+import type { Config, HistoryQuery, TransactionPage } from "@bindings";
+
+export function historyQuery(limit: number, filters: Partial<HistoryQuery> = {}): HistoryQuery {
+    return {
+        cursor: null,
+        direction: null,
+        counterparty: null,
+        from: null,
+        to: null,
+        ...filters,
+        limit
+    };
+}
 
 export function getTransactions(
     config: Config,
     login: string,
-    n: number
-): ResultAsync<ClassicTransactionReceived[], string> {
+    query: HistoryQuery
+): ResultAsync<TransactionPage, string> {
     return ResultAsync.fromPromise(
-        invoke("get_transactions", { config, login, n }),
+        invoke("get_transactions", { config, login, query }),
         (error) => `Failed to get transactions: ${error}`
     );
 }
@@ -5,7 +5,7 @@ import {
     sendNotification
 } from "@tauri-apps/plugin-notification";
 import { globalConfig, isConfigSet } from "@stores/settings.js";
-import { getTransactions } from "@invoke";
+import { getTransactions, historyQuery } from "@invoke";
 import type { ClassicTransactionReceived } from "@bindings";
 
 // Store for notification settings
@@ -33,10 +33,10 @@ export async function checkForNewTransactions(): Promise<void> {
         return;
     }
 
-    const transactionsRes = await getTransactions(config, config.user_login, 5);
+    const transactionsRes = await getTransactions(config, config.user_login, historyQuery(5));
 
     if (transactionsRes.isOk()) {
-        const transactions = transactionsRes.value;
+        const transactions = transactionsRes.value.transactions;
 
         if (transactions.length > 0) {
             const lastCount = get(lastKnownTransactionCount);
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ClassicTransactionReceivedType } from "./ClassicTransactionReceivedType";
+
+export type HistoryQuery = { cursor: string | null, limit: number, direction: ClassicTransactionReceivedType | null, counterparty: string | null, from: number | null, to: number | null, };
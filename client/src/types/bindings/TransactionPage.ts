@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ClassicTransactionReceived } from "./ClassicTransactionReceived";
+
+export type TransactionPage = { transactions: Array<ClassicTransactionReceived>, next_cursor: string | null, };
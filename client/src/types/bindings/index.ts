@@ -5,12 +5,14 @@ export * from "./ClassicTransactionSent";
 export * from "./Config";
 export * from "./Constants";
 export * from "./Contact";
+export * from "./HistoryQuery";
 export * from "./Invoice";
 export * from "./KeyPairResult";
 export * from "./LoginNames";
 export * from "./PeerInfo";
 export * from "./ServerInfos";
 export * from "./TokenType";
+export * from "./TransactionPage";
 export * from "./TryConnectResult";
 export * from "./UserStats";
 export * from "./WorkingServerInfo";
@@ -0,0 +1,58 @@
+//! Timing of the RSA private operations with and without CRT
+//!
+//! cargo run --release -p nexium --example rsa_bench
+
+use nexium::{
+    defaults::KEYPAIR_BIT_SIZE,
+    rsa::{envelope::Envelope, KeyPair},
+};
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 50;
+
+fn time<F>(mut f: F) -> Duration
+where
+    F: FnMut(),
+{
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    println!("Generating a {} bits key...", KEYPAIR_BIT_SIZE);
+    let key = KeyPair::generate(KEYPAIR_BIT_SIZE, "bench");
+    let mut blinded = key.clone();
+    blinded.set_blinding(true);
+
+    let message = "x".repeat(4096);
+    let envelope = Envelope::seal(&key, message.as_bytes()).unwrap();
+
+    let keys = [
+        ("modpow(d, n)", key.without_crt()),
+        ("CRT", key),
+        ("CRT + blinding", blinded),
+    ];
+
+    println!("{:<16} {:>12} {:>16}", "", "sign", "open envelope");
+    let mut reference = None;
+    for (name, k) in keys.iter() {
+        let sign = time(|| {
+            k.sign_pkcs1_v15(message.as_bytes()).unwrap();
+        });
+        let open = time(|| {
+            envelope.open(k).unwrap();
+        });
+
+        let base = *reference.get_or_insert(sign);
+        println!(
+            "{:<16} {:>12.2?} {:>16.2?}   x{:.1}",
+            name,
+            sign,
+            open,
+            base.as_secs_f64() / sign.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Errors returned by the server routes
+//!
+//! Every error response has a JSON body
+//! `{"code": "NONCE_GAP", "message": "...", "details": {...}}`. The
+//! `code` is stable and meant to be matched on. The `message` is for
+//! humans and may change. `details` is only present for some codes.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request is not valid HTTP
+    MalformedRequest,
+    /// The request line and headers are too large
+    HeadersTooLarge,
+    /// The body is too large
+    PayloadTooLarge,
+    /// The request was not received in time
+    RequestTimeout,
+    /// No route for this method and path
+    NotFound,
+    /// A path or query parameter is invalid, details: `{"name"}`
+    InvalidParameter,
+    /// The server failed, retrying later may work
+    InternalError,
+
+    /// An authentication header is missing, details: `{"header"}`
+    MissingHeader,
+    /// The Timestamp header is not a number
+    InvalidTimestamp,
+    /// The Timestamp header is too far from the server time
+    RequestExpired,
+    /// The nonce was not issued, has expired or was already used
+    InvalidNonce,
+    /// The request signature matches none of the keys of the login
+    InvalidSignature,
+    /// Too many unused challenges were asked
+    TooManyChallenges,
+
+    /// The body could not be decrypted
+    InvalidEnvelope,
+    /// The transaction can't be decoded or is inconsistent
+    MalformedTransaction,
+    /// The emitter doesn't exist or has no key
+    UnknownEmitter,
+    /// The transaction signature matches none of the emitter keys
+    InvalidTransactionSignature,
+    /// The keys of the emitter could not be fetched
+    KeysUnavailable,
+    /// The transaction has no nonce
+    MissingNonce,
+    /// The nonce of the transaction was already used
+    NonceAlreadyUsed,
+    /// The nonce is ahead of the next one, details: `{"expected"}`
+    NonceGap,
+    /// The same transaction is already waiting
+    DuplicateTransaction,
+    /// The emitter can't pay the transaction on top of its pending ones,
+    /// details: `{"available"}` in µNEX
+    InsufficientBalance,
+    /// The mempool is full of transactions paying at least as much,
+    /// details: `{"min_fees"}` per byte
+    MempoolFull,
+    /// Invalid transaction id
+    InvalidTransactionId,
+    /// No transaction with this id
+    TransactionNotFound,
+    /// The account state could not be computed
+    AccountUnavailable,
+
+    /// The peer is banned
+    PeerBanned,
+    /// The peer description can't be decoded
+    InvalidPeer,
+    /// The block is not valid base64 or can't be decoded
+    MalformedBlock,
+    /// The block `from` of a sync request is not in the chain
+    UnknownBlock,
+    /// The block doesn't extend the chain tip
+    BlockDoesNotConnect,
+    /// The block breaks a consensus rule, details: `{"reason"}`
+    BlockRejected,
+    /// The chain could not be read
+    ChainUnavailable,
+
+    /// Code unknown to this version
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(Value::String(s)) => write!(f, "{s}"),
+            _ => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+/// Body of an error response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new<T>(code: ErrorCode, message: T) -> Self
+    where
+        T: Into<String>,
+    {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Parse an error body, `None` for servers that answer plain text
+    pub fn from_json(body: &str) -> Option<Self> {
+        serde_json::from_str(body).ok()
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Number of µNEX in one NEX
+pub const MICRO_PER_NEX: u64 = 1_000_000;
+/// Number of decimal digits an amount can carry
+pub const AMOUNT_DECIMALS: usize = 6;
+
+/// Fixed-point amount of NEX, stored as an integer number of µNEX
+/// (micro-NEX = 0.000001 NEX) so that additions never drift
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmountError {
+    Empty,
+    InvalidCharacter,
+    TooManyDecimals,
+    Overflow,
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            AmountError::Empty => "Le montant est vide.",
+            AmountError::InvalidCharacter => {
+                "Le montant contient un/des caractère(s) invalide(s)."
+            }
+            AmountError::TooManyDecimals => "Le montant a plus de 6 décimales.",
+            AmountError::Overflow => "Le montant est trop grand.",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    pub const fn from_micro(micro: u64) -> Self {
+        Self(micro)
+    }
+
+    pub const fn as_micro(&self) -> u64 {
+        self.0
+    }
+
+    /// Amount of `nex` whole NEX, None on overflow
+    pub fn from_nex(nex: u64) -> Option<Self> {
+        nex.checked_mul(MICRO_PER_NEX).map(Self)
+    }
+
+    /// Convert a floating NEX value, rounded to the nearest µNEX
+    /// Only meant for legacy data (f32 transactions, old servers)
+    pub fn from_nex_f64(nex: f64) -> Option<Self> {
+        if !nex.is_finite() || nex < 0.0 {
+            return None;
+        }
+        let micro = (nex * MICRO_PER_NEX as f64).round();
+        if micro >= u64::MAX as f64 {
+            return None;
+        }
+        Some(Self(micro as u64))
+    }
+
+    /// Convert an amount of the legacy f32 layout. The shortest decimal
+    /// representation of the f32 is used, so that 100.65489 (stored as
+    /// 100.654892...) gives back the value the user typed.
+    pub fn from_legacy_f32(nex: f32) -> Option<Self> {
+        match nex.to_string().parse::<Amount>() {
+            Ok(a) => Some(a),
+            Err(AmountError::TooManyDecimals) => Self::from_nex_f64(nex as f64),
+            Err(_) => None,
+        }
+    }
+
+    /// Lossy conversion, for display purposes only
+    pub fn to_nex_f64(&self) -> f64 {
+        self.0 as f64 / MICRO_PER_NEX as f64
+    }
+
+    /// Fee cost of a transaction of `size` bytes at `fees_per_byte` µNEX
+    pub fn fee(fees_per_byte: u16, size: usize) -> Option<Self> {
+        (size as u64).checked_mul(fees_per_byte as u64).map(Self)
+    }
+
+    pub fn integer_part(&self) -> u64 {
+        self.0 / MICRO_PER_NEX
+    }
+
+    pub fn fractional_part(&self) -> u64 {
+        self.0 % MICRO_PER_NEX
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_le_bytes(bytes))
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Without precision, trailing zeros of the decimals are trimmed
+    /// ("12.5", "5000"). With a precision (`{:.2}`), exactly that many
+    /// decimals are written, truncating the extra digits.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = format!("{:06}", self.fractional_part());
+        let decimals = match f.precision() {
+            Some(p) => {
+                let mut d = decimals[..p.min(AMOUNT_DECIMALS)].to_string();
+                while d.len() < p {
+                    d.push('0');
+                }
+                d
+            }
+            None => decimals.trim_end_matches('0').to_string(),
+        };
+
+        if decimals.is_empty() {
+            write!(f, "{}", self.integer_part())
+        } else {
+            write!(f, "{}.{}", self.integer_part(), decimals)
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    /// Parse a decimal NEX value such as "12", "12.5" or "0.000001"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AmountError::Empty);
+        }
+
+        let (int_str, dec_str) = match s.split_once('.') {
+            Some((i, d)) => (i, d),
+            None => (s, ""),
+        };
+
+        if int_str.is_empty() && dec_str.is_empty() {
+            return Err(AmountError::Empty);
+        }
+
+        if !int_str.chars().all(|c| c.is_ascii_digit())
+            || !dec_str.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(AmountError::InvalidCharacter);
+        }
+
+        if dec_str.len() > AMOUNT_DECIMALS {
+            return Err(AmountError::TooManyDecimals);
+        }
+
+        let integer = match int_str {
+            "" => 0,
+            i => i.parse::<u64>().map_err(|_| AmountError::Overflow)?,
+        };
+
+        let mut decimals = dec_str.to_string();
+        while decimals.len() < AMOUNT_DECIMALS {
+            decimals.push('0');
+        }
+        // At most 6 digits, always fits
+        let fraction = decimals.parse::<u64>().unwrap_or(0);
+
+        integer
+            .checked_mul(MICRO_PER_NEX)
+            .and_then(|i| i.checked_add(fraction))
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Amount, AmountError> {
+        s.parse::<Amount>()
+    }
+
+    #[test]
+    fn parses_decimal_values() {
+        assert_eq!(parse("12"), Ok(Amount(12_000_000)));
+        assert_eq!(parse("12.5"), Ok(Amount(12_500_000)));
+        assert_eq!(parse(" 0.000001 "), Ok(Amount(1)));
+        assert_eq!(parse(".5"), Ok(Amount(500_000)));
+        assert_eq!(parse("7."), Ok(Amount(7_000_000)));
+        assert_eq!(parse("0.100000"), Ok(Amount(100_000)));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(parse(""), Err(AmountError::Empty));
+        assert_eq!(parse("  "), Err(AmountError::Empty));
+        assert_eq!(parse("."), Err(AmountError::Empty));
+        assert_eq!(parse("-1"), Err(AmountError::InvalidCharacter));
+        assert_eq!(parse("1e3"), Err(AmountError::InvalidCharacter));
+        assert_eq!(parse("1.2.3"), Err(AmountError::InvalidCharacter));
+        assert_eq!(parse("1,5"), Err(AmountError::InvalidCharacter));
+        assert_eq!(parse("0.0000001"), Err(AmountError::TooManyDecimals));
+        assert_eq!(parse("1.1234560"), Err(AmountError::TooManyDecimals));
+    }
+
+    #[test]
+    fn rejects_overflowing_values() {
+        let max = u64::MAX / MICRO_PER_NEX;
+        assert_eq!(parse(&max.to_string()), Ok(Amount(max * MICRO_PER_NEX)));
+        assert_eq!(parse(&Amount::MAX.to_string()), Ok(Amount::MAX));
+        assert_eq!(parse(&(max + 1).to_string()), Err(AmountError::Overflow));
+        assert_eq!(parse("18446744073709.551616"), Err(AmountError::Overflow));
+        assert_eq!(parse("99999999999999999999"), Err(AmountError::Overflow));
+    }
+
+    #[test]
+    fn displays_trimmed_or_with_precision() {
+        assert_eq!(Amount(0).to_string(), "0");
+        assert_eq!(Amount(5_000_000_000).to_string(), "5000");
+        assert_eq!(Amount(12_500_000).to_string(), "12.5");
+        assert_eq!(Amount(1).to_string(), "0.000001");
+        assert_eq!(Amount::MAX.to_string(), "18446744073709.551615");
+        assert_eq!(format!("{:.2}", Amount(12_345_678)), "12.34");
+        assert_eq!(format!("{:.0}", Amount(12_999_999)), "12");
+        assert_eq!(format!("{:.8}", Amount(1)), "0.00000100");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for micro in [0, 1, 10, 999_999, 1_000_000, 123_456_789, u64::MAX] {
+            assert_eq!(parse(&Amount(micro).to_string()), Ok(Amount(micro)));
+        }
+    }
+
+    #[test]
+    fn rounds_floats_to_the_nearest_micro() {
+        assert_eq!(Amount::from_nex_f64(0.1), Some(Amount(100_000)));
+        assert_eq!(Amount::from_nex_f64(0.0000004), Some(Amount(0)));
+        assert_eq!(Amount::from_nex_f64(0.0000006), Some(Amount(1)));
+        assert_eq!(Amount::from_nex_f64(2.9999999), Some(Amount(3_000_000)));
+        assert_eq!(Amount::from_nex_f64(-0.1), None);
+        assert_eq!(Amount::from_nex_f64(f64::NAN), None);
+        assert_eq!(Amount::from_nex_f64(f64::INFINITY), None);
+        assert_eq!(Amount::from_nex_f64(1e20), None);
+    }
+
+    #[test]
+    fn converts_legacy_floats_as_typed() {
+        assert_eq!(
+            Amount::from_legacy_f32(100.65489),
+            Some(Amount(100_654_890))
+        );
+        assert_eq!(Amount::from_legacy_f32(0.1), Some(Amount(100_000)));
+        assert_eq!(Amount::from_legacy_f32(-1.0), None);
+    }
+}
@@ -1,4 +1,16 @@
-pub const TRANSACTION_HEADER_SIZE: usize = 73;
+use super::amount::Amount;
+
+pub const TRANSACTION_HEADER_SIZE: usize =
+    NONCE_TRANSACTION_HEADER_SIZE + TRANSACTION_SIGNATURE_SCHEME_SIZE;
+/// Size of the version 0 headers, written without nonce
+pub const LEGACY_TRANSACTION_HEADER_SIZE: usize = 73;
+/// Size of the version 1 headers, written without signature scheme
+pub const NONCE_TRANSACTION_HEADER_SIZE: usize =
+    LEGACY_TRANSACTION_HEADER_SIZE + TRANSACTION_NONCE_SIZE;
+pub const TRANSACTION_NONCE_SIZE: usize = 8;
+pub const TRANSACTION_SIGNATURE_SCHEME_SIZE: usize = 1;
+/// Stored in the high nibble of the data type byte
+pub const TRANSACTION_HEADER_VERSION: u8 = 2;
 // pub const TRANSACTION_SIZE: usize = 2;
 // pub const TRANSACTION_TIMESTAMP: usize = 4;
 // pub const TRANSACTION_FEES: usize = 2;
@@ -9,10 +21,26 @@ pub const SIGNATURE_SIZE: usize = 256;
 // ClassicTransaction
 pub const TRANSACTION_RECEIVER: usize = 64;
 pub const DESCRIPTION_SIZE: usize = 256;
-pub const CLASSIC_TRANSACTION_MIN_SIZE: usize = TRANSACTION_EMITTER + 4 + 1;
+pub const AMOUNT_SIZE: usize = 8;
+pub const CLASSIC_TRANSACTION_MIN_SIZE: usize =
+    TRANSACTION_RECEIVER + AMOUNT_SIZE + 1;
 pub const CLASSIC_TRANSACTION_MAX_SIZE: usize =
     CLASSIC_TRANSACTION_MIN_SIZE + DESCRIPTION_SIZE;
 
+// Coinbase
+pub const COINBASE_HEIGHT_SIZE: usize = 8;
+pub const COINBASE_SIZE: usize = COINBASE_HEIGHT_SIZE + AMOUNT_SIZE;
+/// Size of a whole coinbase transaction
+pub const COINBASE_TRANSACTION_SIZE: usize =
+    TRANSACTION_HEADER_SIZE + COINBASE_SIZE + SIGNATURE_SIZE;
+
+// Legacy ClassicTransaction (f32 amount)
+pub const LEGACY_AMOUNT_SIZE: usize = 4;
+pub const LEGACY_CLASSIC_TRANSACTION_MIN_SIZE: usize =
+    TRANSACTION_RECEIVER + LEGACY_AMOUNT_SIZE + 1;
+pub const LEGACY_CLASSIC_TRANSACTION_MAX_SIZE: usize =
+    LEGACY_CLASSIC_TRANSACTION_MIN_SIZE + DESCRIPTION_SIZE;
+
 /// Estimate the total transaction size for a classic transaction
 /// Used to calculate fees before creating the transaction
 pub fn estimate_classic_transaction_size(has_description: bool) -> usize {
@@ -24,19 +52,21 @@ pub fn estimate_classic_transaction_size(has_description: bool) -> usize {
     TRANSACTION_HEADER_SIZE + data_size + SIGNATURE_SIZE
 }
 
-/// Calculate the fee cost in NEX for a given fees rate (µNEX/byte) and transaction size
+/// Calculate the fee cost for a given fees rate (µNEX/byte) and transaction size
 /// Fees are defined as µNEX per byte (micro-NEX = 0.000001 NEX)
-pub fn calculate_fee_cost(fees_per_byte: u16, transaction_size: usize) -> f64 {
-    let size = transaction_size as f64;
-    let fees = fees_per_byte as f64;
-    (size * fees) / 1_000_000.0
+pub fn calculate_fee_cost(
+    fees_per_byte: u16,
+    transaction_size: usize,
+) -> Amount {
+    // u16 * usize always fits in u64 for real transaction sizes
+    Amount::fee(fees_per_byte, transaction_size).unwrap_or(Amount::MAX)
 }
 
 /// Estimate the fee cost for a classic transaction
 pub fn estimate_classic_transaction_fee(
     fees_per_byte: u16,
     has_description: bool,
-) -> f64 {
+) -> Amount {
     let size = estimate_classic_transaction_size(has_description);
     calculate_fee_cost(fees_per_byte, size)
 }
@@ -4,14 +4,28 @@ use serde::{Deserialize, Serialize};
 pub enum DataType {
     #[default]
     Unknown = 0,
+    /// Legacy layout, the amount is stored as an f32 of NEX
     ClassicTransaction = 1,
+    /// The amount is stored as a u64 of µNEX
+    ClassicTransactionV2 = 2,
+    /// Reward of the miner of a block, first transaction of the block
+    Coinbase = 3,
 }
 
 impl DataType {
     pub fn from_u8(t: u8) -> Self {
         match t {
             1 => DataType::ClassicTransaction,
+            2 => DataType::ClassicTransactionV2,
+            3 => DataType::Coinbase,
             _ => DataType::Unknown,
         }
     }
+
+    pub fn is_classic(&self) -> bool {
+        matches!(
+            self,
+            DataType::ClassicTransaction | DataType::ClassicTransactionV2
+        )
+    }
 }
@@ -0,0 +1,289 @@
+//! Binary Merkle tree over the transactions of a block
+//!
+//! Leaves are the double sha256 of the signed transaction buffers, parents
+//! are the double sha256 of the concatenation of their two children. When a
+//! level has an odd number of nodes, the last one is promoted to the next
+//! level unchanged (it is not paired with itself, so two different lists of
+//! transactions can never share a root).
+
+use super::transaction::Transaction;
+use crate::sha256::sha256;
+use serde::{Deserialize, Serialize};
+
+pub const MERKLE_HASH_SIZE: usize = 32;
+
+pub type MerkleHash = [u8; MERKLE_HASH_SIZE];
+
+/// Side on which the sibling hash sits when climbing the tree
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MerkleSide {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleStep {
+    #[serde(with = "serde_hash")]
+    pub hash: MerkleHash,
+    pub side: MerkleSide,
+}
+
+/// Inclusion proof of a transaction: the sibling hashes from the leaf up
+/// to the root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub tx_index: u32,
+    pub steps: Vec<MerkleStep>,
+}
+
+fn double_sha256(data: &[u8]) -> MerkleHash {
+    sha256(&sha256(data))
+}
+
+/// Leaves are the transaction ids
+pub fn leaf_hash(transaction: &Transaction) -> MerkleHash {
+    *transaction.txid().as_bytes()
+}
+
+fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut buff = [0; 2 * MERKLE_HASH_SIZE];
+    buff[..MERKLE_HASH_SIZE].copy_from_slice(left);
+    buff[MERKLE_HASH_SIZE..].copy_from_slice(right);
+    double_sha256(&buff)
+}
+
+fn next_level(level: &[MerkleHash]) -> Vec<MerkleHash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Root of the tree, all zeros for an empty list of transactions
+pub fn merkle_root(transactions: &[Transaction]) -> MerkleHash {
+    let mut level: Vec<MerkleHash> =
+        transactions.iter().map(leaf_hash).collect();
+
+    if level.is_empty() {
+        return [0; MERKLE_HASH_SIZE];
+    }
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+impl MerkleProof {
+    /// Build the proof of the transaction at `tx_index`
+    pub fn build(
+        transactions: &[Transaction],
+        tx_index: usize,
+    ) -> Option<Self> {
+        if tx_index >= transactions.len() {
+            return None;
+        }
+
+        let mut level: Vec<MerkleHash> =
+            transactions.iter().map(leaf_hash).collect();
+        let mut index = tx_index;
+        let mut steps = vec![];
+
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            // An odd node without sibling is promoted, no step is needed
+            if sibling < level.len() {
+                steps.push(MerkleStep {
+                    hash: level[sibling],
+                    side: if sibling < index {
+                        MerkleSide::Left
+                    } else {
+                        MerkleSide::Right
+                    },
+                });
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+
+        Some(Self {
+            tx_index: tx_index as u32,
+            steps,
+        })
+    }
+
+    /// Root obtained by climbing the tree from `leaf`
+    pub fn root_from(&self, leaf: MerkleHash) -> MerkleHash {
+        self.steps.iter().fold(leaf, |acc, step| match step.side {
+            MerkleSide::Left => node_hash(&step.hash, &acc),
+            MerkleSide::Right => node_hash(&acc, &step.hash),
+        })
+    }
+}
+
+/// Check that `transaction` is included in the block whose merkle root
+/// is `root`
+pub fn verify_proof(
+    root: &MerkleHash,
+    transaction: &Transaction,
+    proof: &MerkleProof,
+) -> bool {
+    proof.root_from(leaf_hash(transaction)) == *root
+}
+
+mod serde_hash {
+    use super::{MerkleHash, MERKLE_HASH_SIZE};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(
+        hash: &MerkleHash,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<MerkleHash, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: &str = Deserialize::deserialize(deserializer)?;
+        let bytes = match hex::decode(s) {
+            Ok(b) => b,
+            Err(_) => return Err(serde::de::Error::custom("Invalid hash")),
+        };
+        if bytes.len() != MERKLE_HASH_SIZE {
+            return Err(serde::de::Error::custom("Invalid hash size"));
+        }
+        let mut hash = [0; MERKLE_HASH_SIZE];
+        hash.copy_from_slice(&bytes);
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transactions with distinct ids
+    fn transactions(count: u64) -> Vec<Transaction> {
+        (0..count)
+            .map(|nonce| {
+                let mut tr = Transaction::default();
+                tr.header.nonce = nonce;
+                tr
+            })
+            .collect()
+    }
+
+    fn leaves(transactions: &[Transaction]) -> Vec<MerkleHash> {
+        transactions.iter().map(leaf_hash).collect()
+    }
+
+    /// Check the proof of every transaction against `root`
+    fn check_proofs(transactions: &[Transaction], root: &MerkleHash) {
+        for (i, tr) in transactions.iter().enumerate() {
+            let proof = MerkleProof::build(transactions, i).unwrap();
+            assert_eq!(proof.tx_index, i as u32);
+            assert!(verify_proof(root, tr, &proof));
+            // The proof does not hold for the other transactions
+            for (j, other) in transactions.iter().enumerate() {
+                assert_eq!(verify_proof(root, other, &proof), i == j);
+            }
+        }
+        assert!(MerkleProof::build(transactions, transactions.len()).is_none());
+    }
+
+    #[test]
+    fn empty_root_is_zero() {
+        assert_eq!(merkle_root(&[]), [0; MERKLE_HASH_SIZE]);
+        assert!(MerkleProof::build(&[], 0).is_none());
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let trs = transactions(1);
+        let root = merkle_root(&trs);
+        assert_eq!(root, leaf_hash(&trs[0]));
+        let proof = MerkleProof::build(&trs, 0).unwrap();
+        assert!(proof.steps.is_empty());
+        check_proofs(&trs, &root);
+    }
+
+    #[test]
+    fn two_leaves() {
+        let trs = transactions(2);
+        let l = leaves(&trs);
+        let root = merkle_root(&trs);
+        assert_eq!(root, node_hash(&l[0], &l[1]));
+        assert_ne!(root, node_hash(&l[1], &l[0]));
+        check_proofs(&trs, &root);
+    }
+
+    #[test]
+    fn three_leaves_promote_the_last_one() {
+        let trs = transactions(3);
+        let l = leaves(&trs);
+        let root = merkle_root(&trs);
+        assert_eq!(root, node_hash(&node_hash(&l[0], &l[1]), &l[2]));
+        // Not paired with itself
+        assert_ne!(
+            root,
+            node_hash(&node_hash(&l[0], &l[1]), &node_hash(&l[2], &l[2]))
+        );
+
+        let proof = MerkleProof::build(&trs, 2).unwrap();
+        assert_eq!(
+            proof.steps,
+            vec![MerkleStep {
+                hash: node_hash(&l[0], &l[1]),
+                side: MerkleSide::Left,
+            }]
+        );
+        check_proofs(&trs, &root);
+    }
+
+    #[test]
+    fn five_leaves_promote_the_last_one_twice() {
+        let trs = transactions(5);
+        let l = leaves(&trs);
+        let left =
+            node_hash(&node_hash(&l[0], &l[1]), &node_hash(&l[2], &l[3]));
+        let root = merkle_root(&trs);
+        assert_eq!(root, node_hash(&left, &l[4]));
+
+        let proof = MerkleProof::build(&trs, 4).unwrap();
+        assert_eq!(
+            proof.steps,
+            vec![MerkleStep {
+                hash: left,
+                side: MerkleSide::Left,
+            }]
+        );
+        let proof = MerkleProof::build(&trs, 1).unwrap();
+        assert_eq!(proof.steps.len(), 3);
+        assert_eq!(proof.steps[2].hash, l[4]);
+        assert_eq!(proof.steps[2].side, MerkleSide::Right);
+        check_proofs(&trs, &root);
+    }
+
+    #[test]
+    fn root_depends_on_the_order() {
+        let mut trs = transactions(3);
+        let root = merkle_root(&trs);
+        trs.swap(0, 2);
+        assert_ne!(merkle_root(&trs), root);
+        // A duplicated last transaction changes the root
+        let trs = transactions(3);
+        let mut duplicated = trs.clone();
+        duplicated.push(trs[2].clone());
+        assert_ne!(merkle_root(&duplicated), merkle_root(&trs));
+    }
+}
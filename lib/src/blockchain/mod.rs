@@ -1,5 +1,8 @@
+pub mod amount;
 pub mod consts;
 pub mod data_type;
+pub mod merkle;
 pub mod transaction;
 pub mod transaction_data;
 pub mod transaction_header;
+pub mod txid;
@@ -2,14 +2,17 @@ use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 
 use super::{
-    consts::{SIGNATURE_SIZE, TRANSACTION_HEADER_SIZE},
+    amount::Amount,
+    consts::SIGNATURE_SIZE,
     data_type::DataType,
     transaction_data::{TransactionData, TransactionDataError},
     transaction_header::TransactionHeader,
+    txid::TxId,
 };
 use crate::{
     blockchain::consts::{
-        DESCRIPTION_SIZE, TRANSACTION_EMITTER, TRANSACTION_RECEIVER,
+        calculate_fee_cost, DESCRIPTION_SIZE, TRANSACTION_EMITTER,
+        TRANSACTION_RECEIVER,
     },
     rsa::KeyPair,
 };
@@ -39,10 +42,11 @@ impl Transaction {
     // Create transaction from user-friendly values
     pub fn new_classic<T>(
         receiver: T,
-        amount: f32,
+        amount: Amount,
         description: T,
         fees: u16,
         emitter: T,
+        nonce: u64,
         key: &KeyPair,
     ) -> Result<Self, String>
     where
@@ -98,7 +102,38 @@ impl Transaction {
             data.to_buffer(),
             fees,
             emitter_str,
-            DataType::ClassicTransaction,
+            DataType::ClassicTransactionV2,
+            nonce,
+            key,
+        )
+    }
+
+    /// Coinbase of the block at `height`, paying `reward` to `miner`. It
+    /// uses no nonce of the miner.
+    pub fn new_coinbase<T>(
+        height: u64,
+        reward: Amount,
+        miner: T,
+        key: &KeyPair,
+    ) -> Result<Self, String>
+    where
+        T: Into<String>,
+    {
+        let miner_str = miner.into();
+        if miner_str.len() > TRANSACTION_EMITTER {
+            return Err(format!(
+                "Emitter too long, max size is {}",
+                TRANSACTION_EMITTER
+            ));
+        }
+
+        let data = TransactionData::Coinbase { height, reward };
+        Transaction::new(
+            data.to_buffer(),
+            0,
+            miner_str,
+            DataType::Coinbase,
+            0,
             key,
         )
     }
@@ -108,20 +143,25 @@ impl Transaction {
         fees: u16,
         emitter: T,
         data_type: DataType,
+        nonce: u64,
         key: &KeyPair,
     ) -> Result<Self, String>
     where
         T: Into<String>,
     {
-        let header =
-            TransactionHeader::new(data.len() as u16, fees, emitter, data_type);
+        let header = TransactionHeader::new(
+            data.len() as u16,
+            fees,
+            emitter,
+            data_type,
+            nonce,
+        );
 
-        let mut buff = vec![0; TRANSACTION_HEADER_SIZE + data.len()];
-        buff[0..TRANSACTION_HEADER_SIZE].copy_from_slice(&header.to_buffer());
-        buff[TRANSACTION_HEADER_SIZE..].copy_from_slice(&data);
+        let mut buff = header.to_buffer();
+        buff.extend_from_slice(&data);
         // dbg!(&buff.len());
 
-        let signature = match key.sign(buff) {
+        let signature = match key.sign_with(header.signature_scheme, &buff) {
             Ok(sig) => sig,
             Err(_) => return Err("Error signing transaction".to_string()),
         };
@@ -134,37 +174,42 @@ impl Transaction {
     }
 
     pub fn size(&self) -> u32 {
-        (TRANSACTION_HEADER_SIZE + self.data.len() + SIGNATURE_SIZE) as u32
+        (self.header.size() + self.data.len() + SIGNATURE_SIZE) as u32
+    }
+
+    /// Message covered by the signature: the header followed by the data
+    pub fn signed_message(&self) -> Vec<u8> {
+        let mut message = self.header.to_buffer();
+        message.extend_from_slice(&self.data);
+        message
     }
 
-    /// Calculate the total fee cost in NEX
+    pub fn is_coinbase(&self) -> bool {
+        self.header.data_type == DataType::Coinbase
+    }
+
+    /// Calculate the total fee cost
     /// Fees are defined as µNEX per byte (micro-NEX = 0.000001 NEX)
-    /// Total fee = fees * transaction_size / 1_000_000
-    pub fn fee_cost(&self) -> f32 {
-        let size = self.size() as f32;
-        let fees_per_byte = self.header.fees as f32;
-        (size * fees_per_byte) / 1_000_000.0
+    /// Total fee = fees * transaction_size µNEX
+    pub fn fee_cost(&self) -> Amount {
+        calculate_fee_cost(self.header.fees, self.size() as usize)
     }
 
     /// Calculate the total cost of the transaction (amount + fees)
-    /// Returns None if the transaction is not a ClassicTransaction
-    pub fn total_cost(&self) -> Option<f32> {
+    /// Returns None if the transaction is not a ClassicTransaction or if
+    /// the sum overflows
+    pub fn total_cost(&self) -> Option<Amount> {
         match self.get_data() {
             Ok(TransactionData::ClassicTransaction { amount, .. }) => {
-                Some(amount + self.fee_cost())
+                amount.checked_add(self.fee_cost())
             }
             _ => None,
         }
     }
 
     pub fn from_buffer(buff: &[u8]) -> Result<Self, String> {
-        let data_start = TRANSACTION_HEADER_SIZE;
-        let header_buff = match buff[0..data_start].try_into() {
-            Ok(h) => h,
-            Err(_) => return Err("Buffer too small".to_string()),
-        };
-
-        let header = TransactionHeader::from_buffer(&header_buff);
+        let header = TransactionHeader::from_buffer(buff)?;
+        let data_start = header.size();
         let signature_start = data_start + header.transaction_size as usize;
         let signature_end = signature_start + SIGNATURE_SIZE;
         // check signature size
@@ -182,22 +227,20 @@ impl Transaction {
     }
 
     pub fn to_buffer(&self) -> Vec<u8> {
-        let data_start = TRANSACTION_HEADER_SIZE;
+        let data_start = self.header.size();
         let signature_start =
             data_start + self.header.transaction_size as usize;
 
         let mut res = vec![
             0;
-            TRANSACTION_HEADER_SIZE
+            data_start
                 + self.header.transaction_size as usize
                 + SIGNATURE_SIZE
         ];
 
-        res[0..TRANSACTION_HEADER_SIZE]
-            .copy_from_slice(&self.header.to_buffer());
+        res[0..data_start].copy_from_slice(&self.header.to_buffer());
 
-        res[TRANSACTION_HEADER_SIZE..signature_start]
-            .copy_from_slice(&self.data);
+        res[data_start..signature_start].copy_from_slice(&self.data);
 
         let mut sig = self.signature.to_bytes_le();
         if sig.len() < SIGNATURE_SIZE {
@@ -207,6 +250,11 @@ impl Transaction {
         return res;
     }
 
+    /// Identifier of the transaction, covering its signature
+    pub fn txid(&self) -> TxId {
+        TxId::from_signed_buffer(&self.to_buffer())
+    }
+
     pub fn get_data(&self) -> Result<TransactionData, TransactionDataError> {
         TransactionData::from_buffer(&self.header.data_type, &self.data)
     }
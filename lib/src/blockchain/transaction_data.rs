@@ -1,5 +1,10 @@
 use super::{
-    consts::{DESCRIPTION_SIZE, TRANSACTION_RECEIVER},
+    amount::Amount,
+    consts::{
+        AMOUNT_SIZE, COINBASE_HEIGHT_SIZE, COINBASE_SIZE, DESCRIPTION_SIZE,
+        LEGACY_AMOUNT_SIZE, LEGACY_CLASSIC_TRANSACTION_MAX_SIZE,
+        LEGACY_CLASSIC_TRANSACTION_MIN_SIZE, TRANSACTION_RECEIVER,
+    },
     data_type::DataType,
 };
 use crate::blockchain::consts::{
@@ -10,12 +15,20 @@ pub type DESCRIPTION = [u8; DESCRIPTION_SIZE];
 pub type RECEIVER = [u8; TRANSACTION_RECEIVER];
 
 pub enum TransactionData {
+    /// Decoded from both the legacy f32 layout and the µNEX layout,
+    /// always encoded with the µNEX layout (`DataType::ClassicTransactionV2`)
     ClassicTransaction {
         receiver: RECEIVER,
-        amount: f32,
+        amount: Amount,
         has_description: bool,
         description: DESCRIPTION,
     },
+    /// Reward of the emitter for mining the block at `height`, its fees
+    /// and subsidy. The height makes every coinbase unique.
+    Coinbase {
+        height: u64,
+        reward: Amount,
+    },
     Unknown {
         data: Vec<u8>,
     },
@@ -27,48 +40,81 @@ pub enum TransactionDataError {
 }
 
 impl TransactionData {
-    pub fn from_buffer(
-        data_type: &DataType,
-        buffer: &Vec<u8>,
+    fn classic_from_buffer(
+        buffer: &[u8],
+        amount_size: usize,
+        min_size: usize,
+        max_size: usize,
+        read_amount: impl Fn(&[u8]) -> Option<Amount>,
     ) -> Result<Self, TransactionDataError> {
-        match data_type {
-            DataType::ClassicTransaction => {
-                let amount_start = TRANSACTION_RECEIVER;
-                let has_description_start = amount_start + 4;
-                let description_start = has_description_start + 1;
+        let amount_start = TRANSACTION_RECEIVER;
+        let has_description_start = amount_start + amount_size;
+        let description_start = has_description_start + 1;
 
-                if buffer.len() < CLASSIC_TRANSACTION_MIN_SIZE {
-                    return Err(TransactionDataError::InvalidData);
-                }
+        if buffer.len() < min_size {
+            return Err(TransactionDataError::InvalidData);
+        }
 
-                let mut receiver = [0; TRANSACTION_RECEIVER];
-                let mut description = [0; DESCRIPTION_SIZE];
+        let mut receiver = [0; TRANSACTION_RECEIVER];
+        let mut description = [0; DESCRIPTION_SIZE];
 
-                receiver.copy_from_slice(&buffer[0..amount_start]);
+        receiver.copy_from_slice(&buffer[0..amount_start]);
 
-                let amount = f32::from_le_bytes(
-                    buffer[amount_start..has_description_start]
-                        .try_into()
-                        .unwrap(),
-                );
+        let amount =
+            match read_amount(&buffer[amount_start..has_description_start]) {
+                Some(a) => a,
+                None => return Err(TransactionDataError::InvalidData),
+            };
 
-                let has_description = buffer[has_description_start] == 1;
+        let has_description = buffer[has_description_start] == 1;
 
-                if has_description {
-                    if buffer.len() == description_start
-                        || buffer.len() > CLASSIC_TRANSACTION_MAX_SIZE
-                    {
-                        return Err(TransactionDataError::InvalidData);
-                    }
+        if has_description {
+            if buffer.len() != max_size {
+                return Err(TransactionDataError::InvalidData);
+            }
 
-                    description.copy_from_slice(&buffer[description_start..]);
-                }
+            description.copy_from_slice(&buffer[description_start..]);
+        }
 
-                Ok(TransactionData::ClassicTransaction {
-                    receiver,
-                    amount,
-                    has_description,
-                    description,
+        Ok(TransactionData::ClassicTransaction {
+            receiver,
+            amount,
+            has_description,
+            description,
+        })
+    }
+
+    pub fn from_buffer(
+        data_type: &DataType,
+        buffer: &Vec<u8>,
+    ) -> Result<Self, TransactionDataError> {
+        match data_type {
+            DataType::ClassicTransaction => Self::classic_from_buffer(
+                buffer,
+                LEGACY_AMOUNT_SIZE,
+                LEGACY_CLASSIC_TRANSACTION_MIN_SIZE,
+                LEGACY_CLASSIC_TRANSACTION_MAX_SIZE,
+                |b| {
+                    Amount::from_legacy_f32(f32::from_le_bytes(
+                        b.try_into().ok()?,
+                    ))
+                },
+            ),
+            DataType::ClassicTransactionV2 => Self::classic_from_buffer(
+                buffer,
+                AMOUNT_SIZE,
+                CLASSIC_TRANSACTION_MIN_SIZE,
+                CLASSIC_TRANSACTION_MAX_SIZE,
+                |b| Some(Amount::from_le_bytes(b.try_into().ok()?)),
+            ),
+            DataType::Coinbase => {
+                if buffer.len() != COINBASE_SIZE {
+                    return Err(TransactionDataError::InvalidData);
+                }
+                let (height, reward) = buffer.split_at(COINBASE_HEIGHT_SIZE);
+                Ok(TransactionData::Coinbase {
+                    height: u64::from_le_bytes(height.try_into().unwrap()),
+                    reward: Amount::from_le_bytes(reward.try_into().unwrap()),
                 })
             }
             DataType::Unknown => Ok(TransactionData::Unknown {
@@ -86,7 +132,7 @@ impl TransactionData {
                 description,
             } => {
                 let amount_start = TRANSACTION_RECEIVER;
-                let has_description_start = amount_start + 4;
+                let has_description_start = amount_start + AMOUNT_SIZE;
                 let description_start = has_description_start + 1;
 
                 let mut buffer = vec![0; self.size()];
@@ -99,6 +145,11 @@ impl TransactionData {
                 }
                 buffer
             }
+            TransactionData::Coinbase { height, reward } => {
+                let mut buffer = height.to_le_bytes().to_vec();
+                buffer.extend_from_slice(&reward.to_le_bytes());
+                buffer
+            }
             TransactionData::Unknown { data } => data.clone(),
         }
     }
@@ -116,13 +167,17 @@ impl TransactionData {
                 }
                 size
             }
+            TransactionData::Coinbase { .. } => COINBASE_SIZE,
             TransactionData::Unknown { data } => data.len(),
         }
     }
 
     pub fn get_type(&self) -> u8 {
         match self {
-            TransactionData::ClassicTransaction { .. } => 1,
+            TransactionData::ClassicTransaction { .. } => {
+                DataType::ClassicTransactionV2 as u8
+            }
+            TransactionData::Coinbase { .. } => DataType::Coinbase as u8,
             TransactionData::Unknown { .. } => 0,
         }
     }
@@ -154,6 +209,10 @@ impl core::fmt::Debug for TransactionData {
                     )?;
                 }
             }
+            TransactionData::Coinbase { height, reward } => {
+                writeln!(f, "height: {},", height)?;
+                writeln!(f, "reward: {},", reward)?;
+            }
             TransactionData::Unknown { data } => {
                 write!(f, "data: {:?},\n", data)?;
             }
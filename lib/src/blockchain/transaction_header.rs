@@ -1,9 +1,13 @@
 use super::{
-    consts::{TRANSACTION_EMITTER, TRANSACTION_HEADER_SIZE},
+    consts::{
+        LEGACY_TRANSACTION_HEADER_SIZE, NONCE_TRANSACTION_HEADER_SIZE,
+        TRANSACTION_EMITTER, TRANSACTION_HEADER_SIZE,
+        TRANSACTION_HEADER_VERSION,
+    },
     data_type::DataType,
 };
-use crate::utils::time::current_time;
-use serde::{Deserialize, Serialize};
+use crate::{rsa::signature::SignatureScheme, utils::time::current_time};
+use serde::{Deserialize, Deserializer, Serialize};
 
 pub type EMITTER = [u8; TRANSACTION_EMITTER];
 
@@ -15,6 +19,17 @@ pub struct TransactionHeader {
     #[serde(with = "serde_emitter")]
     pub emitter: EMITTER,
     pub data_type: DataType,
+    /// 0 for the legacy headers without nonce
+    #[serde(default, deserialize_with = "deserialize_version")]
+    pub version: u8,
+    /// Sequence number of the transaction among the ones of its emitter,
+    /// starting at 0. Only present from version 1.
+    #[serde(default)]
+    pub nonce: u64,
+    /// Scheme of the signature of the transaction. Only present from
+    /// version 2, older headers are signed with `SignatureScheme::Raw`.
+    #[serde(default)]
+    pub signature_scheme: SignatureScheme,
 }
 
 impl Default for TransactionHeader {
@@ -25,6 +40,9 @@ impl Default for TransactionHeader {
             transaction_size: 0,
             timestamp: 0,
             fees: 0,
+            version: TRANSACTION_HEADER_VERSION,
+            nonce: 0,
+            signature_scheme: SignatureScheme::Pkcs1v15Sha256,
         }
     }
 }
@@ -35,6 +53,7 @@ impl TransactionHeader {
         fees: u16,
         emitter: T,
         data_type: DataType,
+        nonce: u64,
     ) -> Self
     where
         T: Into<String>,
@@ -52,9 +71,40 @@ impl TransactionHeader {
             fees,
             emitter: em,
             data_type,
+            version: TRANSACTION_HEADER_VERSION,
+            nonce,
+            signature_scheme: SignatureScheme::Pkcs1v15Sha256,
         }
     }
 
+    /// Size of a header of version `version`, None for unknown versions
+    pub fn size_of_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(LEGACY_TRANSACTION_HEADER_SIZE),
+            1 => Some(NONCE_TRANSACTION_HEADER_SIZE),
+            2 => Some(TRANSACTION_HEADER_SIZE),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self.version {
+            0 => LEGACY_TRANSACTION_HEADER_SIZE,
+            1 => NONCE_TRANSACTION_HEADER_SIZE,
+            _ => TRANSACTION_HEADER_SIZE,
+        }
+    }
+
+    /// Whether the header carries a nonce protecting against replays
+    pub fn has_nonce(&self) -> bool {
+        self.version >= 1
+    }
+
+    /// Whether the signature scheme is written in the header
+    pub fn has_signature_scheme(&self) -> bool {
+        self.version >= 2
+    }
+
     pub fn get_login(&self) -> String {
         self.emitter
             .iter()
@@ -74,25 +124,70 @@ impl TransactionHeader {
     //     self.data_type = DataType::from_u8(buff[72]);
     // }
 
-    pub fn from_buffer(buff: &[u8; TRANSACTION_HEADER_SIZE]) -> Self {
-        TransactionHeader {
+    pub fn from_buffer(buff: &[u8]) -> Result<Self, String> {
+        if buff.len() < LEGACY_TRANSACTION_HEADER_SIZE {
+            return Err("Buffer too small".to_string());
+        }
+
+        let version = buff[72] >> 4;
+        let size = match TransactionHeader::size_of_version(version) {
+            Some(s) => s,
+            None => {
+                return Err(format!(
+                    "Unknown transaction header version {}",
+                    version
+                ))
+            }
+        };
+        if buff.len() < size {
+            return Err("Buffer too small".to_string());
+        }
+
+        let nonce = match version {
+            0 => 0,
+            _ => u64::from_be_bytes(buff[73..81].try_into().unwrap()),
+        };
+
+        let signature_scheme = match version {
+            0 | 1 => SignatureScheme::Raw,
+            _ => match SignatureScheme::from_u8(buff[81]) {
+                Some(s) => s,
+                None => {
+                    return Err(format!(
+                        "Unknown signature scheme {}",
+                        buff[81]
+                    ))
+                }
+            },
+        };
+
+        Ok(TransactionHeader {
             transaction_size: u16::from_be_bytes(
                 buff[0..2].try_into().unwrap(),
             ),
             timestamp: u32::from_be_bytes(buff[2..6].try_into().unwrap()),
             fees: u16::from_be_bytes(buff[6..8].try_into().unwrap()),
             emitter: buff[8..72].try_into().unwrap(),
-            data_type: DataType::from_u8(buff[72]),
-        }
+            data_type: DataType::from_u8(buff[72] & 0x0f),
+            version,
+            nonce,
+            signature_scheme,
+        })
     }
 
-    pub fn to_buffer(self) -> [u8; TRANSACTION_HEADER_SIZE] {
-        let mut res = [0; TRANSACTION_HEADER_SIZE];
+    pub fn to_buffer(self) -> Vec<u8> {
+        let mut res = vec![0; self.size()];
         res[0..2].copy_from_slice(&self.transaction_size.to_be_bytes());
         res[2..6].copy_from_slice(&self.timestamp.to_be_bytes());
         res[6..8].copy_from_slice(&self.fees.to_be_bytes());
         res[8..72].copy_from_slice(&self.emitter);
-        res[72] = self.data_type as u8;
+        res[72] = (self.version << 4) | (self.data_type as u8 & 0x0f);
+        if self.has_nonce() {
+            res[73..81].copy_from_slice(&self.nonce.to_be_bytes());
+        }
+        if self.has_signature_scheme() {
+            res[81] = self.signature_scheme as u8;
+        }
         return res;
     }
 }
@@ -104,12 +199,30 @@ impl core::fmt::Debug for TransactionHeader {
         write!(f, "timestamp: {},\n", self.timestamp)?;
         write!(f, "fees: {},\n", self.fees)?;
         write!(f, "emitter: {},\n", String::from_utf8_lossy(&self.emitter))?;
+        writeln!(f, "version: {},", self.version)?;
+        writeln!(f, "nonce: {},", self.nonce)?;
+        writeln!(f, "signature_scheme: {:?},", self.signature_scheme)?;
         // write!(f, "data_type: {},\n", self.data_type)?;
         write!(f, "}}")?;
         Ok(())
     }
 }
 
+/// Version of a header, rejected if it can't be written in a buffer
+fn deserialize_version<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let version = u8::deserialize(deserializer)?;
+    match TransactionHeader::size_of_version(version) {
+        Some(_) => Ok(version),
+        None => Err(serde::de::Error::custom(format!(
+            "Unknown transaction header version {}",
+            version
+        ))),
+    }
+}
+
 mod serde_emitter {
     use serde::{Deserialize, Deserializer, Serializer};
 
@@ -0,0 +1,85 @@
+use crate::sha256::sha256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+pub const TXID_SIZE: usize = 32;
+
+/// Identifier of a transaction: double sha256 of its signed buffer,
+/// written as lowercase hex
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId([u8; TXID_SIZE]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxIdError {
+    InvalidHex,
+    InvalidLength,
+}
+
+impl fmt::Display for TxIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            TxIdError::InvalidHex => {
+                "L'identifiant de transaction n'est pas en hexadécimal."
+            }
+            TxIdError::InvalidLength => {
+                "L'identifiant de transaction doit faire 64 caractères."
+            }
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl TxId {
+    pub const fn from_bytes(bytes: [u8; TXID_SIZE]) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; TXID_SIZE] {
+        &self.0
+    }
+
+    /// Id of the transaction whose signed buffer is `buffer`
+    pub fn from_signed_buffer(buffer: &[u8]) -> Self {
+        Self(sha256(&sha256(buffer)))
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for TxId {
+    type Err = TxIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = match hex::decode(s.trim()) {
+            Ok(b) => b,
+            Err(_) => return Err(TxIdError::InvalidHex),
+        };
+        match bytes.try_into() {
+            Ok(b) => Ok(Self(b)),
+            Err(_) => Err(TxIdError::InvalidLength),
+        }
+    }
+}
+
+impl Serialize for TxId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: &str = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
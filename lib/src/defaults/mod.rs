@@ -2,9 +2,41 @@ pub const GITLAB_API_ENDPOINT: &str = "/api/v4";
 pub const NEXIUM_HOME: &str = ".nexiumlocal";
 pub const INITIAL_BALANCE: u32 = 5000;
 pub const BLOCKCHAIN_FILE: &str = "blockchain.dat";
-pub const TRANSACTION_COUNT: usize = 1;
-pub const BLOCK_VERSION: u16 = 1;
-pub const DIFFICULTY_TARGET: u32 = 1;
+/// Index of the blocks of BLOCKCHAIN_FILE, rebuilt from it when stale
+pub const BLOCK_INDEX_FILE: &str = "blockchain.idx";
+/// State of the accounts at the tip of BLOCKCHAIN_FILE
+pub const ACCOUNTS_FILE: &str = "accounts.dat";
+pub const BLOCK_VERSION: u16 = 6;
+/// First block version whose header holds the root of a real merkle tree
+pub const MERKLE_TREE_BLOCK_VERSION: u16 = 2;
+/// First block version whose transactions must be covered by the balance
+/// of their emitter. The first servers kept f32 balances with no lower
+/// bound, so an older block may take an account below zero.
+pub const STRICT_BALANCE_BLOCK_VERSION: u16 = 2;
+/// First block version whose transactions must all carry a nonce
+pub const NONCE_BLOCK_VERSION: u16 = 3;
+/// First block version whose difficulty is a compact target, adjusted
+/// every RETARGET_INTERVAL blocks
+pub const COMPACT_TARGET_BLOCK_VERSION: u16 = 4;
+/// Difficulty of the blocks before COMPACT_TARGET_BLOCK_VERSION, a number
+/// of leading hex zeros of the block hash
+pub const LEGACY_DIFFICULTY: u32 = 1;
+/// Easiest compact target, as hard as LEGACY_DIFFICULTY
+pub const POW_LIMIT: u32 = 0x200fffff;
+/// Number of blocks between two difficulty adjustments
+pub const RETARGET_INTERVAL: u64 = 16;
+/// Expected time between two blocks, in seconds
+pub const TARGET_BLOCK_TIME: u32 = 60;
+/// First block version whose first transaction is the coinbase paying its
+/// miner
+pub const COINBASE_BLOCK_VERSION: u16 = 5;
+/// First block version whose transactions must all be signed with
+/// SignatureScheme::Pkcs1v15Sha256, raw signatures are only valid before
+pub const PKCS1_SIGNATURE_BLOCK_VERSION: u16 = 6;
+/// Subsidy of the first blocks, in µNEX, on top of the fees
+pub const INITIAL_BLOCK_SUBSIDY: u64 = 10_000_000;
+/// Number of blocks after which the subsidy is halved
+pub const SUBSIDY_HALVING_INTERVAL: u64 = 100_000;
 
 pub const KEYPAIR_BIT_SIZE: usize = 2048;
 pub const GITLAB_URL: &str = "https://gitlab.cri.epita.fr";
@@ -1,3 +1,4 @@
+pub mod api;
 pub mod blockchain;
 pub mod defaults;
 pub mod gitlab;
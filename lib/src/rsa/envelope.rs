@@ -0,0 +1,142 @@
+//! Hybrid encryption of messages of any size
+//!
+//! The message is encrypted with a random AES-256-GCM session key, and the
+//! session key is encrypted with the RSA key of the recipient (OAEP). The
+//! binary format is:
+//!
+//! ```text
+//! version (1) | wrapped key size (2, BE) | wrapped key | nonce (12) |
+//! ciphertext with its GCM tag
+//! ```
+//!
+//! The version byte is authenticated with the message.
+
+use super::{KeyPair, RSAError};
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rand::Rng;
+
+pub const ENVELOPE_VERSION: u8 = 1;
+pub const ENVELOPE_KEY_SIZE: usize = 32;
+pub const ENVELOPE_NONCE_SIZE: usize = 12;
+pub const ENVELOPE_TAG_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub version: u8,
+    /// Session key encrypted with the key of the recipient
+    pub wrapped_key: Vec<u8>,
+    pub nonce: [u8; ENVELOPE_NONCE_SIZE],
+    /// Encrypted message followed by the GCM tag
+    pub ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    /// Encrypt `message` for the owner of `key`
+    pub fn seal(key: &KeyPair, message: &[u8]) -> Result<Self, RSAError> {
+        let mut rng = rand::rng();
+        let session_key: [u8; ENVELOPE_KEY_SIZE] = rng.random();
+        let nonce: [u8; ENVELOPE_NONCE_SIZE] = rng.random();
+
+        let wrapped_key = key.oaep_encrypt(&session_key)?;
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&session_key));
+        let payload = Payload {
+            msg: message,
+            aad: &[ENVELOPE_VERSION],
+        };
+        let ciphertext =
+            match cipher.encrypt(Nonce::from_slice(&nonce), payload) {
+                Ok(c) => c,
+                Err(_) => return Err(RSAError::MessageTooBig),
+            };
+
+        Ok(Self {
+            version: ENVELOPE_VERSION,
+            wrapped_key,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the message with the private key of the recipient
+    pub fn open(&self, key: &KeyPair) -> Result<Vec<u8>, RSAError> {
+        if self.version != ENVELOPE_VERSION {
+            return Err(RSAError::UnsupportedEnvelopeVersion);
+        }
+
+        let session_key = key.oaep_decrypt(&self.wrapped_key)?;
+        if session_key.len() != ENVELOPE_KEY_SIZE {
+            return Err(RSAError::DecryptionError);
+        }
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&session_key));
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: &[self.version],
+        };
+        match cipher.decrypt(Nonce::from_slice(&self.nonce), payload) {
+            Ok(m) => Ok(m),
+            Err(_) => Err(RSAError::DecryptionError),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buff = Vec::with_capacity(
+            3 + self.wrapped_key.len()
+                + ENVELOPE_NONCE_SIZE
+                + self.ciphertext.len(),
+        );
+        buff.push(self.version);
+        buff.extend_from_slice(&(self.wrapped_key.len() as u16).to_be_bytes());
+        buff.extend_from_slice(&self.wrapped_key);
+        buff.extend_from_slice(&self.nonce);
+        buff.extend_from_slice(&self.ciphertext);
+        buff
+    }
+
+    pub fn from_bytes(buff: &[u8]) -> Result<Self, RSAError> {
+        let (version, rest) = match buff.split_first() {
+            Some((v, rest)) => (*v, rest),
+            None => return Err(RSAError::BadEnvelopeFormat),
+        };
+        if version != ENVELOPE_VERSION {
+            return Err(RSAError::UnsupportedEnvelopeVersion);
+        }
+
+        if rest.len() < 2 {
+            return Err(RSAError::BadEnvelopeFormat);
+        }
+        let key_size = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let rest = &rest[2..];
+
+        if rest.len() < key_size + ENVELOPE_NONCE_SIZE + ENVELOPE_TAG_SIZE {
+            return Err(RSAError::BadEnvelopeFormat);
+        }
+        let (wrapped_key, rest) = rest.split_at(key_size);
+        let (nonce, ciphertext) = rest.split_at(ENVELOPE_NONCE_SIZE);
+
+        let mut n = [0u8; ENVELOPE_NONCE_SIZE];
+        n.copy_from_slice(nonce);
+        Ok(Self {
+            version,
+            wrapped_key: wrapped_key.to_vec(),
+            nonce: n,
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.to_bytes())
+    }
+
+    pub fn from_base64(data: &str) -> Result<Self, RSAError> {
+        match STANDARD.decode(data.trim()) {
+            Ok(buff) => Self::from_bytes(&buff),
+            Err(_) => Err(RSAError::BadEnvelopeFormat),
+        }
+    }
+}
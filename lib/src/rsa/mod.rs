@@ -1,3 +1,7 @@
+pub mod envelope;
+pub mod request;
+pub mod signature;
+
 use super::sha256::sha256;
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use libaes::Cipher;
@@ -11,6 +15,12 @@ use std::fs::File;
 use std::io::Read;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use envelope::Envelope;
+use signature::emsa_pkcs1_v15_encode;
+
+/// Size of the sha256 digests used by OAEP
+const OAEP_HASH_SIZE: usize = 32;
+
 #[derive(Debug)]
 pub enum RSAError {
     MessageTooBig,
@@ -19,6 +29,10 @@ pub enum RSAError {
     FileWriteError,
     FileReadError,
     BadPEMFormat,
+    BadEnvelopeFormat,
+    UnsupportedEnvelopeVersion,
+    DecryptionError,
+    FaultyComputation,
 }
 
 impl fmt::Display for RSAError {
@@ -30,6 +44,12 @@ impl fmt::Display for RSAError {
             RSAError::FileWriteError => "Erreur d'écriture dans le fichier",
             RSAError::FileReadError => "Erreur de lecture du fichier",
             RSAError::BadPEMFormat => "Erreur de format PEM",
+            RSAError::BadEnvelopeFormat => "Mauvais format d'enveloppe",
+            RSAError::UnsupportedEnvelopeVersion => {
+                "Version d'enveloppe non supportée"
+            }
+            RSAError::DecryptionError => "Erreur de déchiffrement",
+            RSAError::FaultyComputation => "Erreur de calcul de la clé privée",
         };
         write!(f, "{}", msg)
     }
@@ -49,6 +69,37 @@ pub struct KeyPair {
     q: BigUint,
     timestamp: u32,
     user_id: String,
+    /// None when p and q are unknown, the private operations then fall
+    /// back to a full exponentiation modulo n
+    crt: Option<CrtParams>,
+    blinding: bool,
+}
+
+/// Exponents and coefficient used to do the private operations modulo p
+/// and q (Chinese remainder theorem), about 3 times faster than modulo n
+#[derive(Clone)]
+struct CrtParams {
+    /// d mod (p - 1)
+    dp: BigUint,
+    /// d mod (q - 1)
+    dq: BigUint,
+    /// q^-1 mod p
+    qinv: BigUint,
+}
+
+impl CrtParams {
+    fn new(n: &BigUint, d: &BigUint, p: &BigUint, q: &BigUint) -> Option<Self> {
+        let one = BigUint::from(1u32);
+        if p <= &one || q <= &one || &(p * q) != n {
+            return None;
+        }
+
+        Some(Self {
+            dp: d % (p - 1u32),
+            dq: d % (q - 1u32),
+            qinv: q.modinv(p)?,
+        })
+    }
 }
 
 struct _ASN1Key {
@@ -81,6 +132,8 @@ impl KeyPair {
             .unwrap()
             .as_secs() as u32;
 
+        let crt = CrtParams::new(&n, &d, &p, &q);
+
         return KeyPair {
             n: n,
             e: e,
@@ -89,6 +142,8 @@ impl KeyPair {
             q: q,
             timestamp: timestamp,
             user_id: user_id.to_string(),
+            crt,
+            blinding: false,
         };
     }
 
@@ -146,6 +201,78 @@ impl KeyPair {
         Ok(key)
     }
 
+    /// Blind the private operations with a random factor, so that their
+    /// duration doesn't depend on the (possibly attacker chosen) input
+    pub fn set_blinding(&mut self, blinding: bool) {
+        self.blinding = blinding;
+    }
+
+    /// Copy of the key doing its private operations as if only n and d
+    /// were known
+    pub fn without_crt(&self) -> Self {
+        Self {
+            crt: None,
+            ..self.clone()
+        }
+    }
+
+    /// m^d mod n, checked with the public exponent before being returned:
+    /// a result corrupted by a fault during the CRT computation would
+    /// reveal a factor of n
+    fn private_op(&self, m: &BigUint) -> Result<BigUint, RSAError> {
+        let res = self.blinded_private_op(m);
+        if res.modpow(&self.e, &self.n) != *m {
+            return Err(RSAError::FaultyComputation);
+        }
+        Ok(res)
+    }
+
+    fn blinded_private_op(&self, m: &BigUint) -> BigUint {
+        if !self.blinding {
+            return self.unblinded_private_op(m);
+        }
+
+        let (r, r_inv) = match self.blinding_factor() {
+            Some(f) => f,
+            None => return self.unblinded_private_op(m),
+        };
+        let blinded = (m * r.modpow(&self.e, &self.n)) % &self.n;
+        (self.unblinded_private_op(&blinded) * r_inv) % &self.n
+    }
+
+    fn unblinded_private_op(&self, m: &BigUint) -> BigUint {
+        let crt = match &self.crt {
+            Some(c) => c,
+            None => return m.modpow(&self.d, &self.n),
+        };
+
+        let m1 = m.modpow(&crt.dp, &self.p);
+        let m2 = m.modpow(&crt.dq, &self.q);
+        // h = qinv * (m1 - m2) mod p
+        let diff = (m1 + &self.p - (&m2 % &self.p)) % &self.p;
+        let h = (&crt.qinv * diff) % &self.p;
+        m2 + h * &self.q
+    }
+
+    /// Random r invertible modulo n, and its inverse
+    fn blinding_factor(&self) -> Option<(BigUint, BigUint)> {
+        let mut rng = rand::rng();
+        let one = BigUint::from(1u32);
+        // A random number is almost never a multiple of p or q
+        for _ in 0..8 {
+            let bytes: Vec<u8> =
+                (0..self.modulus_size()).map(|_| rng.random()).collect();
+            let r = BigUint::from_bytes_be(&bytes) % &self.n;
+            if r <= one {
+                continue;
+            }
+            if let Some(r_inv) = r.modinv(&self.n) {
+                return Some((r, r_inv));
+            }
+        }
+        None
+    }
+
     pub fn sign<T>(&self, message: T) -> Result<BigUint, RSAError>
     where
         T: Into<Vec<u8>>,
@@ -162,7 +289,7 @@ impl KeyPair {
             return Err(RSAError::MessageTooBig);
         }
 
-        Ok(m.modpow(&self.d, &self.n))
+        self.private_op(&m)
     }
 
     pub fn check_signature(
@@ -195,29 +322,6 @@ impl KeyPair {
         Ok(format!("{:0617}", res))
     }
 
-    pub fn crypt_split(&self, message: &String) -> Result<String, RSAError> {
-        let mut i = 0;
-        let mut j = 0;
-        let mut res = String::new();
-
-        while i < message.len() {
-            j += 250;
-            if j > message.len() {
-                j = message.len();
-            }
-
-            let r = match self.crypt(&message[i..j]) {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-            res.push_str(r.as_str());
-            i += 250;
-        }
-        return Ok(res);
-    }
-
     pub fn decrypt(&self, message: &str) -> Result<String, RSAError> {
         let parsed_msg = BigUint::parse_bytes(message.as_bytes(), 10)
             .ok_or(RSAError::BadSignatureFormat)?;
@@ -228,33 +332,95 @@ impl KeyPair {
             return Err(RSAError::MessageTooBig);
         }
 
-        let res = m.modpow(&self.d, &self.n);
+        let res = self.private_op(&m)?;
         match String::from_utf8(res.to_bytes_be()) {
             Ok(s) => Ok(s),
             Err(_) => Err(RSAError::BadSignatureFormat),
         }
     }
 
-    pub fn decrypt_split(&self, message: &String) -> Result<String, RSAError> {
-        let mut i = 0;
-        let mut j = 0;
-        let mut res = String::new();
+    /// Size in bytes of the modulus
+    fn modulus_size(&self) -> usize {
+        ((self.n.bits() + 7) / 8) as usize
+    }
 
-        while i < message.len() {
-            j += 617;
-            if j > message.len() {
-                j = message.len();
-            }
-            let r = match self.decrypt(&message[i..j]) {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-            res.push_str(r.as_str());
-            i += 617;
+    /// RSAES-OAEP encryption (RFC 8017) with sha256 and MGF1-sha256
+    pub fn oaep_encrypt(&self, message: &[u8]) -> Result<Vec<u8>, RSAError> {
+        let k = self.modulus_size();
+        if message.is_empty() {
+            return Err(RSAError::EmptyMessage);
+        }
+        if k < 2 * OAEP_HASH_SIZE + 2
+            || message.len() > k - 2 * OAEP_HASH_SIZE - 2
+        {
+            return Err(RSAError::MessageTooBig);
+        }
+
+        // DB = lHash || PS || 0x01 || M
+        let mut db = vec![0u8; k - OAEP_HASH_SIZE - 1];
+        db[..OAEP_HASH_SIZE].copy_from_slice(&sha256(&[]));
+        let one = db.len() - message.len() - 1;
+        db[one] = 0x01;
+        db[one + 1..].copy_from_slice(message);
+
+        let seed: [u8; OAEP_HASH_SIZE] = rand::rng().random();
+        xor_in_place(&mut db, &mgf1(&seed, k - OAEP_HASH_SIZE - 1));
+        let mut masked_seed = seed;
+        xor_in_place(&mut masked_seed, &mgf1(&db, OAEP_HASH_SIZE));
+
+        let mut em = Vec::with_capacity(k);
+        em.push(0x00);
+        em.extend_from_slice(&masked_seed);
+        em.extend_from_slice(&db);
+
+        let c = BigUint::from_bytes_be(&em).modpow(&self.e, &self.n);
+        Ok(left_pad(&c.to_bytes_be(), k))
+    }
+
+    /// RSAES-OAEP decryption, every padding error is reported the same way
+    pub fn oaep_decrypt(&self, cipher: &[u8]) -> Result<Vec<u8>, RSAError> {
+        let k = self.modulus_size();
+        if cipher.len() != k || k < 2 * OAEP_HASH_SIZE + 2 {
+            return Err(RSAError::DecryptionError);
+        }
+
+        let c = BigUint::from_bytes_be(cipher);
+        if c >= self.n {
+            return Err(RSAError::DecryptionError);
+        }
+        let em = left_pad(&self.private_op(&c)?.to_bytes_be(), k);
+
+        let mut seed = [0u8; OAEP_HASH_SIZE];
+        seed.copy_from_slice(&em[1..OAEP_HASH_SIZE + 1]);
+        let mut db = em[OAEP_HASH_SIZE + 1..].to_vec();
+        xor_in_place(&mut seed, &mgf1(&db, OAEP_HASH_SIZE));
+        xor_in_place(&mut db, &mgf1(&seed, k - OAEP_HASH_SIZE - 1));
+
+        let l_hash = sha256(&[]);
+        let mut bad = em[0] != 0x00;
+        bad |= db[..OAEP_HASH_SIZE] != l_hash;
+        let one = db[OAEP_HASH_SIZE..]
+            .iter()
+            .position(|b| *b != 0x00)
+            .map(|i| i + OAEP_HASH_SIZE);
+        match one {
+            Some(i) if !bad && db[i] == 0x01 => Ok(db[i + 1..].to_vec()),
+            _ => Err(RSAError::DecryptionError),
+        }
+    }
+
+    /// Encrypt `message` in a base64 envelope for the owner of this key
+    pub fn seal(&self, message: &str) -> Result<String, RSAError> {
+        Envelope::seal(self, message.as_bytes()).map(|e| e.to_base64())
+    }
+
+    /// Decrypt a base64 envelope sealed with this key
+    pub fn open(&self, message: &str) -> Result<String, RSAError> {
+        let plain = Envelope::from_base64(message)?.open(self)?;
+        match String::from_utf8(plain) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(RSAError::DecryptionError),
         }
-        return Ok(res);
     }
 
     // Concatenate packet of the public key with packet of user id
@@ -393,6 +559,8 @@ impl KeyPair {
                     q: BigUint::default(),
                     timestamp,
                     user_id: user_id.to_string(),
+                    crt: None,
+                    blinding: false,
                 });
             } else {
                 i += length;
@@ -498,6 +666,7 @@ impl KeyPair {
                 let p = parse_mpi(&decrypted, &mut j);
                 let q = parse_mpi(&decrypted, &mut j);
                 let _u = parse_mpi(&decrypted, &mut j);
+                let crt = CrtParams::new(&n, &d, &p, &q);
                 return Ok(KeyPair {
                     n,
                     e,
@@ -506,6 +675,8 @@ impl KeyPair {
                     q,
                     timestamp,
                     user_id: user_id.to_string(),
+                    crt,
+                    blinding: false,
                 });
             }
             i += len;
@@ -651,24 +822,15 @@ impl KeyPair {
         let hash = sha256(&to_hash);
         assert_eq!(hash.len(), 32);
         let hash_prefix = &hash[0..2];
-        let mut digest_info = vec![];
-        digest_info.extend_from_slice(&[
-            0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65,
-            0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
-        ]);
-        digest_info.extend_from_slice(&hash);
-        assert_eq!(digest_info.len(), 51);
-        let modulus_len = (self.n.bits() + 7) / 8;
-        let ps_len = modulus_len as usize - 3 - digest_info.len();
-        let mut padded = Vec::with_capacity(modulus_len as usize);
-        padded.push(0x00);
-        padded.push(0x01);
-        padded.extend(std::iter::repeat(0xFF).take(ps_len));
-        padded.push(0x00);
-        padded.extend(digest_info);
-        assert_eq!(padded.len(), modulus_len as usize);
+        let padded = match emsa_pkcs1_v15_encode(&hash, self.modulus_size()) {
+            Ok(p) => p,
+            Err(_) => return Vec::new(),
+        };
         let m = BigUint::from_bytes_be(&padded);
-        let sig = m.modpow(&self.d, &self.n);
+        let sig = match self.private_op(&m) {
+            Ok(s) => s,
+            Err(_) => return Vec::new(),
+        };
         let mpi = encode_n_e(&sig);
         let mut final_body = hashed_data;
         final_body.extend(&(unhashed_subpackets.len() as u16).to_be_bytes());
@@ -764,6 +926,36 @@ fn crc24(data: &[u8]) -> u32 {
     crc & 0xFFFFFF
 }
 
+// MGF1 mask generation function (RFC 8017) over sha256: concatenates
+// sha256(seed || counter) until `len` bytes are produced
+
+fn mgf1(seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask = Vec::with_capacity(len + OAEP_HASH_SIZE);
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut input = seed.to_vec();
+        input.extend_from_slice(&counter.to_be_bytes());
+        mask.extend_from_slice(&sha256(&input));
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+fn xor_in_place(data: &mut [u8], mask: &[u8]) {
+    for (d, m) in data.iter_mut().zip(mask) {
+        *d ^= m;
+    }
+}
+
+// big endian integer written on exactly `len` bytes
+
+fn left_pad(bytes: &[u8], len: usize) -> Vec<u8> {
+    let mut res = vec![0u8; len.saturating_sub(bytes.len())];
+    res.extend_from_slice(bytes);
+    res
+}
+
 fn parse_mpi(data: &[u8], i: &mut usize) -> BigUint {
     let bit_len = ((data[*i] as u16) << 8) | data[*i + 1] as u16;
     let byte_len = ((bit_len + 7) / 8) as usize;
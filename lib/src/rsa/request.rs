@@ -0,0 +1,33 @@
+//! Authentication of the requests sent to a server
+//!
+//! The client asks the server for a nonce (`GET /challenge`), then signs
+//! the method, the path with its query, the timestamp, the nonce and the
+//! sha256 of the body with `SignatureScheme::Pkcs1v15Sha256`. The server
+//! accepts each nonce only once, so a captured request can't be replayed.
+
+use crate::{defaults::SIG_SAMPLE, sha256::sha256};
+
+pub const LOGIN_HEADER: &str = "Login";
+pub const NONCE_HEADER: &str = "Nonce";
+pub const TIMESTAMP_HEADER: &str = "Timestamp";
+pub const SIGNATURE_HEADER: &str = "Signature";
+
+/// Message signed by the client for a request
+pub fn request_message(
+    method: &str,
+    path: &str,
+    timestamp: u32,
+    nonce: &str,
+    body: &[u8],
+) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        SIG_SAMPLE,
+        method.to_uppercase(),
+        path,
+        timestamp,
+        nonce,
+        hex::encode(sha256(body))
+    )
+    .into_bytes()
+}
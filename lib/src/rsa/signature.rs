@@ -0,0 +1,199 @@
+//! Signature schemes
+//!
+//! `Raw` is the historical textbook RSA signature of the sha256 of the
+//! message, it is malleable and only kept to check old transactions.
+//! `Pkcs1v15Sha256` is RSASSA-PKCS1-v1_5 (RFC 8017) with sha256.
+
+use super::{KeyPair, RSAError};
+use crate::sha256::sha256;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+/// DER encoding of the sha256 AlgorithmIdentifier, prefix of the
+/// DigestInfo
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03,
+    0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    #[default]
+    Raw = 0,
+    Pkcs1v15Sha256 = 1,
+}
+
+impl SignatureScheme {
+    pub fn from_u8(s: u8) -> Option<Self> {
+        match s {
+            0 => Some(SignatureScheme::Raw),
+            1 => Some(SignatureScheme::Pkcs1v15Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// EMSA-PKCS1-v1_5 encoding of a sha256 digest on `k` bytes:
+/// 0x00 || 0x01 || 0xff.. || 0x00 || DigestInfo
+pub(super) fn emsa_pkcs1_v15_encode(
+    hash: &[u8; 32],
+    k: usize,
+) -> Result<Vec<u8>, RSAError> {
+    let t_len = SHA256_DIGEST_INFO.len() + hash.len();
+    if k < t_len + 11 {
+        return Err(RSAError::MessageTooBig);
+    }
+
+    let mut em = Vec::with_capacity(k);
+    em.push(0x00);
+    em.push(0x01);
+    em.resize(k - t_len - 1, 0xff);
+    em.push(0x00);
+    em.extend_from_slice(&SHA256_DIGEST_INFO);
+    em.extend_from_slice(hash);
+    Ok(em)
+}
+
+impl KeyPair {
+    pub fn sign_pkcs1_v15(&self, message: &[u8]) -> Result<BigUint, RSAError> {
+        if message.is_empty() {
+            return Err(RSAError::EmptyMessage);
+        }
+
+        let em = emsa_pkcs1_v15_encode(&sha256(message), self.modulus_size())?;
+        self.private_op(&BigUint::from_bytes_be(&em))
+    }
+
+    pub fn check_signature_pkcs1_v15(
+        &self,
+        message: &[u8],
+        signature: &BigUint,
+    ) -> Result<bool, RSAError> {
+        if message.is_empty() {
+            return Err(RSAError::EmptyMessage);
+        }
+        if signature >= &self.n {
+            return Ok(false);
+        }
+
+        let k = self.modulus_size();
+        let expected = emsa_pkcs1_v15_encode(&sha256(message), k)?;
+        let em = signature.modpow(&self.e, &self.n).to_bytes_be();
+
+        // The leading 0x00 of the encoding is lost in the integer
+        Ok(em.len() == k - 1 && em[..] == expected[1..])
+    }
+
+    pub fn sign_with(
+        &self,
+        scheme: SignatureScheme,
+        message: &[u8],
+    ) -> Result<BigUint, RSAError> {
+        match scheme {
+            SignatureScheme::Raw => self.sign(message),
+            SignatureScheme::Pkcs1v15Sha256 => self.sign_pkcs1_v15(message),
+        }
+    }
+
+    pub fn check_signature_with(
+        &self,
+        scheme: SignatureScheme,
+        message: &Vec<u8>,
+        signature: &BigUint,
+    ) -> Result<bool, RSAError> {
+        match scheme {
+            SignatureScheme::Raw => self.check_signature(message, signature),
+            SignatureScheme::Pkcs1v15Sha256 => {
+                self.check_signature_pkcs1_v15(message, signature)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsa::CrtParams;
+    use std::str::FromStr;
+
+    // 512 bits key of the Go crypto/rsa PKCS#1 v1.5 test vectors
+    fn test_key() -> KeyPair {
+        let p = BigUint::from_str(
+            "98920366548084643601728869055592650835572950932266967461790948584\
+             315647051443",
+        )
+        .unwrap();
+        let q = BigUint::from_str(
+            "94560208308847015747498523884063394671606671904944666360068158221\
+             458669711639",
+        )
+        .unwrap();
+        let d = BigUint::from_str(
+            "72663984313281163440576993797492225322793439238190636394970490393\
+             89899328538543087657733766554155839834519529439851673014800261285\
+             757759040931985506583861",
+        )
+        .unwrap();
+        let n = p.clone() * q.clone();
+        let crt = CrtParams::new(&n, &d, &p, &q);
+        assert!(crt.is_some());
+        KeyPair {
+            n,
+            e: BigUint::from(65537u32),
+            d,
+            p,
+            q,
+            timestamp: 0,
+            user_id: String::new(),
+            crt,
+            blinding: false,
+        }
+    }
+
+    const MESSAGE: &[u8] = b"Test.\n";
+    const SIGNATURE: &str =
+        "2ffae3f3e130287b3a1dcb320e46f52e8f3f7969b646932273\
+                             a7e3a6f2a182ea02d42875a7ffa4a148aa311f9e4b562e4e13\
+                             a2223fb15f4e5bf5f2b206d9451b";
+
+    #[test]
+    fn pkcs1_v15_known_answer() {
+        let key = test_key();
+        let mut blinded = key.clone();
+        blinded.set_blinding(true);
+
+        for k in [key.without_crt(), key, blinded] {
+            let sig = k.sign_pkcs1_v15(MESSAGE).unwrap();
+            assert_eq!(hex::encode(sig.to_bytes_be()), SIGNATURE);
+        }
+    }
+
+    #[test]
+    fn pkcs1_v15_verify() {
+        let key = test_key();
+        let sig = BigUint::from_bytes_be(&hex::decode(SIGNATURE).unwrap());
+        let check = |m: &[u8], s| key.check_signature_pkcs1_v15(m, s).unwrap();
+
+        assert!(check(MESSAGE, &sig));
+        assert!(!check(b"Test.\r\n", &sig));
+        assert!(!check(MESSAGE, &(sig.clone() + 1u32)));
+    }
+
+    #[test]
+    fn schemes_are_not_interchangeable() {
+        let key = test_key();
+        let message = MESSAGE.to_vec();
+        let raw = key.sign_with(SignatureScheme::Raw, MESSAGE).unwrap();
+        let padded = key
+            .sign_with(SignatureScheme::Pkcs1v15Sha256, MESSAGE)
+            .unwrap();
+        let check = |scheme, sig| {
+            key.check_signature_with(scheme, &message, sig).unwrap()
+        };
+
+        assert!(check(SignatureScheme::Raw, &raw));
+        assert!(check(SignatureScheme::Pkcs1v15Sha256, &padded));
+        assert!(!check(SignatureScheme::Pkcs1v15Sha256, &raw));
+        assert!(!check(SignatureScheme::Raw, &padded));
+    }
+}
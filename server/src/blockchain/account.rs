@@ -0,0 +1,70 @@
+use nexium::{
+    blockchain::amount::Amount,
+    defaults::{INITIAL_BALANCE, STRICT_BALANCE_BLOCK_VERSION},
+};
+
+/// State of an account at a given point of the chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Account {
+    pub balance: Amount,
+    /// Amount spent beyond the balance by the blocks older than
+    /// STRICT_BALANCE_BLOCK_VERSION, paid back by the next credits. The
+    /// balance is zero while it is not.
+    pub debt: Amount,
+    /// Nonce expected for the next transaction of the account
+    pub nonce: u64,
+    /// Number of classic transactions sent or received
+    pub transactions: u64,
+}
+
+impl Account {
+    /// State of an account that never appeared in the chain
+    pub fn initial() -> Result<Self, String> {
+        match Amount::from_nex(INITIAL_BALANCE as u64) {
+            Some(balance) => Ok(Self {
+                balance,
+                debt: Amount::ZERO,
+                nonce: 0,
+                transactions: 0,
+            }),
+            None => Err("Invalid initial balance".to_string()),
+        }
+    }
+
+    /// Account credited with `amount`, the debt being paid back first.
+    /// None on overflow.
+    pub fn credited(self, amount: Amount) -> Option<Self> {
+        let mut res = self;
+        match self.debt.checked_sub(amount) {
+            Some(debt) => res.debt = debt,
+            None => {
+                let rest = amount.checked_sub(self.debt)?;
+                res.balance = self.balance.checked_add(rest)?;
+                res.debt = Amount::ZERO;
+            }
+        }
+        Some(res)
+    }
+
+    /// Account debited of `amount`. Beyond the balance, the rest becomes a
+    /// debt if `overdraft` is set, None otherwise.
+    pub fn debited(self, amount: Amount, overdraft: bool) -> Option<Self> {
+        let mut res = self;
+        match self.balance.checked_sub(amount) {
+            Some(balance) => res.balance = balance,
+            None if overdraft => {
+                let rest = amount.checked_sub(self.balance)?;
+                res.debt = self.debt.checked_add(rest)?;
+                res.balance = Amount::ZERO;
+            }
+            None => return None,
+        }
+        Some(res)
+    }
+}
+
+/// Whether the transactions of a block of `version` may take their emitter
+/// below zero, as the f32 balances of the first servers did
+pub fn allows_overdraft(version: u16) -> bool {
+    version < STRICT_BALANCE_BLOCK_VERSION
+}
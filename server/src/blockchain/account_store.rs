@@ -0,0 +1,494 @@
+//! State of the accounts at the tip of the main chain
+//!
+//! The store holds the balance, the nonce and the number of transactions
+//! of every account that appears in the chain. It is updated when a block
+//! is connected or disconnected, so that an account never needs a scan of
+//! the chain. It is saved with the tip it matches every CHECKPOINT_INTERVAL
+//! blocks: the chain itself records the changes since the last save, its
+//! blocks after the saved tip are applied again at startup. The store is
+//! rebuilt from the whole chain when the saved tip is not on it.
+//!
+//! The store also indexes the position of the classic transactions of each
+//! login, emitted or received, to read its history without a scan.
+
+use super::{
+    account::{allows_overdraft, Account},
+    atomic_file,
+    history::TxPosition,
+    structure::{
+        block::Block, block_header::HeaderPreviousBlockHash,
+        consts::HEADER_PREVIOUS_BLOCK_HASH_SIZE,
+    },
+};
+use nexium::{
+    blockchain::{
+        amount::Amount, transaction::Transaction,
+        transaction_data::TransactionData,
+    },
+    sha256::sha256,
+};
+use std::{collections::HashMap, fs, path::Path};
+
+const STORE_MAGIC: &[u8; 4] = b"NXAS";
+const STORE_VERSION: u8 = 3;
+const CHECKSUM_SIZE: usize = 4;
+/// Number of blocks connected between two saves of the store
+pub const CHECKPOINT_INTERVAL: u64 = 64;
+
+pub struct AccountStore {
+    accounts: HashMap<String, Account>,
+    /// State of the accounts missing from the store
+    initial: Account,
+    /// Positions of the classic transactions of each login, oldest first
+    history: HashMap<String, Vec<TxPosition>>,
+}
+
+impl AccountStore {
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            accounts: HashMap::new(),
+            initial: Account::initial()?,
+            history: HashMap::new(),
+        })
+    }
+
+    /// Account of `login` at the tip
+    pub fn get(&self, login: &str) -> Account {
+        match self.accounts.get(login) {
+            Some(a) => *a,
+            None => self.initial,
+        }
+    }
+
+    /// Positions of the classic transactions emitted or received by
+    /// `login`, oldest first
+    pub fn history(&self, login: &str) -> &[TxPosition] {
+        match self.history.get(login) {
+            Some(h) => h,
+            None => &[],
+        }
+    }
+
+    /// Apply the transactions of the block connected to the tip at `height`
+    pub fn apply(&mut self, block: &Block, height: u64) -> Result<(), String> {
+        for (index, tr) in block.transactions.iter().enumerate() {
+            let mut logins = Self::logins(tr)?;
+            for login in logins.iter() {
+                let account =
+                    self.accounts.entry(login.clone()).or_insert(self.initial);
+                Self::update(account, login, tr, block.header.version, true)?;
+            }
+
+            if !tr.header.data_type.is_classic() {
+                continue;
+            }
+            let position = TxPosition {
+                height,
+                index: index as u32,
+            };
+            // A transaction to oneself is indexed once
+            logins.dedup();
+            for login in logins {
+                self.history.entry(login).or_default().push(position);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo the transactions of the block disconnected from the tip at
+    /// `height`
+    pub fn revert(&mut self, block: &Block, height: u64) -> Result<(), String> {
+        for tr in block.transactions.iter().rev() {
+            for login in Self::logins(tr)? {
+                let account =
+                    self.accounts.entry(login.clone()).or_insert(self.initial);
+                Self::update(account, &login, tr, block.header.version, false)?;
+                if *account == self.initial {
+                    self.accounts.remove(&login);
+                }
+
+                if let Some(positions) = self.history.get_mut(&login) {
+                    while positions.last().is_some_and(|p| p.height >= height) {
+                        positions.pop();
+                    }
+                    if positions.is_empty() {
+                        self.history.remove(&login);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Logins whose account is changed by `tr`
+    pub fn logins(tr: &Transaction) -> Result<Vec<String>, String> {
+        let mut logins = vec![tr.header.get_login()];
+        match tr.get_data() {
+            Ok(TransactionData::ClassicTransaction { receiver, .. }) => {
+                logins.push(
+                    String::from_utf8_lossy(&receiver)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                );
+            }
+            Ok(_) => {}
+            Err(_) => return Err("Failed to get transaction data".to_string()),
+        }
+        Ok(logins)
+    }
+
+    /// Apply `tr`, of a block of `version`, to `account`, the account of
+    /// `login`, or undo it if `forward` is false. A block older than
+    /// STRICT_BALANCE_BLOCK_VERSION may take the emitter below zero: what
+    /// is spent beyond the balance is kept as a debt, so that undoing the
+    /// transaction gives back the exact previous state.
+    pub fn update(
+        account: &mut Account,
+        login: &str,
+        tr: &Transaction,
+        version: u16,
+        forward: bool,
+    ) -> Result<(), String> {
+        let emitter = tr.header.get_login();
+        let data = match tr.get_data() {
+            Ok(d) => d,
+            Err(_) => return Err("Failed to get transaction data".to_string()),
+        };
+
+        if tr.is_coinbase() {
+            // The coinbase credits its miner and uses no nonce
+            if let TransactionData::Coinbase { reward, .. } = data {
+                if emitter == login {
+                    *account = credit(*account, reward, forward)?;
+                }
+            }
+            return Ok(());
+        }
+
+        if emitter == login && tr.header.has_nonce() {
+            account.nonce = if forward {
+                tr.header.nonce + 1
+            } else {
+                tr.header.nonce
+            };
+        }
+
+        let (receiver, amount) = match data {
+            TransactionData::ClassicTransaction {
+                receiver, amount, ..
+            } => (receiver, amount),
+            _ => return Ok(()),
+        };
+        let receiver = String::from_utf8_lossy(&receiver)
+            .trim_end_matches('\0')
+            .to_string();
+
+        if receiver == login {
+            *account = credit(*account, amount, forward)?;
+        } else if emitter == login {
+            // The emitter pays the amount and the fees
+            let cost = match tr.total_cost() {
+                Some(c) => c,
+                None => return Err("Invalid balance".to_string()),
+            };
+            let res = if forward {
+                account.debited(cost, allows_overdraft(version))
+            } else {
+                account.credited(cost)
+            };
+            *account = match res {
+                Some(a) => a,
+                None => return Err("Invalid balance".to_string()),
+            };
+        } else {
+            return Ok(());
+        }
+
+        account.transactions = if forward {
+            account.transactions + 1
+        } else {
+            account.transactions.saturating_sub(1)
+        };
+        Ok(())
+    }
+
+    /// Load the store saved at `path`, with the number of blocks of the
+    /// chain it was saved for and the hash of the last one
+    pub fn load(
+        path: &Path,
+    ) -> Result<(Self, u64, HeaderPreviousBlockHash), String> {
+        let buff = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) => return Err(format!("Failed to read accounts: {}", e)),
+        };
+        let header_size =
+            STORE_MAGIC.len() + 1 + HEADER_PREVIOUS_BLOCK_HASH_SIZE + 8 + 4;
+        if buff.len() < header_size + CHECKSUM_SIZE
+            || &buff[..STORE_MAGIC.len()] != STORE_MAGIC
+            || buff[STORE_MAGIC.len()] != STORE_VERSION
+        {
+            return Err("Not an account store".to_string());
+        }
+        let (data, checksum) = buff.split_at(buff.len() - CHECKSUM_SIZE);
+        if sha256(data)[..CHECKSUM_SIZE] != *checksum {
+            return Err("Bad checksum".to_string());
+        }
+
+        let mut pos = STORE_MAGIC.len() + 1;
+        let mut take = |n: usize| -> Result<&[u8], String> {
+            match data.get(pos..pos + n) {
+                Some(field) => {
+                    pos += n;
+                    Ok(field)
+                }
+                None => Err("Truncated account store".to_string()),
+            }
+        };
+
+        let tip: HeaderPreviousBlockHash =
+            take(HEADER_PREVIOUS_BLOCK_HASH_SIZE)?.try_into().unwrap();
+        let count = u64::from_be_bytes(take(8)?.try_into().unwrap());
+
+        let mut store = Self::new()?;
+        let n = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        for _ in 0..n {
+            let len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+            let login =
+                String::from_utf8_lossy(take(len as usize)?).to_string();
+            let balance = u64::from_be_bytes(take(8)?.try_into().unwrap());
+            let debt = u64::from_be_bytes(take(8)?.try_into().unwrap());
+            let nonce = u64::from_be_bytes(take(8)?.try_into().unwrap());
+            let transactions = u64::from_be_bytes(take(8)?.try_into().unwrap());
+            let account = Account {
+                balance: Amount::from_micro(balance),
+                debt: Amount::from_micro(debt),
+                nonce,
+                transactions,
+            };
+            store.accounts.insert(login, account);
+        }
+
+        let n = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        for _ in 0..n {
+            let len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+            let login =
+                String::from_utf8_lossy(take(len as usize)?).to_string();
+            let count = u32::from_be_bytes(take(4)?.try_into().unwrap());
+            let mut positions = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let height = u64::from_be_bytes(take(8)?.try_into().unwrap());
+                let index = u32::from_be_bytes(take(4)?.try_into().unwrap());
+                positions.push(TxPosition { height, index });
+            }
+            store.history.insert(login, positions);
+        }
+        Ok((store, count, tip))
+    }
+
+    /// Save the store to `path`, as the state after the chain of `count`
+    /// blocks ending with `tip`. The previous save is replaced once the new
+    /// one is fully written.
+    pub fn save(
+        &self,
+        path: &Path,
+        tip: &HeaderPreviousBlockHash,
+        count: u64,
+    ) -> Result<(), String> {
+        let mut buff = STORE_MAGIC.to_vec();
+        buff.push(STORE_VERSION);
+        buff.extend_from_slice(tip);
+        buff.extend_from_slice(&count.to_be_bytes());
+        buff.extend_from_slice(&(self.accounts.len() as u32).to_be_bytes());
+        for (login, account) in self.accounts.iter() {
+            buff.extend_from_slice(&(login.len() as u16).to_be_bytes());
+            buff.extend_from_slice(login.as_bytes());
+            buff.extend_from_slice(&account.balance.as_micro().to_be_bytes());
+            buff.extend_from_slice(&account.debt.as_micro().to_be_bytes());
+            buff.extend_from_slice(&account.nonce.to_be_bytes());
+            buff.extend_from_slice(&account.transactions.to_be_bytes());
+        }
+        buff.extend_from_slice(&(self.history.len() as u32).to_be_bytes());
+        for (login, positions) in self.history.iter() {
+            buff.extend_from_slice(&(login.len() as u16).to_be_bytes());
+            buff.extend_from_slice(login.as_bytes());
+            buff.extend_from_slice(&(positions.len() as u32).to_be_bytes());
+            for p in positions.iter() {
+                buff.extend_from_slice(&p.height.to_be_bytes());
+                buff.extend_from_slice(&p.index.to_be_bytes());
+            }
+        }
+        let checksum = sha256(&buff);
+        buff.extend_from_slice(&checksum[..CHECKSUM_SIZE]);
+
+        if let Err(e) = atomic_file::write(path, &buff) {
+            return Err(format!("Failed to write accounts: {}", e));
+        }
+        Ok(())
+    }
+}
+
+/// `account` credited with `amount`, or debited if `add` is false. A
+/// credit is only undone to go back to a previous state of the account,
+/// which may have been in debt.
+fn credit(
+    account: Account,
+    amount: Amount,
+    add: bool,
+) -> Result<Account, String> {
+    let res = if add {
+        account.credited(amount)
+    } else {
+        account.debited(amount, true)
+    };
+    match res {
+        Some(a) => Ok(a),
+        None => Err("Invalid balance".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::structure::block_header::BlockHeader;
+    use nexium::{
+        blockchain::{
+            consts::{DESCRIPTION_SIZE, TRANSACTION_RECEIVER},
+            data_type::DataType,
+            transaction_header::TransactionHeader,
+        },
+        defaults::STRICT_BALANCE_BLOCK_VERSION,
+    };
+    use num_bigint::BigUint;
+    use std::{env, process};
+
+    fn nex(n: u64) -> Amount {
+        Amount::from_nex(n).unwrap()
+    }
+
+    /// Unsigned transaction of `data`: the store does not check signatures
+    fn transaction(
+        data: TransactionData,
+        data_type: DataType,
+        emitter: &str,
+    ) -> Transaction {
+        let data = data.to_buffer();
+        Transaction {
+            header: TransactionHeader::new(
+                data.len() as u16,
+                1,
+                emitter,
+                data_type,
+                0,
+            ),
+            data,
+            signature: BigUint::from(0_u8),
+        }
+    }
+
+    fn classic(emitter: &str, receiver: &str, amount: Amount) -> Transaction {
+        let mut buff = [0; TRANSACTION_RECEIVER];
+        buff[..receiver.len()].copy_from_slice(receiver.as_bytes());
+        let data = TransactionData::ClassicTransaction {
+            receiver: buff,
+            amount,
+            has_description: false,
+            description: [0; DESCRIPTION_SIZE],
+        };
+        transaction(data, DataType::ClassicTransactionV2, emitter)
+    }
+
+    fn coinbase(miner: &str, height: u64, reward: Amount) -> Transaction {
+        let data = TransactionData::Coinbase { height, reward };
+        transaction(data, DataType::Coinbase, miner)
+    }
+
+    fn block(version: u16, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                version,
+                ..Default::default()
+            },
+            transactions,
+        }
+    }
+
+    /// Blocks taking "alice" into debt, then paying part of it back
+    fn blocks_with_debt() -> Vec<Block> {
+        let initial = Account::initial().unwrap().balance;
+        let spend =
+            classic("alice", "bob", initial.checked_add(nex(5)).unwrap());
+        let pay_back = classic("bob", "alice", nex(3));
+        vec![
+            block(1, vec![coinbase("carol", 0, nex(10)), spend]),
+            block(1, vec![coinbase("alice", 1, nex(1)), pay_back]),
+        ]
+    }
+
+    #[test]
+    fn revert_undoes_apply_with_debt() {
+        let blocks = blocks_with_debt();
+        let mut store = AccountStore::new().unwrap();
+        let mut states = vec![];
+        for (height, block) in blocks.iter().enumerate() {
+            states.push((store.accounts.clone(), store.history.clone()));
+            store.apply(block, height as u64).unwrap();
+        }
+
+        let spend = &blocks[0].transactions[1];
+        let fee = spend.fee_cost();
+        let alice = store.get("alice");
+        assert_eq!(alice.balance, Amount::ZERO);
+        assert_eq!(
+            alice.debt,
+            nex(5)
+                .checked_add(fee)
+                .unwrap()
+                .checked_sub(nex(4))
+                .unwrap()
+        );
+        assert_eq!(alice.nonce, 1);
+        assert_eq!(alice.transactions, 2);
+        assert_eq!(store.history("alice").len(), 2);
+
+        for (height, block) in blocks.iter().enumerate().rev() {
+            store.revert(block, height as u64).unwrap();
+            let (accounts, history) = &states[height];
+            assert_eq!(store.accounts, *accounts);
+            assert_eq!(store.history, *history);
+        }
+        assert!(store.accounts.is_empty());
+    }
+
+    #[test]
+    fn strict_blocks_refuse_debt() {
+        let mut blocks = blocks_with_debt();
+        blocks[0].header.version = STRICT_BALANCE_BLOCK_VERSION;
+        let mut store = AccountStore::new().unwrap();
+        assert!(store.apply(&blocks[0], 0).is_err());
+    }
+
+    #[test]
+    fn loads_saved_store() {
+        let path = env::temp_dir()
+            .join(format!("nexium-account-store-{}", process::id()));
+        let mut store = AccountStore::new().unwrap();
+        for (height, block) in blocks_with_debt().iter().enumerate() {
+            store.apply(block, height as u64).unwrap();
+        }
+        let tip = [7; HEADER_PREVIOUS_BLOCK_HASH_SIZE];
+        store.save(&path, &tip, 2).unwrap();
+
+        let (loaded, count, loaded_tip) = AccountStore::load(&path).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(loaded_tip, tip);
+        assert_eq!(loaded.accounts, store.accounts);
+        assert_eq!(loaded.history, store.history);
+
+        let mut buff = fs::read(&path).unwrap();
+        let middle = buff.len() / 2;
+        buff[middle] ^= 0xff;
+        fs::write(&path, &buff).unwrap();
+        assert!(AccountStore::load(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,505 @@
+//! Storage of the blocks in the blockchain file
+//!
+//! After a magic and a version, the file holds one record per block of the
+//! main chain: the length of the block, the block and the first bytes of
+//! its sha256. A block is appended with a single write, so a crash can only
+//! leave its record partly written at the end of the file. Such a torn
+//! record is cut on open. A damaged record followed by other ones is not a
+//! torn write: the file is then only cut in repair mode, keeping the blocks
+//! before it.
+//!
+//! Files of the first version, holding the blocks one after the other
+//! without framing, are rewritten in the current format on open. They are
+//! recognised by their first block, whose header has a known version and no
+//! previous block. Any other file is an error: in repair mode, it is moved
+//! aside and a new chain is started.
+
+use super::{
+    atomic_file,
+    structure::{
+        block_header::{BlockHeader, HeaderPreviousBlockHash},
+        consts::BLOCK_HEADER_SIZE,
+    },
+};
+use nexium::{defaults::BLOCK_VERSION, sha256::sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const FILE_MAGIC: &[u8; 4] = b"NXBC";
+const FILE_VERSION: u8 = 2;
+/// Size of the magic and the version, the first record follows them
+pub const FILE_HEADER_SIZE: u64 = FILE_MAGIC.len() as u64 + 1;
+const RECORD_HEADER_SIZE: u64 = 4;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Size of the record of a block of `block_size` bytes
+pub fn record_size(block_size: u64) -> u64 {
+    RECORD_HEADER_SIZE + block_size + CHECKSUM_SIZE as u64
+}
+
+/// Record of the block `buff`
+pub fn record(buff: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(record_size(buff.len() as u64) as usize);
+    res.extend_from_slice(&(buff.len() as u32).to_be_bytes());
+    res.extend_from_slice(buff);
+    res.extend_from_slice(&sha256(buff)[..CHECKSUM_SIZE]);
+    res
+}
+
+/// Open the blockchain file at `path`, creating it if needed, and return it
+/// with its size. A file of the first version is upgraded first. A file in
+/// no known format is moved aside in `repair` mode, an error otherwise.
+pub fn open(path: &Path, repair: bool) -> Result<(File, u64), String> {
+    let mut file = match OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(format!(
+                "Failed to open blockchain file {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    };
+    let size = match file.metadata() {
+        Ok(m) => m.len(),
+        Err(e) => {
+            return Err(format!("Failed to get blockchain file size: {}", e));
+        }
+    };
+
+    if size == 0 {
+        let mut header = FILE_MAGIC.to_vec();
+        header.push(FILE_VERSION);
+        file.write_all(&header).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        return Ok((file, FILE_HEADER_SIZE));
+    }
+
+    let mut start = vec![];
+    let read = (&file)
+        .take(BLOCK_HEADER_SIZE as u64)
+        .read_to_end(&mut start);
+    if let Err(e) = read {
+        return Err(format!("Failed to read blockchain file: {}", e));
+    }
+    let header = &start[..start.len().min(FILE_HEADER_SIZE as usize)];
+    let magic = header.len().min(FILE_MAGIC.len());
+    if header[..magic] != FILE_MAGIC[..magic] {
+        drop(file);
+        if is_legacy(&start) {
+            upgrade(path)?;
+        } else if repair {
+            set_aside(path)?;
+        } else {
+            return Err(format!(
+                "{} is not a blockchain file, start with --repair to move it \
+                 aside and start a new chain",
+                path.display()
+            ));
+        }
+        return open(path, repair);
+    }
+    if header.len() < FILE_HEADER_SIZE as usize {
+        // The creation of the file was interrupted
+        truncate(&mut file, 0)?;
+        drop(file);
+        return open(path, repair);
+    }
+    if header[FILE_MAGIC.len()] != FILE_VERSION {
+        return Err(format!(
+            "Unsupported blockchain file version {}",
+            header[FILE_MAGIC.len()]
+        ));
+    }
+    Ok((file, size))
+}
+
+/// Whether `start`, the first bytes of a file, is the header of the first
+/// block of a file of the first version
+fn is_legacy(start: &[u8]) -> bool {
+    let header = match start.try_into() {
+        Ok(h) => BlockHeader::from_buff(h),
+        Err(_) => return false,
+    };
+    (1..=BLOCK_VERSION).contains(&header.version)
+        && header.previous_block_hash == HeaderPreviousBlockHash::default()
+}
+
+/// Move the file at `path` to `<path>.bad`, so that a new one is created
+fn set_aside(path: &Path) -> Result<(), String> {
+    let mut bad = path.as_os_str().to_owned();
+    bad.push(".bad");
+    if let Err(e) = fs::rename(path, &bad) {
+        return Err(format!("Failed to move blockchain file: {}", e));
+    }
+    println!(
+        "Moved the unknown blockchain file to {}",
+        Path::new(&bad).display()
+    );
+    Ok(())
+}
+
+/// Rewrite the file of the first version at `path` in the current format.
+/// A block cut by the end of the file is dropped.
+fn upgrade(path: &Path) -> Result<(), String> {
+    let buff = match fs::read(path) {
+        Ok(b) => b,
+        Err(e) => return Err(format!("Failed to read blockchain file: {}", e)),
+    };
+    println!(
+        "Upgrading the blockchain file to version {}...",
+        FILE_VERSION
+    );
+
+    let mut res = FILE_MAGIC.to_vec();
+    res.push(FILE_VERSION);
+    let mut offset = 0;
+    while offset + BLOCK_HEADER_SIZE <= buff.len() {
+        let header = BlockHeader::from_buff(
+            buff[offset..offset + BLOCK_HEADER_SIZE].try_into().unwrap(),
+        );
+        let end =
+            offset + BLOCK_HEADER_SIZE + header.transactions_size as usize;
+        if end > buff.len() {
+            break;
+        }
+        res.extend(record(&buff[offset..end]));
+        offset = end;
+    }
+    if offset < buff.len() {
+        println!(
+            "Dropped a torn block at the end of the blockchain file ({} bytes)",
+            buff.len() - offset
+        );
+    }
+
+    if let Err(e) = atomic_file::write(path, &res) {
+        return Err(format!("Failed to write blockchain file: {}", e));
+    }
+    Ok(())
+}
+
+fn seek(file: &mut File, offset: u64) -> Result<(), String> {
+    match file.seek(SeekFrom::Start(offset)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to seek in blockchain file: {}", e)),
+    }
+}
+
+/// Header of the block of the record at `offset` of `file`
+pub fn read_header(
+    file: &mut File,
+    offset: u64,
+) -> Result<[u8; BLOCK_HEADER_SIZE], String> {
+    let mut buff = [0_u8; BLOCK_HEADER_SIZE];
+    seek(file, offset + RECORD_HEADER_SIZE)?;
+    match file.read_exact(&mut buff) {
+        Ok(_) => Ok(buff),
+        Err(e) => Err(format!("Error reading blockchain file: {}", e)),
+    }
+}
+
+/// Block of the record at `offset` of `file`, checked against its checksum
+pub fn read_block(file: &mut File, offset: u64) -> Result<Vec<u8>, String> {
+    seek(file, offset)?;
+    let mut len = [0_u8; RECORD_HEADER_SIZE as usize];
+    if let Err(e) = file.read_exact(&mut len) {
+        return Err(format!("Failed to read blockchain file: {}", e));
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    let mut buff = vec![0_u8; len + CHECKSUM_SIZE];
+    if let Err(e) = file.read_exact(&mut buff) {
+        return Err(format!("Failed to read blockchain file: {}", e));
+    }
+    let checksum = buff.split_off(len);
+    if sha256(&buff)[..CHECKSUM_SIZE] != checksum {
+        return Err(format!("Bad checksum for the block at offset {}", offset));
+    }
+    Ok(buff)
+}
+
+/// Damage found at the end of the valid records
+enum Damage {
+    /// The last record was not fully written
+    Torn,
+    /// A record is invalid and followed by other ones
+    Corrupt(String),
+}
+
+/// Check the records of `file` from `offset` to its end, `size`. Returns
+/// the end of the valid records and the damage found after them.
+fn scan(
+    file: &mut File,
+    mut offset: u64,
+    size: u64,
+) -> Result<(u64, Option<Damage>), String> {
+    seek(file, offset)?;
+    let mut reader = BufReader::new(file);
+    let mut buff = vec![];
+
+    while offset < size {
+        let mut len = [0_u8; RECORD_HEADER_SIZE as usize];
+        if size - offset < RECORD_HEADER_SIZE {
+            return Ok((offset, Some(Damage::Torn)));
+        }
+        reader.read_exact(&mut len).map_err(|e| e.to_string())?;
+
+        let len = u32::from_be_bytes(len) as u64;
+        let end = offset + record_size(len);
+        if end > size {
+            let damage = past_end(&mut reader, offset, size, len)?;
+            return Ok((offset, Some(damage)));
+        }
+        buff.resize((end - offset - RECORD_HEADER_SIZE) as usize, 0);
+        reader.read_exact(&mut buff).map_err(|e| e.to_string())?;
+
+        let (block, checksum) = buff.split_at(buff.len() - CHECKSUM_SIZE);
+        if sha256(block)[..CHECKSUM_SIZE] != *checksum {
+            let damage = if end == size {
+                Damage::Torn
+            } else {
+                Damage::Corrupt(format!(
+                    "Bad checksum for the block at offset {}",
+                    offset
+                ))
+            };
+            return Ok((offset, Some(damage)));
+        }
+        offset = end;
+    }
+    Ok((offset, None))
+}
+
+/// Damage of the record at `offset` whose length, `len`, goes past the
+/// end of the file, `size`, `reader` being right after the length. The
+/// record is torn if it is the start of a record of `len` bytes: the length
+/// matches the size of the block, once its header is written. Otherwise the
+/// length itself is damaged, and other records may follow.
+fn past_end(
+    reader: &mut impl Read,
+    offset: u64,
+    size: u64,
+    len: u64,
+) -> Result<Damage, String> {
+    if size - offset - RECORD_HEADER_SIZE < BLOCK_HEADER_SIZE as u64 {
+        return Ok(Damage::Torn);
+    }
+    let mut buff = [0_u8; BLOCK_HEADER_SIZE];
+    reader.read_exact(&mut buff).map_err(|e| e.to_string())?;
+    let header = BlockHeader::from_buff(&buff);
+    if BLOCK_HEADER_SIZE as u64 + header.transactions_size as u64 == len {
+        Ok(Damage::Torn)
+    } else {
+        Ok(Damage::Corrupt(format!(
+            "Bad length for the block at offset {}",
+            offset
+        )))
+    }
+}
+
+/// Check the records of `file` from `offset` and cut a torn record at its
+/// end. A corrupt record is an error, unless `repair` is set: the file is
+/// then cut before it. Returns the new size of the file.
+pub fn recover(
+    file: &mut File,
+    offset: u64,
+    repair: bool,
+) -> Result<u64, String> {
+    let size = match file.metadata() {
+        Ok(m) => m.len(),
+        Err(e) => {
+            return Err(format!("Failed to get blockchain file size: {}", e));
+        }
+    };
+
+    let (end, damage) = scan(file, offset, size)?;
+    match damage {
+        None => return Ok(size),
+        Some(Damage::Torn) => {
+            println!("Truncating a torn block of {} bytes", size - end)
+        }
+        Some(Damage::Corrupt(e)) if repair => {
+            println!("{}, dropping the last {} bytes", e, size - end)
+        }
+        Some(Damage::Corrupt(e)) => {
+            return Err(format!(
+                "{}, start with --repair to keep the blocks before it",
+                e
+            ));
+        }
+    }
+    truncate(file, end)?;
+    Ok(end)
+}
+
+/// Cut `file` at `size` and sync it
+pub fn truncate(file: &mut File, size: u64) -> Result<(), String> {
+    file.set_len(size).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Empty blockchain file at a path unique to the test `name`
+    fn test_file(name: &str) -> (File, std::path::PathBuf) {
+        let path = test_path(name);
+        let (file, _) = open(&path, false).unwrap();
+        (file, path)
+    }
+
+    /// Buffer of a block whose transactions take `n` bytes
+    fn block(n: u32) -> Vec<u8> {
+        let header = BlockHeader {
+            version: 1,
+            transactions_size: n,
+            ..Default::default()
+        };
+        let mut res = header.to_buffer().to_vec();
+        res.resize(BLOCK_HEADER_SIZE + n as usize, 7);
+        res
+    }
+
+    /// Path unique to the test `name`, with no file
+    fn test_path(name: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!(
+            "nexium-block-file-{}-{}",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Write the records of `blocks` to `file`, returns their offsets
+    fn write_records(file: &mut File, blocks: &[Vec<u8>]) -> Vec<u64> {
+        let mut offsets = vec![];
+        let mut offset = FILE_HEADER_SIZE;
+        for b in blocks {
+            offsets.push(offset);
+            file.write_all(&record(b)).unwrap();
+            offset += record_size(b.len() as u64);
+        }
+        offsets
+    }
+
+    /// Flip the byte at `offset` of the file at `path`
+    fn flip(path: &Path, offset: u64) {
+        let mut buff = fs::read(path).unwrap();
+        buff[offset as usize] ^= 0xff;
+        fs::write(path, buff).unwrap();
+    }
+
+    #[test]
+    fn cuts_torn_tail() {
+        let (mut file, path) = test_file("torn");
+        let offsets = write_records(&mut file, &[block(10), block(20)]);
+        let rec = record(&block(30));
+        file.write_all(&rec[..rec.len() / 2]).unwrap();
+
+        let end = offsets[1] + record_size(block(20).len() as u64);
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, false), Ok(end));
+        assert_eq!(file.metadata().unwrap().len(), end);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn cuts_missing_checksum_on_last_record() {
+        let (mut file, path) = test_file("last-checksum");
+        let offsets = write_records(&mut file, &[block(10), block(20)]);
+        let size = file.metadata().unwrap().len();
+        file.set_len(size - 1).unwrap();
+
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, false), Ok(offsets[1]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn cuts_bad_checksum_on_last_record() {
+        let (mut file, path) = test_file("last-bad");
+        let offsets = write_records(&mut file, &[block(10), block(20)]);
+        let size = file.metadata().unwrap().len();
+        flip(&path, size - 1);
+
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, false), Ok(offsets[1]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn keeps_bad_checksum_in_the_middle() {
+        let (mut file, path) = test_file("middle-bad");
+        let blocks = [block(10), block(20), block(30)];
+        let offsets = write_records(&mut file, &blocks);
+        let size = file.metadata().unwrap().len();
+        flip(&path, offsets[1] + RECORD_HEADER_SIZE + 1);
+
+        assert!(recover(&mut file, FILE_HEADER_SIZE, false).is_err());
+        assert_eq!(file.metadata().unwrap().len(), size);
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, true), Ok(offsets[1]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn keeps_bad_length_in_the_middle() {
+        let (mut file, path) = test_file("middle-length");
+        let blocks = [block(10), block(20), block(30)];
+        let offsets = write_records(&mut file, &blocks);
+        let size = file.metadata().unwrap().len();
+        // The length now goes past the end of the file
+        flip(&path, offsets[1]);
+
+        assert!(recover(&mut file, FILE_HEADER_SIZE, false).is_err());
+        assert_eq!(file.metadata().unwrap().len(), size);
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, true), Ok(offsets[1]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn upgrades_legacy_file() {
+        let path = test_path("legacy");
+        let blocks = [block(10), block(20)];
+        let mut legacy = blocks.concat();
+        // A torn third block is dropped
+        legacy.extend_from_slice(&block(30)[..50]);
+        fs::write(&path, &legacy).unwrap();
+
+        let (mut file, size) = open(&path, false).unwrap();
+        let mut offset = FILE_HEADER_SIZE;
+        for b in blocks.iter() {
+            assert_eq!(read_block(&mut file, offset).unwrap(), *b);
+            offset += record_size(b.len() as u64);
+        }
+        assert_eq!(size, offset);
+        assert_eq!(recover(&mut file, FILE_HEADER_SIZE, false), Ok(size));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sets_unknown_file_aside_in_repair_mode() {
+        let path = test_path("unknown");
+        let mut bad = path.as_os_str().to_owned();
+        bad.push(".bad");
+        let content = b"not a blockchain file at all".repeat(4);
+        fs::write(&path, &content).unwrap();
+
+        assert!(open(&path, false).is_err());
+        assert_eq!(fs::read(&path).unwrap(), content);
+
+        let (_, size) = open(&path, true).unwrap();
+        assert_eq!(size, FILE_HEADER_SIZE);
+        assert_eq!(fs::read(&bad).unwrap(), content);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&bad);
+    }
+}
@@ -0,0 +1,379 @@
+//! Index of the blocks of the main chain
+//!
+//! The index keeps, for each block of the blockchain file, its hash, its
+//! offset in the file, its height, the cumulative work of the chain up to
+//! it, its header and the ids of its transactions. The chain is loaded from
+//! it at startup instead of reading and validating every block.
+//!
+//! After a magic and a version, the file holds one record per block: the
+//! length of the entry, the entry and the first bytes of its sha256. An
+//! entry is appended once its block is written, so that a torn record or
+//! an index lagging behind the blockchain file is detected on open, and the
+//! index is then rebuilt from the blocks.
+
+use super::{
+    atomic_file,
+    block_file::{self, FILE_HEADER_SIZE},
+    difficulty::block_work,
+    structure::{
+        block_header::{BlockHeader, HeaderPreviousBlockHash},
+        consts::{BLOCK_HEADER_SIZE, HEADER_PREVIOUS_BLOCK_HASH_SIZE},
+    },
+};
+use nexium::{
+    blockchain::txid::{TxId, TXID_SIZE},
+    sha256::sha256,
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+const INDEX_MAGIC: &[u8; 4] = b"NXBI";
+const INDEX_VERSION: u8 = 2;
+const INDEX_HEADER_SIZE: usize = INDEX_MAGIC.len() + 1;
+const CHECKSUM_SIZE: usize = 4;
+/// Size of an entry without its txids
+const ENTRY_FIXED_SIZE: usize =
+    HEADER_PREVIOUS_BLOCK_HASH_SIZE + 8 + 8 + 16 + BLOCK_HEADER_SIZE + 4;
+
+/// Block of the main chain, as stored in the index
+#[derive(Clone)]
+pub struct IndexEntry {
+    pub hash: HeaderPreviousBlockHash,
+    /// Offset of the record of the block in the blockchain file
+    pub offset: u64,
+    pub height: u64,
+    /// Cumulative work of the chain up to this block
+    pub work: u128,
+    pub header: BlockHeader,
+    pub txids: Vec<TxId>,
+}
+
+impl IndexEntry {
+    /// Size of the block in the blockchain file
+    pub fn block_size(&self) -> u64 {
+        (BLOCK_HEADER_SIZE + self.header.transactions_size as usize) as u64
+    }
+
+    /// Size of the record of the block in the blockchain file
+    pub fn record_size(&self) -> u64 {
+        block_file::record_size(self.block_size())
+    }
+
+    fn to_buffer(&self) -> Vec<u8> {
+        let mut res =
+            Vec::with_capacity(ENTRY_FIXED_SIZE + self.txids.len() * TXID_SIZE);
+        res.extend_from_slice(&self.hash);
+        res.extend_from_slice(&self.offset.to_be_bytes());
+        res.extend_from_slice(&self.height.to_be_bytes());
+        res.extend_from_slice(&self.work.to_be_bytes());
+        res.extend_from_slice(&self.header.to_buffer());
+        res.extend_from_slice(&(self.txids.len() as u32).to_be_bytes());
+        for txid in self.txids.iter() {
+            res.extend_from_slice(txid.as_bytes());
+        }
+        res
+    }
+
+    fn from_buffer(buff: &[u8]) -> Result<Self, String> {
+        if buff.len() < ENTRY_FIXED_SIZE {
+            return Err("Index entry too short".to_string());
+        }
+
+        let mut pos = 0;
+        let mut take = |n: usize| {
+            let field = &buff[pos..pos + n];
+            pos += n;
+            field
+        };
+        let hash: HeaderPreviousBlockHash =
+            take(HEADER_PREVIOUS_BLOCK_HASH_SIZE).try_into().unwrap();
+        let offset = u64::from_be_bytes(take(8).try_into().unwrap());
+        let height = u64::from_be_bytes(take(8).try_into().unwrap());
+        let work = u128::from_be_bytes(take(16).try_into().unwrap());
+        let header =
+            BlockHeader::from_buff(take(BLOCK_HEADER_SIZE).try_into().unwrap());
+        let count = u32::from_be_bytes(take(4).try_into().unwrap()) as usize;
+
+        if buff.len() != ENTRY_FIXED_SIZE + count * TXID_SIZE {
+            return Err("Invalid index entry size".to_string());
+        }
+        let txids = buff[ENTRY_FIXED_SIZE..]
+            .chunks_exact(TXID_SIZE)
+            .map(|c| TxId::from_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            hash,
+            offset,
+            height,
+            work,
+            header,
+            txids,
+        })
+    }
+}
+
+pub struct BlockIndex {
+    file: File,
+    /// Position of the record of each block in the index file
+    positions: Vec<u64>,
+    /// Size of the index file
+    size: u64,
+}
+
+impl BlockIndex {
+    fn record(entry: &IndexEntry) -> Vec<u8> {
+        let payload = entry.to_buffer();
+        let mut res = Vec::with_capacity(4 + payload.len() + CHECKSUM_SIZE);
+        res.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        res.extend_from_slice(&payload);
+        res.extend_from_slice(&sha256(&payload)[..CHECKSUM_SIZE]);
+        res
+    }
+
+    /// Open the index at `path` and read its entries. The entries must
+    /// follow each other from the first block: heights, offsets, previous
+    /// hashes and work are checked.
+    pub fn open(path: &Path) -> Result<(Self, Vec<IndexEntry>), String> {
+        let buff = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) => return Err(format!("Failed to read index: {}", e)),
+        };
+        if buff.len() < INDEX_HEADER_SIZE
+            || &buff[..INDEX_MAGIC.len()] != INDEX_MAGIC
+        {
+            return Err("Not a block index".to_string());
+        }
+        if buff[INDEX_MAGIC.len()] != INDEX_VERSION {
+            return Err(format!(
+                "Unsupported index version {}",
+                buff[INDEX_MAGIC.len()]
+            ));
+        }
+
+        let mut entries: Vec<IndexEntry> = vec![];
+        let mut positions = vec![];
+        let mut pos = INDEX_HEADER_SIZE;
+        while pos < buff.len() {
+            if pos + 4 > buff.len() {
+                return Err("Truncated index record".to_string());
+            }
+            let len = u32::from_be_bytes(buff[pos..pos + 4].try_into().unwrap())
+                as usize;
+            let start = pos + 4;
+            let end = start + len;
+            if end + CHECKSUM_SIZE > buff.len() {
+                return Err("Truncated index record".to_string());
+            }
+            let payload = &buff[start..end];
+            if sha256(payload)[..CHECKSUM_SIZE]
+                != buff[end..end + CHECKSUM_SIZE]
+            {
+                return Err(format!(
+                    "Bad checksum for the index entry {}",
+                    entries.len()
+                ));
+            }
+
+            let entry = IndexEntry::from_buffer(payload)?;
+            Self::check_follows(entries.last(), &entry)?;
+            positions.push(pos as u64);
+            entries.push(entry);
+            pos = end + CHECKSUM_SIZE;
+        }
+
+        let file = match OpenOptions::new().append(true).open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Failed to open index: {}", e)),
+        };
+        let index = Self {
+            file,
+            positions,
+            size: buff.len() as u64,
+        };
+        Ok((index, entries))
+    }
+
+    /// Check that `entry` is the block following `previous` in the chain
+    fn check_follows(
+        previous: Option<&IndexEntry>,
+        entry: &IndexEntry,
+    ) -> Result<(), String> {
+        let (height, offset, hash, work) = match previous {
+            Some(p) => {
+                (p.height + 1, p.offset + p.record_size(), p.hash, p.work)
+            }
+            None => {
+                (0, FILE_HEADER_SIZE, HeaderPreviousBlockHash::default(), 0)
+            }
+        };
+        if entry.height != height
+            || entry.offset != offset
+            || entry.header.previous_block_hash != hash
+            || entry.work != work + block_work(&entry.header)
+        {
+            return Err(format!(
+                "Index entry {} does not follow the previous one",
+                height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write `entries` to a new index at `path`. The previous index is
+    /// replaced once the new one is fully written.
+    pub fn create(path: &Path, entries: &[IndexEntry]) -> Result<Self, String> {
+        let mut buff = INDEX_MAGIC.to_vec();
+        buff.push(INDEX_VERSION);
+        let mut positions = vec![];
+        for entry in entries {
+            positions.push(buff.len() as u64);
+            buff.extend(Self::record(entry));
+        }
+
+        if let Err(e) = atomic_file::write(path, &buff) {
+            return Err(format!("Failed to write index: {}", e));
+        }
+
+        match OpenOptions::new().append(true).open(path) {
+            Ok(file) => Ok(Self {
+                file,
+                positions,
+                size: buff.len() as u64,
+            }),
+            Err(e) => Err(format!("Failed to open index: {}", e)),
+        }
+    }
+
+    /// Add the entry of a block appended to the chain. The record is
+    /// written with a single write and synced.
+    pub fn append(&mut self, entry: &IndexEntry) -> Result<(), String> {
+        let record = Self::record(entry);
+        self.file.write_all(&record).map_err(|e| e.to_string())?;
+        self.file.sync_data().map_err(|e| e.to_string())?;
+        self.positions.push(self.size);
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Remove the entries from `height` to the tip
+    pub fn truncate(&mut self, height: u64) -> Result<(), String> {
+        let size = match self.positions.get(height as usize) {
+            Some(p) => *p,
+            None => return Ok(()),
+        };
+        self.file.set_len(size).map_err(|e| e.to_string())?;
+        self.file.sync_data().map_err(|e| e.to_string())?;
+        self.positions.truncate(height as usize);
+        self.size = size;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process};
+
+    /// Index path unique to the test `name`, with no file
+    fn test_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "nexium-block-index-{}-{}",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Entries of a chain of `count` blocks with `txs` transactions each
+    fn chain(count: u64, txs: u8) -> Vec<IndexEntry> {
+        let mut entries: Vec<IndexEntry> = vec![];
+        for height in 0..count {
+            let previous = entries.last();
+            let header = BlockHeader {
+                version: 1,
+                previous_block_hash: previous
+                    .map(|p| p.hash)
+                    .unwrap_or_default(),
+                difficulty_target: 1,
+                transactions_size: 100,
+                ..Default::default()
+            };
+            let entry = IndexEntry {
+                hash: sha256(&header.to_buffer()),
+                offset: previous
+                    .map(|p| p.offset + p.record_size())
+                    .unwrap_or(FILE_HEADER_SIZE),
+                height,
+                work: previous.map(|p| p.work).unwrap_or(0)
+                    + block_work(&header),
+                header,
+                txids: (0..txs)
+                    .map(|i| TxId::from_bytes([i; TXID_SIZE]))
+                    .collect(),
+            };
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn hashes(entries: &[IndexEntry]) -> Vec<HeaderPreviousBlockHash> {
+        entries.iter().map(|e| e.hash).collect()
+    }
+
+    #[test]
+    fn reopens_created_and_appended_entries() {
+        let path = test_path("round-trip");
+        let entries = chain(4, 2);
+        let mut index = BlockIndex::create(&path, &entries[..2]).unwrap();
+        index.append(&entries[2]).unwrap();
+        index.append(&entries[3]).unwrap();
+        drop(index);
+
+        let (mut index, read) = BlockIndex::open(&path).unwrap();
+        assert_eq!(hashes(&read), hashes(&entries));
+        assert!(read.iter().zip(entries.iter()).all(|(r, e)| {
+            r.offset == e.offset
+                && r.work == e.work
+                && r.header == e.header
+                && r.txids == e.txids
+        }));
+
+        index.truncate(1).unwrap();
+        drop(index);
+        let (_, read) = BlockIndex::open(&path).unwrap();
+        assert_eq!(hashes(&read), hashes(&entries[..1]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_an_entry_not_following_the_previous_one() {
+        let path = test_path("not-following");
+        let mut entries = chain(3, 1);
+        entries[2].header.previous_block_hash = entries[0].hash;
+        BlockIndex::create(&path, &entries).unwrap();
+        assert!(BlockIndex::open(&path).is_err());
+
+        let mut entries = chain(3, 1);
+        entries[1].offset += 1;
+        BlockIndex::create(&path, &entries).unwrap();
+        assert!(BlockIndex::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let path = test_path("checksum");
+        BlockIndex::create(&path, &chain(2, 1)).unwrap();
+        let mut buff = fs::read(&path).unwrap();
+        let last = buff.len() - 1;
+        buff[last] ^= 0xff;
+        fs::write(&path, &buff).unwrap();
+        assert!(BlockIndex::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,35 +1,128 @@
 use super::{
-    mempool::Mempool,
+    account::Account,
+    account_store::{AccountStore, CHECKPOINT_INTERVAL},
+    block_file::{self, FILE_HEADER_SIZE},
+    block_index::{BlockIndex, IndexEntry},
+    cache::cache::Cache,
+    history::{HistoryPage, HistoryQuery},
+    journal::Journal,
+    mempool::{Mempool, MempoolError, MAX_MEMPOOL_SIZE, MEMPOOL_EXPIRY},
+    side_chain::{SideChain, MAX_SIDE_BLOCKS},
+    staging::StagedBlocks,
     structure::{
         block::Block,
         block_header::{BlockHeader, HeaderPreviousBlockHash},
         consts::BLOCK_HEADER_SIZE,
     },
+    difficulty::{block_work, DifficultyWindow},
+    reward::max_reward,
+    validator::{check_proof_of_work, BlockValidationError, BlockValidator},
 };
-use crate::peers::PeerList;
+use crate::config::BlockProduction;
+use colored::Colorize;
 use nexium::{
     blockchain::{
-        consts::TRANSACTION_RECEIVER, data_type::DataType,
-        transaction::Transaction, transaction_data::TransactionData,
+        amount::Amount,
+        consts::COINBASE_TRANSACTION_SIZE,
+        transaction::Transaction,
+        transaction_data::TransactionData,
+        txid::TxId,
     },
-    defaults::{BLOCKCHAIN_FILE, INITIAL_BALANCE},
-    gitlab::GitlabClient,
+    defaults::{
+        ACCOUNTS_FILE, BLOCKCHAIN_FILE, BLOCK_INDEX_FILE, BLOCK_VERSION,
+        RETARGET_INTERVAL,
+    },
+    rsa::{signature::SignatureScheme, KeyPair},
+    utils::time::current_time,
 };
 use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{Read, Seek, SeekFrom, Write},
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Notify};
+
+/// What became of a block received from a peer
+pub enum BlockOutcome {
+    /// The block is already known
+    Known,
+    /// The block extended the main chain, with the given number of blocks
+    /// that were waiting for it
+    Connected(u64),
+    /// The main chain switched to the branch of the block
+    Reorganised { disconnected: u64, connected: u64 },
+    /// The block is kept on a branch with less work than the main chain
+    SideChain,
+    /// The block is kept until its parent is received
+    Orphan,
+}
+
+#[derive(Debug)]
+pub enum SubmitError {
+    /// The block, or a block of its branch, breaks a consensus rule
+    Invalid(BlockValidationError),
+    /// The blockchain file could not be updated
+    Storage(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::Storage(e) => write!(f, "Failed to update blockchain: {}", e),
+        }
+    }
+}
+
+/// Why a transaction of the mempool is left out of a block template
+enum TemplateSkip {
+    /// The transaction breaks a rule for good, it is dropped
+    Invalid,
+    /// The transaction may go in a later block
+    Later,
+}
+
+/// Position of a transaction in the chain
+#[derive(Debug, Clone, Copy)]
+pub struct TxLocation {
+    pub block_hash: HeaderPreviousBlockHash,
+    pub height: u64,
+    /// Index of the transaction in its block
+    pub index: usize,
+}
 
 pub struct Blockchain {
     pub cache: HashMap<HeaderPreviousBlockHash, u64>,
+    /// Hash of each block, by height
+    pub hashes: Vec<HeaderPreviousBlockHash>,
+    heights: HashMap<HeaderPreviousBlockHash, u64>,
+    /// Cumulative work of the chain up to each height
+    works: Vec<u128>,
+    /// Difficulty window of the main chain
+    pub window: DifficultyWindow,
+    side: SideChain,
+    pub tx_index: HashMap<TxId, TxLocation>,
     file: File,
+    /// Block index, `None` while the chain is loaded
+    index: Option<BlockIndex>,
+    /// State of the accounts at the tip
+    accounts: AccountStore,
+    /// Number of blocks the saved state of the accounts was saved for,
+    /// `None` if it is not on the chain
+    accounts_saved: Option<u64>,
     pub last_hash: HeaderPreviousBlockHash,
+    pub last_header: Option<BlockHeader>,
     mempool: Mempool,
     pub size: u64,
-    gitlab: GitlabClient,
+    /// Hash of the tip, watched by the miner to drop stale templates
+    tip: watch::Sender<HeaderPreviousBlockHash>,
+    /// Notified when a client transaction is added to the mempool
+    pending: Arc<Notify>,
 }
 
 impl Blockchain {
@@ -43,274 +136,966 @@ impl Blockchain {
     //     );
     // }
 
-    pub fn init(gitlab: GitlabClient) -> Result<Self, String> {
-        let r = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(BLOCKCHAIN_FILE);
-
-        let file = match r {
-            Ok(f) => f,
-            Err(_) => {
-                return Err(format!(
-                    "Failed to open blockchain file: {}",
-                    BLOCKCHAIN_FILE
-                ));
-            }
+    /// Add the transactions `txids` of the block `hash` at `height` to
+    /// `tx_index`
+    fn index_transactions(
+        tx_index: &mut HashMap<TxId, TxLocation>,
+        txids: &[TxId],
+        block_hash: HeaderPreviousBlockHash,
+        height: u64,
+    ) {
+        for (index, txid) in txids.iter().enumerate() {
+            let location = TxLocation {
+                block_hash,
+                height,
+                index,
+            };
+            tx_index.insert(*txid, location);
+        }
+    }
+
+    /// Index the block written at `offset` as the new tip, returns its entry
+    /// of the block index. A block that can't be applied to the accounts
+    /// leaves them out of step with the chain, they are then rebuilt.
+    fn push_block(
+        &mut self,
+        block: &Block,
+        hash: HeaderPreviousBlockHash,
+        offset: u64,
+    ) -> Result<IndexEntry, String> {
+        let entry = IndexEntry {
+            hash,
+            offset,
+            height: self.hashes.len() as u64,
+            work: self.chain_work() + block_work(&block.header),
+            header: block.header,
+            txids: block.transactions.iter().map(|t| t.txid()).collect(),
         };
+        self.push_entry(&entry);
+        if let Err(e) = self.accounts.apply(block, entry.height) {
+            eprintln!("Failed to update accounts: {}", e);
+            self.rebuild_accounts()?;
+        }
+        Ok(entry)
+    }
 
-        // read the file and load the blocks into the cache
+    /// Add the block of `entry` to the chain as the new tip
+    fn push_entry(&mut self, entry: &IndexEntry) {
+        let hash = entry.hash;
+        Self::index_transactions(
+            &mut self.tx_index,
+            &entry.txids,
+            hash,
+            entry.height,
+        );
+        self.cache.insert(hash, entry.offset);
+        self.heights.insert(hash, entry.height);
+        self.hashes.push(hash);
+        self.works.push(entry.work);
+        self.window.push(&entry.header);
+        self.last_hash = hash;
+        self.last_header = Some(entry.header);
+        self.size = entry.offset + entry.record_size();
+        self.tip.send_replace(hash);
+    }
 
-        let mut b = Self {
-            cache: HashMap::new(),
-            file,
-            last_hash: HeaderPreviousBlockHash::default(),
-            mempool: Mempool::new(),
-            size: 0,
-            gitlab,
+    /// Validate the next block of a chain being loaded, `ledger` holds the
+    /// state of the accounts touched by the previous blocks
+    async fn validate_next(
+        validator: &mut BlockValidator<'_>,
+        ledger: &mut HashMap<String, Account>,
+        block: &Block,
+        height: usize,
+    ) -> Result<(), String> {
+        let initial = Account::initial()?;
+        let accounts = match validator
+            .validate(block, |login| {
+                Ok(ledger.get(login).copied().unwrap_or(initial))
+            })
+            .await
+        {
+            Ok(a) => a,
+            Err(e) => return Err(format!("Invalid block {}: {}", height, e)),
         };
+        ledger.extend(accounts);
+        Ok(())
+    }
 
-        let blockchain_size = match b.file.metadata() {
-            Ok(m) => m.len(),
-            Err(_) => {
-                return Err(format!(
-                    "Failed to get blockchain file size: {}",
-                    BLOCKCHAIN_FILE
-                ));
-            }
+    /// Size of the blockchain file covered by the entries of the block
+    /// index, once checked that the last block is stored where the index
+    /// says
+    fn indexed_size(
+        file: &mut File,
+        entries: &[IndexEntry],
+    ) -> Result<u64, String> {
+        let last = match entries.last() {
+            Some(e) => e,
+            None => return Ok(FILE_HEADER_SIZE),
         };
 
-        if blockchain_size == 0 {
-            // Empty blockchain
+        let block = Self::read_block_from(file, last.offset)?;
+        if block.double_hash() != last.hash {
+            return Err("Last block does not match the index".to_string());
         }
+        Ok(last.offset + last.record_size())
+    }
 
-        loop {
-            if b.size >= blockchain_size {
-                break;
-            }
+    /// Read and validate every block of the blockchain file, returns the
+    /// entries of the block index. The damaged records were already cut. A
+    /// block breaking a consensus rule is an error, unless in `repair` mode:
+    /// the file is then cut before it, keeping the blocks before it.
+    async fn load_blocks(
+        &mut self,
+        file_size: u64,
+        cache: &mut Cache,
+        repair: bool,
+    ) -> Result<Vec<IndexEntry>, String> {
+        let mut validator = BlockValidator::new(
+            cache,
+            HeaderPreviousBlockHash::default(),
+            None,
+            DifficultyWindow::default(),
+        )
+        .without_signatures();
+        let mut ledger = HashMap::new();
+        let mut entries = vec![];
 
-            let block = match b.read_block(b.size) {
+        while self.size < file_size {
+            let offset = self.size;
+            let checked = match self.read_block(offset) {
+                Ok(block) => Self::validate_next(
+                    &mut validator,
+                    &mut ledger,
+                    &block,
+                    self.cache.len(),
+                )
+                .await
+                .map(|_| block),
+                Err(e) => Err(format!("Failed to read blockchain file: {}", e)),
+            };
+            let block = match checked {
                 Ok(b) => b,
+                Err(e) if repair => {
+                    println!(
+                        "{}, dropping the last {} bytes",
+                        e,
+                        file_size - offset
+                    );
+                    block_file::truncate(&mut self.file, offset)?;
+                    break;
+                }
                 Err(e) => {
                     return Err(format!(
-                        "Failed to read blockchain file: {}",
+                        "{}, start with --repair to keep the blocks before it",
                         e
-                    ));
+                    ))
                 }
             };
+            entries.push(self.push_block(
+                &block,
+                block.double_hash(),
+                offset,
+            )?);
+        }
+        Ok(entries)
+    }
+
+    /// Load the chain of the blockchain file. In `repair` mode, every record
+    /// and every block of the file is checked again and the file is cut
+    /// before the first damaged or invalid one.
+    pub async fn init(
+        cache: &mut Cache,
+        repair: bool,
+    ) -> Result<Self, String> {
+        let (mut file, _) =
+            block_file::open(Path::new(BLOCKCHAIN_FILE), repair)?;
 
-            match block.header.previous_block_hash {
-                x if x == b.last_hash => {}
-                _ => {
-                    return Err("Invalid previous block hash".to_string());
+        let index_path = Path::new(BLOCK_INDEX_FILE);
+        let mut indexed = match BlockIndex::open(index_path) {
+            Ok(_) if repair => None,
+            Ok((index, entries)) => {
+                match Self::indexed_size(&mut file, &entries) {
+                    Ok(size) => Some((index, entries, size)),
+                    Err(e) => {
+                        println!("Block index is stale: {}", e);
+                        None
+                    }
                 }
             }
+            Err(e) if index_path.exists() => {
+                println!("Block index is corrupt: {}", e);
+                None
+            }
+            Err(_) => None,
+        };
 
-            // dbg!(&block);
-            b.last_hash = block.double_hash();
-            // dbg!(b.last_hash);
-            b.cache.insert(b.last_hash, b.size);
-            b.size += BLOCK_HEADER_SIZE as u64
-                + block.header.transactions_size as u64;
-            // dbg!(b.size, blockchain_size, block.size());
+        // The blocks of the index were checked when they were written, only
+        // the records after them are checked, and a torn one is cut
+        let start = match &indexed {
+            Some((_, _, size)) => *size,
+            None => FILE_HEADER_SIZE,
+        };
+        let blockchain_size = block_file::recover(&mut file, start, repair)?;
+
+        // The index is only trusted if it covers the whole blockchain file
+        if indexed
+            .as_ref()
+            .is_some_and(|(_, _, size)| *size != blockchain_size)
+        {
+            println!(
+                "Block index is stale: it does not cover the blockchain file"
+            );
+            indexed = None;
         }
 
-        // dbg!(&b.cache);
-        // dbg!(&b.cache.len());
+        let mut b = Self {
+            cache: HashMap::new(),
+            hashes: vec![],
+            heights: HashMap::new(),
+            works: vec![],
+            window: DifficultyWindow::default(),
+            side: SideChain::new(),
+            tx_index: HashMap::new(),
+            file,
+            index: None,
+            accounts: AccountStore::new()?,
+            accounts_saved: None,
+            last_hash: HeaderPreviousBlockHash::default(),
+            last_header: None,
+            mempool: Mempool::new(),
+            size: FILE_HEADER_SIZE,
+            tip: watch::Sender::new(HeaderPreviousBlockHash::default()),
+            pending: Arc::new(Notify::new()),
+        };
+
+        let index = match indexed {
+            Some((index, entries, _)) => {
+                for entry in entries.iter() {
+                    b.push_entry(entry);
+                }
+                b.load_accounts()?;
+                index
+            }
+            None => {
+                if blockchain_size > FILE_HEADER_SIZE {
+                    println!("Rebuilding the block index...");
+                }
+                let entries =
+                    b.load_blocks(blockchain_size, cache, repair).await?;
+                BlockIndex::create(index_path, &entries)?
+            }
+        };
+        b.index = Some(index);
+        b.save_accounts();
+
         Ok(b)
     }
 
-    pub fn append(&mut self, block: &Block) {
-        let buff = block.to_buffer();
-        match self.file.write_all(&buff) {
+    /// Load the saved state of the accounts and apply the blocks after it,
+    /// or rebuild it from the blocks if it is not on the chain
+    fn load_accounts(&mut self) -> Result<(), String> {
+        let path = Path::new(ACCOUNTS_FILE);
+        match AccountStore::load(path) {
+            Ok((accounts, count, tip))
+                if self.count_to(&tip) == Some(count) =>
+            {
+                self.accounts = accounts;
+                match self.apply_blocks_from(count) {
+                    Ok(_) => {
+                        self.accounts_saved = Some(count);
+                        return Ok(());
+                    }
+                    Err(e) => println!("Account store is stale: {}", e),
+                }
+            }
             Ok(_) => {
-                // Flush to disk
-                let _ = self.file.sync_all();
-                self.last_hash = Block::double_hash_(&buff);
-                self.cache.insert(self.last_hash, self.size);
-                self.size += buff.len() as u64;
+                println!("Account store is stale: its tip is not on the chain")
             }
-            Err(e) => {
-                eprintln!("Failed to write block to file: {}", e);
+            Err(e) if path.exists() => {
+                println!("Account store is stale: {}", e);
             }
+            Err(_) => {}
+        }
+        self.rebuild_accounts()
+    }
+
+    /// Apply the blocks of the chain from `height` to the accounts
+    fn apply_blocks_from(&mut self, height: u64) -> Result<(), String> {
+        for h in height..self.hashes.len() as u64 {
+            let block = self.block_at(h)?;
+            self.accounts.apply(&block, h)?;
         }
+        Ok(())
+    }
+
+    /// Rebuild the state of the accounts from the blocks of the chain
+    fn rebuild_accounts(&mut self) -> Result<(), String> {
+        let count = self.hashes.len() as u64;
+        if count > 0 {
+            println!("Rebuilding the account store...");
+        }
+        let mut accounts = AccountStore::new()?;
+        let mut height = 0;
+        self.block_foreach_until(count, |b| {
+            accounts.apply(b, height)?;
+            height += 1;
+            Ok(())
+        })?;
+        self.accounts = accounts;
+        Ok(())
+    }
+
+    /// Save the state of the accounts every CHECKPOINT_INTERVAL blocks, or
+    /// at once if the saved state is no longer on the chain. A failed save
+    /// is tried again on the next block.
+    fn save_accounts(&mut self) {
+        let count = self.hashes.len() as u64;
+        if self
+            .accounts_saved
+            .is_some_and(|saved| count < saved + CHECKPOINT_INTERVAL)
+        {
+            return;
+        }
+
+        let path = Path::new(ACCOUNTS_FILE);
+        match self.accounts.save(path, &self.last_hash, count) {
+            Ok(_) => self.accounts_saved = Some(count),
+            Err(e) => eprintln!("Failed to save accounts: {}", e),
+        }
+    }
+
+    pub fn append(&mut self, block: &Block) -> Result<(), String> {
+        let buff = block.to_buffer();
+        // A record left by a failed write that could not be cut
+        if self.file.metadata().map(|m| m.len()).ok() != Some(self.size) {
+            block_file::truncate(&mut self.file, self.size)?;
+        }
+
+        // A crash during the write leaves a torn record, cut on next start.
+        // A failed write or sync is cut at once, so that the file ends with
+        // the last block of the chain.
+        let written = self
+            .file
+            .write_all(&block_file::record(&buff))
+            .and_then(|_| self.file.sync_all());
+        if let Err(e) = written {
+            let _ = block_file::truncate(&mut self.file, self.size);
+            return Err(format!("Failed to write block to file: {}", e));
+        }
+        let offset = self.size;
+        let entry =
+            self.push_block(block, Block::double_hash_(&buff), offset)?;
+        // A failed update leaves a stale index, rebuilt on next start
+        if let Some(index) = self.index.as_mut() {
+            if let Err(e) = index.append(&entry) {
+                eprintln!("Failed to update block index: {}", e);
+            }
+        }
+        self.save_accounts();
+        Ok(())
     }
 
     /// Append a block received from peer sync, also clears matching transactions from mempool
-    pub fn append_synced_block(&mut self, block: &Block) {
+    pub fn append_synced_block(&mut self, block: &Block) -> Result<(), String> {
         // Remove transactions that are in the block from our mempool
         self.mempool.remove_transactions(&block.transactions);
         // Append the block
-        self.append(block);
+        self.append(block)
+    }
+
+    /// Put back the `disconnected` blocks after the first `count` ones,
+    /// when a switch to another branch failed
+    pub fn restore(
+        &mut self,
+        count: u64,
+        disconnected: &mut StagedBlocks,
+    ) -> Result<(), String> {
+        self.truncate(count)?;
+        for i in 0..disconnected.len() {
+            let block = disconnected.get(i)?;
+            self.append_synced_block(&block)?;
+        }
+        Ok(())
     }
 
     /// Get blockchain info for synchronization
     pub fn get_info(&self) -> crate::peers::BlockchainInfo {
         crate::peers::BlockchainInfo {
             block_count: self.cache.len() as u64,
+            chain_work: self.chain_work().to_string(),
             size: self.size,
             last_hash: hex::encode(&self.last_hash),
         }
     }
 
-    /// Read the entire blockchain file as bytes
-    pub fn read_all(&mut self) -> Result<Vec<u8>, String> {
-        self.file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
-        let mut data = vec![0u8; self.size as usize];
-        self.file.read_exact(&mut data).map_err(|e| e.to_string())?;
-        Ok(data)
-    }
-
-    /// Replace the entire blockchain with downloaded data
-    pub fn replace_from_data(&mut self, data: &[u8]) -> Result<(), String> {
-        // Open file with truncate to overwrite
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .open(BLOCKCHAIN_FILE)
-            .map_err(|e| e.to_string())?;
-        
-        file.write_all(data).map_err(|e| e.to_string())?;
-        file.sync_all().map_err(|e| e.to_string())?;
-        
-        // Reopen for append
-        self.file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(BLOCKCHAIN_FILE)
-            .map_err(|e| e.to_string())?;
-        
-        // Rebuild cache
-        self.cache.clear();
-        self.last_hash = HeaderPreviousBlockHash::default();
-        self.size = 0;
-        
-        let blockchain_size = data.len() as u64;
-        
-        loop {
-            if self.size >= blockchain_size {
-                break;
+    /// Hash of the last block, `None` for an empty chain
+    pub fn tip(&self) -> Option<HeaderPreviousBlockHash> {
+        self.hashes.last().copied()
+    }
+
+    /// Height of the block `hash`, if it is in the chain
+    pub fn height_of(&self, hash: &HeaderPreviousBlockHash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Hash and header of the block preceding the block at `height`, the
+    /// state a validator starts from to validate a block at `height`
+    pub fn block_before(
+        &mut self,
+        height: u64,
+    ) -> Result<(HeaderPreviousBlockHash, Option<BlockHeader>), String> {
+        if height == 0 {
+            return Ok((HeaderPreviousBlockHash::default(), None));
+        }
+        let header = self.header_at(height - 1)?;
+        Ok((self.hashes[height as usize - 1], Some(header)))
+    }
+
+    /// Difficulty window of the chain made of the first `count` blocks
+    pub fn window_at(
+        &mut self,
+        count: u64,
+    ) -> Result<DifficultyWindow, String> {
+        let start = count.saturating_sub(RETARGET_INTERVAL);
+        let mut window = DifficultyWindow::new(start);
+        for height in start..count {
+            window.push(&self.header_at(height)?);
+        }
+        Ok(window)
+    }
+
+    /// Remove the blocks from `height` to the tip
+    pub fn truncate(&mut self, height: u64) -> Result<(), String> {
+        let height = height as usize;
+        if height >= self.hashes.len() {
+            return Ok(());
+        }
+
+        let offset = self.cache[&self.hashes[height]];
+        let (last_hash, last_header) = self.block_before(height as u64)?;
+        let window = self.window_at(height as u64)?;
+        // The blocks are undone from the tip, the accounts are rebuilt once
+        // the chain is cut if one of them can't be
+        let end = self.hashes.len();
+        let reverted = (height..end).rev().try_for_each(|h| {
+            let block = self.block_at(h as u64)?;
+            self.accounts.revert(&block, h as u64)
+        });
+        self.file.set_len(offset).map_err(|e| e.to_string())?;
+        self.file.sync_all().map_err(|e| e.to_string())?;
+        if let Some(index) = self.index.as_mut() {
+            index.truncate(height as u64)?;
+        }
+
+        for hash in self.hashes.drain(height..) {
+            self.cache.remove(&hash);
+            self.heights.remove(&hash);
+        }
+        self.works.truncate(height);
+        self.window = window;
+        self.tx_index.retain(|_, l| l.height < height as u64);
+        self.last_hash = last_hash;
+        self.last_header = last_header;
+        self.size = offset;
+        self.tip.send_replace(last_hash);
+        // The saved state of the accounts may be after the cut
+        if self.accounts_saved.is_some_and(|saved| saved > height as u64) {
+            self.accounts_saved = None;
+        }
+        if let Err(e) = reverted {
+            eprintln!("Failed to undo blocks in the accounts: {}", e);
+            self.rebuild_accounts()?;
+        }
+        self.save_accounts();
+        Ok(())
+    }
+
+    /// Cumulative work of the main chain
+    pub fn chain_work(&self) -> u128 {
+        self.work_at(self.hashes.len() as u64)
+    }
+
+    /// Cumulative work of the first `count` blocks
+    pub fn work_at(&self, count: u64) -> u128 {
+        match count {
+            0 => 0,
+            n => self.works[n as usize - 1],
+        }
+    }
+
+    /// Number of blocks of the main chain up to the block `hash`, or `None`
+    /// if the block is not on the main chain
+    fn count_to(&self, hash: &HeaderPreviousBlockHash) -> Option<u64> {
+        if *hash == HeaderPreviousBlockHash::default() {
+            return Some(0);
+        }
+        self.height_of(hash).map(|h| h + 1)
+    }
+
+    /// Remove the blocks from `count` to the tip and return them, staged
+    /// in a file
+    pub fn disconnect(&mut self, count: u64) -> Result<StagedBlocks, String> {
+        let path = format!("{}.disconnected", BLOCKCHAIN_FILE);
+        let mut blocks = StagedBlocks::create(PathBuf::from(path))?;
+        for height in count..self.hashes.len() as u64 {
+            let offset = self.cache[&self.hashes[height as usize]];
+            blocks.push(&block_file::read_block(&mut self.file, offset)?)?;
+        }
+        self.truncate(count)?;
+        Ok(blocks)
+    }
+
+    /// Finish a switch of the main chain to a branch of `connected` blocks
+    /// built on the first `count` blocks. The last `disconnected` blocks
+    /// are kept to switch back if their branch gets more work, and their
+    /// transactions missing from the new chain go back to the mempool, as
+    /// many as it can hold.
+    pub fn reorganised(
+        &mut self,
+        count: u64,
+        mut disconnected: StagedBlocks,
+        connected: u64,
+    ) {
+        if disconnected.is_empty() {
+            return;
+        }
+
+        println!(
+            "{} Chain reorganised at height {}: {} block(s) disconnected, {} connected",
+            "REORG".magenta().bold(),
+            count,
+            disconnected.len(),
+            connected
+        );
+
+        let n = disconnected.len();
+        let now = current_time();
+        let mut transactions: Vec<(Transaction, u32)> = vec![];
+        let mut size = 0;
+        for i in 0..n {
+            let block = match disconnected.get(i) {
+                Ok(b) => b,
+                Err(e) => {
+                    eprintln!("Failed to read a disconnected block: {}", e);
+                    break;
+                }
+            };
+            for tr in block.transactions.iter() {
+                let tr_size = tr.size() as usize;
+                if tr.is_coinbase()
+                    || self.tx_index.contains_key(&tr.txid())
+                    || size + tr_size > MAX_MEMPOOL_SIZE
+                {
+                    continue;
+                }
+                size += tr_size;
+                transactions.push((tr.clone(), now));
+            }
+            if i + MAX_SIDE_BLOCKS as u64 >= n {
+                self.side.insert(block.double_hash(), block);
             }
+        }
 
-            let block = self.read_block(self.size)?;
+        // The waiting transactions of the same emitters follow the
+        // disconnected ones: they are taken out of the mempool and added
+        // back with them, in nonce order
+        let logins: HashSet<String> = transactions
+            .iter()
+            .map(|(t, _)| t.header.get_login())
+            .collect();
+        for login in logins.iter() {
+            transactions.extend(self.mempool.take_pending(login));
+        }
+        transactions
+            .sort_by_key(|(t, _)| (t.header.get_login(), t.header.nonce));
 
-            if block.header.previous_block_hash != self.last_hash {
-                return Err("Invalid blockchain data: hash mismatch".to_string());
+        for (tr, arrival) in transactions {
+            // Transactions replaced by the new chain are dropped, with the
+            // later ones of their emitter
+            let txid = tr.txid();
+            let added = self
+                .check_new_transaction(&tr)
+                .and_then(|_| self.mempool.add_at(tr, arrival));
+            if let Err(e) = added {
+                println!(
+                    "{} Dropped pending transaction {}: {}",
+                    "MEMPOOL".yellow().bold(),
+                    txid,
+                    e
+                );
             }
+        }
+        self.pending.notify_one();
+    }
 
-            self.last_hash = block.double_hash();
-            self.cache.insert(self.last_hash, self.size);
-            self.size += BLOCK_HEADER_SIZE as u64 + block.header.transactions_size as u64;
+    /// Validate `block` and append it to the main chain
+    async fn connect(
+        &mut self,
+        block: &Block,
+        cache: &mut Cache,
+    ) -> Result<(), SubmitError> {
+        let mut validator = BlockValidator::new(
+            cache,
+            self.last_hash,
+            self.last_header,
+            self.window.clone(),
+        );
+        if let Err(e) = validator
+            .validate(block, |login| self.get_account(login))
+            .await
+        {
+            return Err(SubmitError::Invalid(e));
+        }
+        if let Err(e) = self.append_synced_block(block) {
+            return Err(SubmitError::Storage(e));
         }
-        
         Ok(())
     }
 
-    async fn create_new_block(&mut self, peer_list: Arc<Mutex<PeerList>>, self_address: String, self_port: u16) {
-        let mut transactions = self.mempool.dump();
-        transactions
-            .sort_by(|a, b| a.header.timestamp.cmp(&b.header.timestamp));
+    /// Make the `branch` of side blocks built on the first `count` blocks
+    /// the main chain. If a block of the branch is invalid, it and its
+    /// descendants are dropped and the previous main chain is restored. A
+    /// block that could not be checked is kept to be checked again later.
+    /// Returns the number of blocks disconnected.
+    async fn switch_to(
+        &mut self,
+        count: u64,
+        branch: Vec<Block>,
+        cache: &mut Cache,
+    ) -> Result<u64, SubmitError> {
+        let mut disconnected = match self.disconnect(count) {
+            Ok(b) => b,
+            Err(e) => return Err(SubmitError::Storage(e)),
+        };
 
-        let mut balances: HashMap<String, f32> = HashMap::new();
-        let mut valid_trs: Vec<Transaction> = vec![];
+        let mut connected: Vec<Block> = vec![];
+        for block in branch {
+            let hash = block.double_hash();
+            self.side.remove(&hash);
 
-        for tr in transactions.iter() {
-            match tr.get_data() {
-                Ok(data) => match data {
-                    TransactionData::ClassicTransaction {
-                        receiver,
-                        amount,
-                        ..
-                    } => {
-                        if amount <= 0 as f32 {
-                            continue; // Invalid transaction amount
-                        }
-
-                        let em = tr.header.get_login();
-                        let r = String::from_utf8_lossy(&receiver)
-                            .trim_end_matches('\0')
-                            .to_string();
-
-                        if em == r {
-                            continue; // Cannot send money to yourself
-                        }
-
-                        match self.gitlab.check_user_existence_async(&r).await {
-                            Ok(exists) => {
-                                if !exists {
-                                    continue; // Receiver does not exist
-                                }
-                            }
-                            Err(_) => {
-                                continue; // Error checking user existence
-                            }
-                        }
-
-                        let mut be = match balances.get(&em) {
-                            Some(b) => *b,
-                            None => match self.get_user_balance(&em) {
-                                Ok(b) => b,
-                                Err(_) => {
-                                    continue; // Error getting emitter balance
-                                }
-                            },
-                        };
-
-                        let mut br = match balances.get(&r) {
-                            Some(b) => *b,
-                            None => match self.get_user_balance(&r) {
-                                Ok(b) => b,
-                                Err(_) => {
-                                    continue; // Error getting receiver balance
-                                }
-                            },
-                        };
-
-                        // Calculate total cost: amount + transaction fees
-                        let fee_cost = tr.fee_cost();
-                        let total_cost = amount + fee_cost;
-
-                        if (be as i64 - total_cost as i64) < 0 {
-                            continue; // Insufficient balance (including fees)
-                        } else {
-                            be -= total_cost; // Deduct amount + fees from sender
-                            br += amount; // Only the amount goes to receiver (fees are "burned")
-                            balances.insert(em, be);
-                            balances.insert(r.to_string(), br);
-                            valid_trs.push(tr.clone()); // Valid transaction
-                        }
+            if let Err(e) = self.connect(&block, cache).await {
+                match &e {
+                    SubmitError::Invalid(e) if !e.is_retryable() => {
+                        self.side.remove_descendants(&hash);
                     }
-                    _ => valid_trs.push(tr.clone()), // Other transaction types are considered valid
-                },
-                Err(_) => continue, // Error getting transaction data
+                    _ => self.side.insert(hash, block),
+                }
+                for block in connected {
+                    self.side.insert(block.double_hash(), block);
+                }
+                if let Err(e) = self.restore(count, &mut disconnected) {
+                    return Err(SubmitError::Storage(e));
+                }
+                return Err(e);
             }
+            connected.push(block);
+        }
+
+        let n = disconnected.len();
+        self.reorganised(count, disconnected, connected.len() as u64);
+        Ok(n)
+    }
+
+    /// Handle a block received from a peer. The block is kept off the main
+    /// chain until its branch has more work than the main chain, which
+    /// then switches to it.
+    pub async fn submit_block(
+        &mut self,
+        block: Block,
+        cache: &mut Cache,
+    ) -> Result<BlockOutcome, SubmitError> {
+        let hash = block.double_hash();
+        if self.heights.contains_key(&hash) || self.side.contains(&hash) {
+            return Ok(BlockOutcome::Known);
+        }
+        if let Err(e) = check_proof_of_work(&block.header, &hash) {
+            return Err(SubmitError::Invalid(e));
+        }
+        self.side.insert(hash, block);
+
+        // Best branch going through the block, including the blocks that
+        // were waiting for it
+        let (tip, _) = self.side.best_tip(&hash);
+        let branch = self.side.branch(&tip);
+        let parent = match self.side.get(&branch[0]) {
+            Some(b) => b.header.previous_block_hash,
+            None => return Ok(BlockOutcome::Orphan),
+        };
+        let count = match self.count_to(&parent) {
+            Some(c) => c,
+            None => return Ok(BlockOutcome::Orphan),
+        };
+
+        // The main chain is only touched once every block of the branch is
+        // at hand
+        let branch: Option<Vec<Block>> =
+            branch.iter().map(|h| self.side.get(h).cloned()).collect();
+        let branch = match branch {
+            Some(b) => b,
+            None => return Ok(BlockOutcome::Orphan),
+        };
+
+        let branch_work: u128 =
+            branch.iter().map(|b| block_work(&b.header)).sum();
+        if branch_work <= self.chain_work() - self.work_at(count) {
+            return Ok(BlockOutcome::SideChain);
+        }
+
+        let disconnected = self.switch_to(count, branch, cache).await?;
+        let connected = self.hashes.len() as u64 - count;
+        if disconnected == 0 {
+            Ok(BlockOutcome::Connected(connected - 1))
+        } else {
+            Ok(BlockOutcome::Reorganised {
+                disconnected,
+                connected,
+            })
+        }
+    }
+
+    /// Receiver notified whenever the tip of the main chain changes
+    pub fn watch_tip(&self) -> watch::Receiver<HeaderPreviousBlockHash> {
+        self.tip.subscribe()
+    }
+
+    /// Notified when a client transaction is added to the mempool
+    pub fn pending(&self) -> Arc<Notify> {
+        self.pending.clone()
+    }
+
+    /// Time to wait before mining a block with the settings of
+    /// `production`: none once the mempool fills a block or the block
+    /// interval passed since the tip, `None` if the mempool is empty
+    pub fn next_block_in(
+        &self,
+        production: &BlockProduction,
+    ) -> Option<Duration> {
+        if self.mempool.is_empty() {
+            return None;
+        }
+
+        let size = BLOCK_HEADER_SIZE + self.mempool.size();
+        if self.mempool.len() >= production.max_transactions
+            || size >= production.max_block_size
+        {
+            return Some(Duration::ZERO);
+        }
+
+        let last = match self.last_header {
+            Some(h) => h.timestamp,
+            None => return Some(Duration::ZERO),
+        };
+        let elapsed = current_time().saturating_sub(last) as u64;
+        Some(
+            production
+                .block_interval
+                .saturating_sub(Duration::from_secs(elapsed)),
+        )
+    }
+
+    /// Apply `tr` to `accounts`, the state of the accounts touched by the
+    /// transactions already in the block template. Only the consensus rules
+    /// are checked, a receiver missing from GitLab is accepted as by the
+    /// validator.
+    fn apply_to_template(
+        &mut self,
+        accounts: &mut HashMap<String, Account>,
+        tr: &Transaction,
+    ) -> Result<(), TemplateSkip> {
+        let em = tr.header.get_login();
+        let mut ae = match accounts.get(&em) {
+            Some(a) => *a,
+            None => match self.get_account(&em) {
+                Ok(a) => a,
+                Err(_) => return Err(TemplateSkip::Later),
+            },
+        };
+
+        if !tr.header.has_nonce() || tr.header.nonce < ae.nonce {
+            return Err(TemplateSkip::Invalid); // Replayed transaction
+        }
+        if tr.header.nonce > ae.nonce {
+            return Err(TemplateSkip::Later); // Out of order transaction
         }
+        ae.nonce += 1;
+
+        let (receiver, amount) = match tr.get_data() {
+            Ok(TransactionData::ClassicTransaction {
+                receiver, amount, ..
+            }) => (receiver, amount),
+            Ok(_) => {
+                // Other transaction types are considered valid
+                accounts.insert(em, ae);
+                return Ok(());
+            }
+            Err(_) => return Err(TemplateSkip::Invalid),
+        };
+        if amount.is_zero() {
+            return Err(TemplateSkip::Invalid); // Invalid transaction amount
+        }
+
+        let r = String::from_utf8_lossy(&receiver)
+            .trim_end_matches('\0')
+            .to_string();
+        if em == r {
+            return Err(TemplateSkip::Invalid); // Cannot send money to yourself
+        }
+
+        let mut ar = match accounts.get(&r) {
+            Some(a) => *a,
+            None => match self.get_account(&r) {
+                Ok(a) => a,
+                Err(_) => return Err(TemplateSkip::Later),
+            },
+        };
+
+        // Calculate total cost: amount + transaction fees
+        let total_cost = match tr.total_cost() {
+            Some(c) => c,
+            None => return Err(TemplateSkip::Invalid), // Amount overflow
+        };
+
+        // Deduct amount + fees from sender. The balance may still be
+        // funded by a transaction that is not mined yet.
+        ae = match ae.debited(total_cost, false) {
+            Some(a) => a,
+            None => return Err(TemplateSkip::Later),
+        };
+        // Only the amount goes to receiver, the fees go to the miner
+        // through the coinbase
+        ar = match ar.credited(amount) {
+            Some(a) => a,
+            None => return Err(TemplateSkip::Later), // Balance overflow
+        };
+        accounts.insert(em, ae);
+        accounts.insert(r, ar);
+        Ok(())
+    }
+
+    /// Block to mine on the tip with the most profitable transactions of
+    /// the mempool, within the limits of `production`, paying its fees and
+    /// subsidy to `miner`, whose key is `key`. `None` if no transaction is
+    /// valid. The nonce of the block still has to be found.
+    pub async fn block_template(
+        &mut self,
+        production: &BlockProduction,
+        miner: &str,
+        key: &KeyPair,
+    ) -> Option<Block> {
+        let expired = self.mempool.expire(current_time());
+        if expired > 0 {
+            println!("{} transaction(s) expired from the mempool", expired);
+        }
+
+        // Transactions of each emitter in nonce order, as they can only go
+        // in the block one after the other
+        let mut queues: HashMap<String, VecDeque<Transaction>> = HashMap::new();
+        for tr in self.mempool.transactions() {
+            queues
+                .entry(tr.header.get_login())
+                .or_default()
+                .push_back(tr.clone());
+        }
+        for queue in queues.values_mut() {
+            queue.make_contiguous().sort_by_key(|t| t.header.nonce);
+        }
+
+        let mut accounts: HashMap<String, Account> = HashMap::new();
+        let mut valid_trs: Vec<Transaction> = vec![];
+        let mut rejected: Vec<Transaction> = vec![];
+        let mut size = BLOCK_HEADER_SIZE + COINBASE_TRANSACTION_SIZE;
+
+        // The coinbase takes a place in the block
+        while valid_trs.len() + 1 < production.max_transactions {
+            // Next transaction of the emitter paying the most per byte,
+            // the oldest one on a tie
+            let login = match queues
+                .iter()
+                .max_by_key(|(_, q)| {
+                    (q[0].header.fees, Reverse(q[0].header.timestamp))
+                })
+            {
+                Some((l, _)) => l.clone(),
+                None => break,
+            };
+            let queue = queues.get_mut(&login).unwrap();
+            let tr = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                queues.remove(&login);
+            }
+
+            // The next transactions of the emitter can't go in the block
+            // without this one
+            let tr_size = tr.size() as usize;
+            if BLOCK_HEADER_SIZE + COINBASE_TRANSACTION_SIZE + tr_size
+                > production.max_block_size
+            {
+                queues.remove(&login);
+                rejected.push(tr); // Never fits in a block
+                continue;
+            }
+            if size + tr_size > production.max_block_size {
+                queues.remove(&login);
+                continue;
+            }
+            match self.apply_to_template(&mut accounts, &tr) {
+                Ok(_) => {}
+                Err(TemplateSkip::Later) => {
+                    queues.remove(&login);
+                    continue;
+                }
+                Err(TemplateSkip::Invalid) => {
+                    queues.remove(&login);
+                    rejected.push(tr);
+                    continue;
+                }
+            }
+
+            size += tr_size;
+            valid_trs.push(tr);
+        }
+
+        // The transactions that can't be mined would otherwise stay in the
+        // mempool until they expire
+        self.mempool.drop_transactions(&rejected);
 
         if valid_trs.is_empty() {
-            return;
+            return None;
         }
 
-        let block = Block::new(self.last_hash, &valid_trs);
-        let block_data = block.to_buffer();
-        self.append(&block);
-        println!("New block created with {} transaction(s)", valid_trs.len());
+        let height = self.hashes.len() as u64;
+        let reward = max_reward(height, &valid_trs)?;
+        let coinbase =
+            match Transaction::new_coinbase(height, reward, miner, key) {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("Failed to create coinbase: {}", e);
+                    return None;
+                }
+            };
+        let mut transactions = vec![coinbase];
+        transactions.extend(valid_trs);
 
-        // Broadcast block to all peers
-        let peers = peer_list.lock().await;
-        peers.broadcast_block(block_data, &self_address, self_port).await;
+        let difficulty =
+            self.window.expected(self.last_header.as_ref(), BLOCK_VERSION);
+        Some(Block::new(self.last_hash, &transactions, difficulty))
     }
 
-    /// Add a transaction from a client (will be broadcasted to peers)
-    pub async fn add_transaction(&mut self, transaction: Transaction, peer_list: Arc<Mutex<PeerList>>, self_address: String, self_port: u16) {
+    /// Add a transaction from a client, once checked against the chain and
+    /// the mempool. It is broadcast to the peers by the caller once added.
+    pub async fn add_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), MempoolError> {
+        self.check_new_transaction(&transaction)?;
+
         let emitter = transaction.header.get_login();
         let fees = transaction.fee_cost();
-        
+
         match transaction.get_data() {
-            Ok(TransactionData::ClassicTransaction { receiver, amount, .. }) => {
+            Ok(TransactionData::ClassicTransaction {
+                receiver, amount, ..
+            }) => {
                 let receiver_str = String::from_utf8_lossy(&receiver)
                     .trim_end_matches('\0')
                     .to_string();
@@ -323,74 +1108,189 @@ impl Blockchain {
                 println!("Transaction received from {}", emitter);
             }
         }
-        
-        self.mempool.add(transaction);
 
-        if self.mempool.is_full() {
-            self.create_new_block(peer_list, self_address, self_port).await;
-        }
+        self.mempool.add(transaction)?;
+        self.pending.notify_one();
+        Ok(())
     }
 
     /// Add a transaction received from peer sync (no broadcast, no duplicate)
-    pub async fn add_transaction_from_sync(&mut self, transaction: Transaction) {
-        let _emitter = transaction.header.get_login();
-        
-        // Check if transaction is already in mempool (avoid duplicates)
-        // For now, just add it - deduplication could be improved
-        self.mempool.add(transaction);
+    pub async fn add_transaction_from_sync(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), MempoolError> {
+        self.check_new_transaction(&transaction)?;
+        self.mempool.add(transaction)?;
 
         // Note: We don't create blocks from synced transactions
         // Only the originating server creates the block and broadcasts it
+        Ok(())
     }
 
-    pub fn read_block(&mut self, offset: u64) -> Result<Block, String> {
-        let mut header_buff = [0_u8; BLOCK_HEADER_SIZE];
+    /// Nonce expected for the next transaction of `login`, counting the
+    /// transactions waiting in the mempool. Expired transactions are
+    /// dropped first so that their nonces can be used again.
+    pub fn next_nonce(&mut self, login: &str) -> Result<u64, String> {
+        self.mempool.expire(current_time());
+        let account = self.get_account(login)?;
+        Ok(account.nonce + self.mempool.pending_count(login))
+    }
 
-        match self.file.seek(SeekFrom::Start(offset)) {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(format!(
-                    "Failed to seek in blockchain file: {}",
-                    e
-                ));
-            }
+    /// Reject transactions without nonce or signed raw, replayed ones, the
+    /// ones whose nonce is not the next one of their emitter, the ones the
+    /// emitter can't pay on top of its pending transactions and the ones
+    /// that don't pay enough to enter a full mempool
+    pub fn check_new_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), MempoolError> {
+        if transaction.is_coinbase() {
+            return Err(MempoolError::Coinbase);
+        }
+        if !transaction.header.has_nonce() {
+            return Err(MempoolError::MissingNonce);
+        }
+        // Raw signatures are no longer valid in the blocks being mined
+        let scheme = transaction.header.signature_scheme;
+        if scheme != SignatureScheme::Pkcs1v15Sha256 {
+            return Err(MempoolError::RawSignature);
+        }
+
+        self.mempool.expire(current_time());
+        let login = transaction.header.get_login();
+        let account = match self.get_account(&login) {
+            Ok(a) => a,
+            Err(e) => return Err(MempoolError::AccountUnavailable(e)),
         };
+        let expected = account.nonce + self.mempool.pending_count(&login);
+        if transaction.header.nonce < expected {
+            return Err(MempoolError::NonceAlreadyUsed);
+        }
+        if transaction.header.nonce > expected {
+            return Err(MempoolError::NonceGap { expected });
+        }
 
-        let header = match self.file.read_exact(&mut header_buff) {
-            Ok(_) => BlockHeader::from_buff(&header_buff),
-            Err(e) => {
-                return Err(format!("Error reading blockchain file: {}", e));
+        // The pending transactions of the emitter are paid first
+        let available = account
+            .balance
+            .checked_sub(self.mempool.pending_spend(&login))
+            .unwrap_or(Amount::ZERO);
+        let cost = match transaction.get_data() {
+            Ok(TransactionData::ClassicTransaction { .. }) => {
+                transaction.total_cost()
             }
+            _ => Some(Amount::ZERO),
         };
+        match cost {
+            Some(c) if c <= available => {}
+            _ => return Err(MempoolError::InsufficientBalance { available }),
+        }
 
-        let mut block_buff = vec![0_u8; header.transactions_size as usize];
+        self.mempool.check_room(transaction)
+    }
 
-        match self
-            .file
-            .seek(SeekFrom::Start(offset + BLOCK_HEADER_SIZE as u64))
-        {
-            Ok(_) => (),
+    /// Reload the transactions of the mempool journal, dropping the ones
+    /// that are no longer valid on the current chain or with the current
+    /// keys of their emitter, then keep journaling the mempool
+    pub async fn restore_mempool(&mut self, cache: &mut Cache) {
+        let path = Journal::path();
+        let entries = match Journal::replay(&path) {
+            Ok(e) => e,
             Err(e) => {
-                return Err(format!(
-                    "Failed to seek in blockchain file: {}",
-                    e
-                ));
+                eprintln!("Failed to reload the mempool: {}", e);
+                vec![]
             }
         };
 
-        match self.file.read_exact(&mut block_buff) {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(format!("Failed to read blockchain file: {}", e));
+        let now = current_time();
+        let mut restored = 0;
+        for entry in entries {
+            let txid = entry.transaction.txid();
+            let expired = entry.arrival.saturating_add(MEMPOOL_EXPIRY) <= now;
+            let result = if expired {
+                Err("Expired".to_string())
+            } else if let Err(e) =
+                cache.check_transaction(&entry.transaction).await
+            {
+                Err(e.to_string())
+            } else if let Err(e) = self.check_new_transaction(&entry.transaction)
+            {
+                Err(e.to_string())
+            } else {
+                self.mempool
+                    .add_at(entry.transaction, entry.arrival)
+                    .map_err(|e| e.to_string())
+            };
+
+            match result {
+                Ok(_) => restored += 1,
+                Err(e) => println!(
+                    "{} Dropped pending transaction {}: {}",
+                    "MEMPOOL".yellow().bold(),
+                    txid,
+                    e
+                ),
             }
-        };
+        }
+        if restored > 0 {
+            println!("{} pending transaction(s) restored", restored);
+            self.pending.notify_one();
+        }
+
+        if let Err(e) = self.mempool.open_journal(&path) {
+            eprintln!("Failed to open the mempool journal: {}", e);
+        }
+    }
+
+    /// Transactions of `login` waiting in the mempool, in nonce order
+    pub fn pending_transactions(&mut self, login: &str) -> Vec<Transaction> {
+        self.mempool.expire(current_time());
+        self.mempool.pending_for(login).into_iter().cloned().collect()
+    }
+
+    /// Transaction `txid` waiting in the mempool
+    pub fn pending_transaction(&self, txid: &TxId) -> Option<Transaction> {
+        self.mempool.get(txid).cloned()
+    }
 
-        let mut buff =
-            vec![0_u8; BLOCK_HEADER_SIZE + header.transactions_size as usize];
-        buff[0..BLOCK_HEADER_SIZE].copy_from_slice(&header_buff);
-        buff[BLOCK_HEADER_SIZE..].copy_from_slice(&block_buff);
+    pub fn read_block(&mut self, offset: u64) -> Result<Block, String> {
+        Self::read_block_from(&mut self.file, offset)
+    }
 
-        Block::from_buffer(&buff)
+    /// Read the header of the block at `offset` of `file`
+    fn read_header_from(
+        file: &mut File,
+        offset: u64,
+    ) -> Result<[u8; BLOCK_HEADER_SIZE], String> {
+        block_file::read_header(file, offset)
+    }
+
+    /// Read the block at `offset` of `file`, a file of block records
+    /// written one after the other
+    pub fn read_block_from(
+        file: &mut File,
+        offset: u64,
+    ) -> Result<Block, String> {
+        Block::from_buffer(&block_file::read_block(file, offset)?)
+    }
+
+    /// Header of the block at `height`
+    pub fn header_at(&mut self, height: u64) -> Result<BlockHeader, String> {
+        let offset = match self.hashes.get(height as usize) {
+            Some(hash) => self.cache[hash],
+            None => return Err(format!("No block at height {}", height)),
+        };
+        let buff = Self::read_header_from(&mut self.file, offset)?;
+        Ok(BlockHeader::from_buff(&buff))
+    }
+
+    /// Block at `height`
+    pub fn block_at(&mut self, height: u64) -> Result<Block, String> {
+        let offset = match self.hashes.get(height as usize) {
+            Some(hash) => self.cache[hash],
+            None => return Err(format!("No block at height {}", height)),
+        };
+        self.read_block(offset)
     }
 
     pub fn get_block(
@@ -406,12 +1306,18 @@ impl Blockchain {
         self.read_block(offset)
     }
 
-    pub fn block_foreach(
+    /// Call `f` on the first `count` blocks
+    fn block_foreach_until(
         &mut self,
+        count: u64,
         mut f: impl FnMut(&Block) -> Result<(), String>,
     ) -> Result<(), String> {
-        let mut offset = 0;
-        while offset < self.size {
+        let end = match self.hashes.get(count as usize) {
+            Some(hash) => self.cache[hash],
+            None => self.size,
+        };
+        let mut offset = FILE_HEADER_SIZE;
+        while offset < end {
             let block = match self.read_block(offset) {
                 Ok(b) => b,
                 Err(e) => {
@@ -422,56 +1328,103 @@ impl Blockchain {
                 }
             };
             f(&block)?;
-            offset += block.size() as u64;
+            offset += block_file::record_size(block.size() as u64);
         }
         Ok(())
     }
 
-    pub fn get_user_balance<T>(&mut self, login: T) -> Result<f32, String>
+    pub fn get_user_balance<T>(&mut self, login: T) -> Result<Amount, String>
+    where
+        T: AsRef<str>,
+    {
+        match self.get_account(login) {
+            Ok(a) => Ok(a.balance),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_account<T>(&mut self, login: T) -> Result<Account, String>
     where
         T: AsRef<str>,
     {
-        let login = login.as_ref();
-        let mut balance = INITIAL_BALANCE as f32;
-
-        let res = self.block_foreach(|b| {
-            for tr in &b.transactions {
-                if tr.header.data_type == DataType::ClassicTransaction {
-                    match tr.get_data() {
-                        Ok(data) => {
-                            match data {
-                                TransactionData::ClassicTransaction {
-                                    receiver,
-                                    amount,
-                                    ..
-                                } => {
-                                    let mut l = [0; TRANSACTION_RECEIVER];
-                                    l[..login.len()]
-                                        .copy_from_slice(login.as_bytes());
-                                    if l == receiver {
-                                        balance += amount;
-                                    } else if l == tr.header.emitter {
-                                        // Deduct the amount + transaction fees
-                                        balance -= amount + tr.fee_cost();
-                                    };
-                                }
-                                _ => (),
-                            };
-                        }
-                        Err(_) => {
-                            return Err(
-                                "Failed to get transaction data".to_string()
-                            );
-                        }
-                    };
-                };
+        Ok(self.accounts.get(login.as_ref()))
+    }
+
+    /// State after the first `count` blocks of the accounts changed by the
+    /// blocks after them, which are undone once from the tip. The other
+    /// accounts are the same as at the tip.
+    pub fn accounts_at(
+        &mut self,
+        count: u64,
+    ) -> Result<HashMap<String, Account>, String> {
+        let mut accounts: HashMap<String, Account> = HashMap::new();
+        for height in (count..self.hashes.len() as u64).rev() {
+            let block = self.block_at(height)?;
+            for tr in block.transactions.iter().rev() {
+                for login in AccountStore::logins(tr)? {
+                    let account = accounts
+                        .entry(login.clone())
+                        .or_insert_with(|| self.accounts.get(&login));
+                    AccountStore::update(
+                        account,
+                        &login,
+                        tr,
+                        block.header.version,
+                        false,
+                    )?;
+                }
             }
-            Ok(())
-        });
+        }
+        Ok(accounts)
+    }
 
-        match res {
-            Ok(_) => Ok(balance),
-            Err(e) => Err(e),
+    /// Page of the confirmed transactions of `login` matching `query`, the
+    /// newest first
+    pub fn history(
+        &mut self,
+        login: &str,
+        query: &HistoryQuery,
+    ) -> Result<HistoryPage, String> {
+        let positions = self.accounts.history(login);
+        let end = match query.cursor {
+            Some(cursor) => positions.partition_point(|p| *p < cursor),
+            None => positions.len(),
+        };
+        let positions = positions[..end].to_vec();
+
+        let mut page = HistoryPage {
+            transactions: vec![],
+            next_cursor: None,
+        };
+        let mut last = None;
+        let mut block: Option<(u64, Block)> = None;
+        for position in positions.into_iter().rev() {
+            if page.transactions.len() >= query.limit {
+                // Transactions are left, the next page starts after the
+                // last one returned
+                page.next_cursor = last;
+                break;
+            }
+
+            let b = match block {
+                Some((height, b)) if height == position.height => b,
+                _ => self.block_at(position.height)?,
+            };
+            let tr = match b.transactions.get(position.index as usize) {
+                Some(t) => t,
+                None => {
+                    return Err(format!(
+                        "No transaction at position {}",
+                        position
+                    ))
+                }
+            };
+            if query.matches(login, tr) {
+                page.transactions.push(tr.clone());
+                last = Some(position);
+            }
+            block = Some((position.height, b));
         }
+        Ok(page)
     }
 }
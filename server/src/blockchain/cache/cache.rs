@@ -1,11 +1,43 @@
-use super::user::User;
-use nexium::{defaults::SIG_SAMPLE, gitlab::GitlabClient, rsa::KeyPair};
+use super::{challenges::Challenges, user::User};
+use nexium::{
+    blockchain::{
+        consts::SIGNATURE_SIZE, transaction::Transaction,
+        transaction_header::TransactionHeader,
+    },
+    gitlab::{GitlabClient, GitlabError},
+    rsa::{signature::SignatureScheme, KeyPair},
+};
 use num_bigint::BigUint;
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Reasons for which a transaction signature can't be verified
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionCheckError {
+    /// The transaction can't be decoded
+    MalformedData,
+    /// The emitter doesn't exist or has no key on Gitlab
+    UnknownEmitter,
+    /// None of the emitter keys matches the signature
+    BadSignature,
+    /// The keys of the emitter could not be fetched
+    KeysUnavailable(String),
+}
+
+impl fmt::Display for TransactionCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedData => write!(f, "Malformed transaction"),
+            Self::UnknownEmitter => write!(f, "Unknown emitter"),
+            Self::BadSignature => write!(f, "Invalid signature"),
+            Self::KeysUnavailable(e) => write!(f, "{}", e),
+        }
+    }
+}
 
 pub struct Cache {
     pub data: HashMap<String, User>,
     pub gitlab: GitlabClient,
+    pub challenges: Challenges,
 }
 
 impl Cache {
@@ -13,6 +45,7 @@ impl Cache {
         Self {
             data: HashMap::new(),
             gitlab,
+            challenges: Challenges::new(),
         }
     }
 
@@ -23,16 +56,11 @@ impl Cache {
         }
     }
 
-    pub async fn update_keys(
+    async fn fetch_keys(
         &mut self,
         login: &String,
-    ) -> Result<Vec<KeyPair>, String> {
-        let keys = match self.gitlab.get_gpg_keys_async(login.as_str()).await {
-            Ok(keys) => keys,
-            Err(e) => {
-                return Err(format!("Failed to get GPG keys: {}", e));
-            }
-        };
+    ) -> Result<Vec<KeyPair>, GitlabError> {
+        let keys = self.gitlab.get_gpg_keys_async(login.as_str()).await?;
 
         let mut user = self.get_user(login);
         let keys: Vec<KeyPair> = keys
@@ -47,6 +75,16 @@ impl Cache {
         Ok(keys)
     }
 
+    pub async fn update_keys(
+        &mut self,
+        login: &String,
+    ) -> Result<Vec<KeyPair>, String> {
+        match self.fetch_keys(login).await {
+            Ok(keys) => Ok(keys),
+            Err(e) => Err(format!("Failed to get GPG keys: {}", e)),
+        }
+    }
+
     // pub fn update_balance(&mut self, login: &String) -> Result<f32, String> {
     //     let mut user = self.get_user(login);
     //     let mut offset = 0;
@@ -98,8 +136,9 @@ impl Cache {
 
     fn check_keys(
         &self,
-        keys: &Vec<KeyPair>,
-        sig: &String,
+        keys: &[KeyPair],
+        scheme: SignatureScheme,
+        sig: &str,
         message: &Vec<u8>,
     ) -> Option<KeyPair> {
         let s = match BigUint::from_str(sig) {
@@ -110,7 +149,7 @@ impl Cache {
         };
 
         for key in keys.iter() {
-            match key.check_signature(message, &s) {
+            match key.check_signature_with(scheme, message, &s) {
                 Ok(b) => {
                     if b {
                         return Some(key.clone());
@@ -122,19 +161,16 @@ impl Cache {
         None
     }
 
+    /// Key of `login` that made the signature `sig` of `msg`
     pub async fn get_key(
         &mut self,
         login: &String,
-        sig: &String,
-        message: Option<&Vec<u8>>,
+        scheme: SignatureScheme,
+        sig: &str,
+        msg: &Vec<u8>,
     ) -> Option<KeyPair> {
-        let msg = match message {
-            Some(m) => m,
-            None => &SIG_SAMPLE.as_bytes().to_vec(),
-        };
-
         match self.data.get(login) {
-            Some(u) => match self.check_keys(&u.keys, sig, msg) {
+            Some(u) => match self.check_keys(&u.keys, scheme, sig, msg) {
                 Some(k) => {
                     return Some(k);
                 }
@@ -144,8 +180,67 @@ impl Cache {
         };
 
         match self.update_keys(&login).await {
-            Ok(keys) => self.check_keys(&keys, sig, msg),
+            Ok(keys) => self.check_keys(&keys, scheme, sig, msg),
             Err(_) => None,
         }
     }
+
+    /// Check that `transaction` is well formed and signed by one of the
+    /// keys of its emitter
+    pub async fn check_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<KeyPair, TransactionCheckError> {
+        if TransactionHeader::size_of_version(transaction.header.version)
+            .is_none()
+            || transaction.header.transaction_size as usize
+                != transaction.data.len()
+            || transaction.signature.bits() > (SIGNATURE_SIZE * 8) as u64
+            || transaction.get_data().is_err()
+        {
+            return Err(TransactionCheckError::MalformedData);
+        }
+
+        // Raw signatures are only checked for the blocks older than
+        // PKCS1_SIGNATURE_BLOCK_VERSION, the mempool refuses them. The
+        // scheme is covered by the signature. Headers older than version 2
+        // can't store it, they are always signed raw.
+        let scheme = transaction.header.signature_scheme;
+        if !transaction.header.has_signature_scheme()
+            && scheme != SignatureScheme::Raw
+        {
+            return Err(TransactionCheckError::MalformedData);
+        }
+        let login = transaction.header.get_login();
+        let sig = transaction.signature.to_string();
+        let message = transaction.signed_message();
+
+        if let Some(u) = self.data.get(&login) {
+            if let Some(k) = self.check_keys(&u.keys, scheme, &sig, &message) {
+                return Ok(k);
+            }
+        }
+
+        let keys = match self.fetch_keys(&login).await {
+            Ok(keys) => keys,
+            Err(GitlabError::UserNotFound) => {
+                return Err(TransactionCheckError::UnknownEmitter);
+            }
+            Err(e) => {
+                return Err(TransactionCheckError::KeysUnavailable(format!(
+                    "Failed to get GPG keys: {}",
+                    e
+                )));
+            }
+        };
+
+        if keys.is_empty() {
+            return Err(TransactionCheckError::UnknownEmitter);
+        }
+
+        match self.check_keys(&keys, scheme, &sig, &message) {
+            Some(k) => Ok(k),
+            None => Err(TransactionCheckError::BadSignature),
+        }
+    }
 }
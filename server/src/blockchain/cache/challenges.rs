@@ -0,0 +1,104 @@
+use nexium::utils::time::current_time;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+
+/// How long (in seconds) an issued nonce can be used, also the maximum
+/// clock difference tolerated on the timestamp of a signed request
+pub const CHALLENGE_TTL: u32 = 5 * 60;
+/// Maximum number of outstanding nonces, the oldest ones are dropped
+/// first
+pub const MAX_CHALLENGES: usize = 4096;
+/// Maximum number of outstanding nonces of a single address, so that one
+/// client can't push the nonces of the others out
+pub const MAX_CHALLENGES_PER_ADDRESS: usize = 64;
+const CHALLENGE_SIZE: usize = 16;
+
+struct Challenge {
+    issued_at: u32,
+    address: String,
+}
+
+/// Nonces issued to the clients, each one authenticates a single request
+pub struct Challenges {
+    issued: HashMap<String, Challenge>,
+    /// Issue order, may still hold nonces that were already used
+    order: VecDeque<String>,
+    /// Number of outstanding nonces by address
+    per_address: HashMap<String, usize>,
+}
+
+impl Challenges {
+    pub fn new() -> Self {
+        Self {
+            issued: HashMap::new(),
+            order: VecDeque::new(),
+            per_address: HashMap::new(),
+        }
+    }
+
+    fn remove(&mut self, nonce: &str) -> Option<Challenge> {
+        let challenge = self.issued.remove(nonce)?;
+        if let Some(n) = self.per_address.get_mut(&challenge.address) {
+            *n -= 1;
+            if *n == 0 {
+                self.per_address.remove(&challenge.address);
+            }
+        }
+        Some(challenge)
+    }
+
+    fn prune(&mut self, now: u32) {
+        while let Some(nonce) = self.order.front() {
+            let expired = match self.issued.get(nonce) {
+                Some(c) => now.saturating_sub(c.issued_at) > CHALLENGE_TTL,
+                None => true,
+            };
+            if !expired && self.order.len() < MAX_CHALLENGES {
+                break;
+            }
+            if let Some(nonce) = self.order.pop_front() {
+                self.remove(&nonce);
+            }
+        }
+    }
+
+    /// New nonce for a client at `address`, `None` if it already holds too
+    /// many unused ones
+    pub fn issue(&mut self, address: &str) -> Option<String> {
+        let now = current_time();
+        self.prune(now);
+
+        let count = self.per_address.get(address).copied().unwrap_or(0);
+        if count >= MAX_CHALLENGES_PER_ADDRESS {
+            return None;
+        }
+
+        let bytes: [u8; CHALLENGE_SIZE] = rand::rng().random();
+        let nonce = hex::encode(bytes);
+        let challenge = Challenge {
+            issued_at: now,
+            address: address.to_string(),
+        };
+        self.issued.insert(nonce.clone(), challenge);
+        self.order.push_back(nonce.clone());
+        self.per_address.insert(address.to_string(), count + 1);
+        Some(nonce)
+    }
+
+    /// Whether `nonce` was issued, has not expired and was not used yet
+    pub fn is_valid(&self, nonce: &str) -> bool {
+        match self.issued.get(nonce) {
+            Some(c) => {
+                current_time().saturating_sub(c.issued_at) <= CHALLENGE_TTL
+            }
+            None => false,
+        }
+    }
+
+    /// Mark `nonce` as used, returns false if it was not valid
+    pub fn consume(&mut self, nonce: &str) -> bool {
+        let valid = self.is_valid(nonce);
+        self.remove(nonce);
+        valid
+    }
+}
@@ -1,9 +1,9 @@
-use nexium::rsa::KeyPair;
+use nexium::{blockchain::amount::Amount, rsa::KeyPair};
 
 #[derive(Clone)]
 pub struct User {
     #[allow(unused)]
-    pub balance: Option<f32>,
+    pub balance: Option<Amount>,
     pub keys: Vec<KeyPair>,
 }
 
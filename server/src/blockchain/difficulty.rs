@@ -0,0 +1,172 @@
+//! Proof of work of the blocks
+//!
+//! Blocks before `COMPACT_TARGET_BLOCK_VERSION` hold in `difficulty_target`
+//! the number of leading hex zeros of their hash, which is always
+//! `LEGACY_DIFFICULTY`. Later blocks hold a compact target: the high byte
+//! is the size of the target in bytes and the 3 low bytes its most
+//! significant bytes, the hash read as a big endian number must not exceed
+//! it. The target is adjusted every `RETARGET_INTERVAL` blocks so that
+//! blocks come every `TARGET_BLOCK_TIME` seconds.
+
+use super::structure::block_header::{BlockHeader, HeaderPreviousBlockHash};
+use nexium::defaults::{
+    COMPACT_TARGET_BLOCK_VERSION, LEGACY_DIFFICULTY, POW_LIMIT,
+    RETARGET_INTERVAL, TARGET_BLOCK_TIME,
+};
+use num_bigint::BigUint;
+use std::collections::VecDeque;
+
+/// Largest factor by which the target changes at each retarget
+const MAX_RETARGET_FACTOR: u64 = 4;
+
+/// Target encoded by `bits`, `None` if it is negative or zero
+pub fn target_from_compact(bits: u32) -> Option<BigUint> {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007fffff;
+    if mantissa == 0 || bits & 0x00800000 != 0 {
+        return None;
+    }
+
+    let mantissa = BigUint::from(mantissa);
+    if size <= 3 {
+        Some(mantissa >> (8 * (3 - size)))
+    } else {
+        Some(mantissa << (8 * (size - 3)))
+    }
+}
+
+/// Compact encoding of `target`, rounded down
+pub fn compact_from_target(target: &BigUint) -> u32 {
+    let bytes = target.to_bytes_be();
+    let mut size = bytes.len() as u32;
+    let mut mantissa = bytes
+        .iter()
+        .take(3)
+        .fold(0_u32, |acc, b| (acc << 8) | *b as u32);
+    if size < 3 {
+        mantissa <<= 8 * (3 - size);
+    }
+
+    // The high bit of the mantissa is a sign bit
+    if mantissa & 0x00800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+fn pow_limit() -> BigUint {
+    target_from_compact(POW_LIMIT).unwrap()
+}
+
+/// Whether `bits` encodes a target no easier than `POW_LIMIT`
+pub fn is_valid_compact(bits: u32) -> bool {
+    match target_from_compact(bits) {
+        Some(target) => target <= pow_limit(),
+        None => false,
+    }
+}
+
+/// Whether the hash of a block meets the difficulty of its header
+pub fn meets_difficulty(
+    header: &BlockHeader,
+    hash: &HeaderPreviousBlockHash,
+) -> bool {
+    if header.version < COMPACT_TARGET_BLOCK_VERSION {
+        let zeros = "0".repeat(header.difficulty_target as usize);
+        return hex::encode(hash).starts_with(&zeros);
+    }
+
+    match target_from_compact(header.difficulty_target) {
+        Some(target) => {
+            target <= pow_limit() && BigUint::from_bytes_be(hash) <= target
+        }
+        None => false,
+    }
+}
+
+/// Number of hashes to try on average to find a block with the difficulty
+/// of `header`
+pub fn block_work(header: &BlockHeader) -> u128 {
+    if header.version < COMPACT_TARGET_BLOCK_VERSION {
+        return 16_u128.saturating_pow(header.difficulty_target);
+    }
+
+    let target = match target_from_compact(header.difficulty_target) {
+        Some(t) => t,
+        None => return 0,
+    };
+    let work = (BigUint::from(1_u8) << 256) / (target + 1_u8);
+    u128::try_from(work).unwrap_or(u128::MAX)
+}
+
+/// Target following `bits` when the last `RETARGET_INTERVAL` blocks were
+/// found from `first_timestamp` to `last_timestamp`
+pub fn retarget(bits: u32, first_timestamp: u32, last_timestamp: u32) -> u32 {
+    let expected = (RETARGET_INTERVAL - 1) * TARGET_BLOCK_TIME as u64;
+    let actual = (last_timestamp.saturating_sub(first_timestamp) as u64).clamp(
+        expected / MAX_RETARGET_FACTOR,
+        expected * MAX_RETARGET_FACTOR,
+    );
+
+    let target = match target_from_compact(bits) {
+        Some(t) => t * actual / expected,
+        None => return POW_LIMIT,
+    };
+    compact_from_target(&target.min(pow_limit()))
+}
+
+/// Timestamps of the last blocks of a chain, what the difficulty of its
+/// next block depends on
+#[derive(Clone, Default)]
+pub struct DifficultyWindow {
+    /// Height of the next block
+    height: u64,
+    /// Timestamps of the last `RETARGET_INTERVAL` blocks, oldest first
+    timestamps: VecDeque<u32>,
+}
+
+impl DifficultyWindow {
+    /// Window of a chain whose next block is at `height`, the headers of
+    /// up to `RETARGET_INTERVAL` previous blocks must then be pushed
+    pub fn new(height: u64) -> Self {
+        Self {
+            height,
+            timestamps: VecDeque::new(),
+        }
+    }
+
+    /// Height of the next block
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn push(&mut self, header: &BlockHeader) {
+        self.height += 1;
+        self.timestamps.push_back(header.timestamp);
+        if self.timestamps.len() > RETARGET_INTERVAL as usize {
+            self.timestamps.pop_front();
+        }
+    }
+
+    /// Difficulty of the next block, of version `version`, following the
+    /// block whose header is `last`
+    pub fn expected(&self, last: Option<&BlockHeader>, version: u16) -> u32 {
+        if version < COMPACT_TARGET_BLOCK_VERSION {
+            return LEGACY_DIFFICULTY;
+        }
+
+        // The first block with a compact target starts from the limit
+        let last = match last {
+            Some(l) if l.version >= COMPACT_TARGET_BLOCK_VERSION => l,
+            _ => return POW_LIMIT,
+        };
+
+        if !self.height.is_multiple_of(RETARGET_INTERVAL)
+            || self.timestamps.len() < RETARGET_INTERVAL as usize
+        {
+            return last.difficulty_target;
+        }
+        retarget(last.difficulty_target, self.timestamps[0], last.timestamp)
+    }
+}
@@ -0,0 +1,117 @@
+//! Transaction history of the accounts
+//!
+//! The classic transactions of each login are indexed by their position in
+//! the main chain, in the account store. The history is read from the
+//! newest transaction, page by page: the cursor of a page is the position
+//! of its last transaction, the next page starts right before it.
+
+use nexium::blockchain::{
+    transaction::Transaction, transaction_data::TransactionData,
+};
+use std::{fmt, str::FromStr};
+
+/// Default number of transactions of a page
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+/// Maximum number of transactions of a page
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Position of a transaction in the main chain, written `height-index`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TxPosition {
+    pub height: u64,
+    /// Index of the transaction in its block
+    pub index: u32,
+}
+
+impl fmt::Display for TxPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.height, self.index)
+    }
+}
+
+impl FromStr for TxPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (height, index) = match s.split_once('-') {
+            Some(p) => p,
+            None => return Err("Invalid cursor".to_string()),
+        };
+        match (height.parse(), index.parse()) {
+            (Ok(height), Ok(index)) => Ok(Self { height, index }),
+            _ => Err("Invalid cursor".to_string()),
+        }
+    }
+}
+
+/// Side of the transactions of a login
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(Self::Sent),
+            "received" => Ok(Self::Received),
+            _ => Err("Invalid direction".to_string()),
+        }
+    }
+}
+
+/// Page of history to read, and filters of its transactions
+pub struct HistoryQuery {
+    /// Cursor of the previous page, `None` for the newest transactions
+    pub cursor: Option<TxPosition>,
+    pub limit: usize,
+    pub direction: Option<Direction>,
+    /// Login on the other side of the transactions
+    pub counterparty: Option<String>,
+    /// Bounds of the timestamps of the transactions, included
+    pub from: Option<u32>,
+    pub to: Option<u32>,
+}
+
+impl HistoryQuery {
+    /// Whether `tr`, a classic transaction of `login`, passes the filters
+    pub fn matches(&self, login: &str, tr: &Transaction) -> bool {
+        let receiver = match tr.get_data() {
+            Ok(TransactionData::ClassicTransaction { receiver, .. }) => {
+                String::from_utf8_lossy(&receiver)
+                    .trim_end_matches('\0')
+                    .to_string()
+            }
+            _ => return false,
+        };
+        let emitter = tr.header.get_login();
+
+        let counterparty = match self.direction {
+            Some(Direction::Sent) if emitter != login => return false,
+            Some(Direction::Received) if receiver != login => return false,
+            _ if emitter == login => receiver,
+            _ => emitter,
+        };
+        if self
+            .counterparty
+            .as_ref()
+            .is_some_and(|c| *c != counterparty)
+        {
+            return false;
+        }
+
+        let timestamp = tr.header.timestamp;
+        !(self.from.is_some_and(|from| timestamp < from)
+            || self.to.is_some_and(|to| timestamp > to))
+    }
+}
+
+pub struct HistoryPage {
+    /// Transactions of the page, the newest first
+    pub transactions: Vec<Transaction>,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<TxPosition>,
+}
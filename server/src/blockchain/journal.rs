@@ -0,0 +1,217 @@
+//! Journal of the mempool
+//!
+//! Every transaction entering or leaving the mempool is appended to a file
+//! under NEXIUM_HOME, so that the pending transactions survive a restart of
+//! the server. On startup the journal is replayed, then written again with
+//! only the transactions that are still valid. It is also written again
+//! once most of its records are about transactions that left the mempool.
+//!
+//! A record is a kind byte, the length of its payload on 4 bytes and the
+//! payload: the arrival time and the buffer of an added transaction, or
+//! the txid of a removed one.
+
+use super::atomic_file;
+use nexium::{
+    blockchain::{
+        transaction::Transaction,
+        txid::{TxId, TXID_SIZE},
+    },
+    defaults::NEXIUM_HOME,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const MEMPOOL_FILE: &str = "mempool.dat";
+
+const RECORD_ADDED: u8 = 0;
+const RECORD_REMOVED: u8 = 1;
+const RECORD_HEADER_SIZE: usize = 5;
+/// Number of removed transactions after which the journal is written
+/// again, if they outnumber the waiting ones
+const COMPACT_MIN_REMOVED: u64 = 1024;
+
+/// Transaction replayed from the journal
+pub struct JournalEntry {
+    pub transaction: Transaction,
+    /// Time the transaction entered the mempool
+    pub arrival: u32,
+}
+
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    /// Size of the complete records, the file is cut back to it when a
+    /// record can't be written
+    size: u64,
+    /// Number of transactions of the journal still in the mempool
+    live: u64,
+    /// Number of transactions of the journal removed from the mempool
+    removed: u64,
+}
+
+impl Journal {
+    pub fn path() -> PathBuf {
+        Path::new(NEXIUM_HOME).join(MEMPOOL_FILE)
+    }
+
+    /// Transactions added to the journal at `path` and not removed since,
+    /// in arrival order. The replay stops at a truncated record, left by a
+    /// crash, or at an invalid one.
+    pub fn replay(path: &Path) -> Result<Vec<JournalEntry>, String> {
+        let buff = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(vec![])
+            }
+            Err(e) => return Err(format!("Failed to read journal: {}", e)),
+        };
+
+        let mut entries: Vec<Option<JournalEntry>> = vec![];
+        let mut positions: HashMap<TxId, usize> = HashMap::new();
+        let mut offset = 0;
+
+        while offset + RECORD_HEADER_SIZE <= buff.len() {
+            let kind = buff[offset];
+            let len = u32::from_be_bytes(
+                buff[offset + 1..offset + RECORD_HEADER_SIZE]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            let start = offset + RECORD_HEADER_SIZE;
+            if start + len > buff.len() {
+                break;
+            }
+            let payload = &buff[start..start + len];
+            offset = start + len;
+
+            match kind {
+                RECORD_ADDED if len > 4 => {
+                    let arrival =
+                        u32::from_be_bytes(payload[..4].try_into().unwrap());
+                    let transaction =
+                        match Transaction::from_buffer(&payload[4..]) {
+                            Ok(t) => t,
+                            Err(_) => break,
+                        };
+                    positions.insert(transaction.txid(), entries.len());
+                    entries.push(Some(JournalEntry {
+                        transaction,
+                        arrival,
+                    }));
+                }
+                RECORD_REMOVED if len == TXID_SIZE => {
+                    let txid = TxId::from_bytes(payload.try_into().unwrap());
+                    if let Some(i) = positions.remove(&txid) {
+                        entries[i] = None;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        Ok(entries.into_iter().flatten().collect())
+    }
+
+    /// Create the journal at `path`, holding only `entries`. The previous
+    /// journal is replaced once the new one is fully written.
+    pub fn create(
+        path: &Path,
+        entries: &[(&Transaction, u32)],
+    ) -> Result<Self, String> {
+        let mut buff = vec![];
+        for (transaction, arrival) in entries {
+            buff.extend(Self::added_record(transaction, *arrival));
+        }
+        if let Err(e) = atomic_file::write(path, &buff) {
+            return Err(format!("Failed to write journal: {}", e));
+        }
+
+        match OpenOptions::new().append(true).open(path) {
+            Ok(file) => Ok(Self {
+                file,
+                path: path.to_path_buf(),
+                size: buff.len() as u64,
+                live: entries.len() as u64,
+                removed: 0,
+            }),
+            Err(e) => Err(format!("Failed to open journal: {}", e)),
+        }
+    }
+
+    /// Whether most of the transactions of the journal left the mempool
+    pub fn needs_compaction(&self) -> bool {
+        self.removed >= COMPACT_MIN_REMOVED && self.removed >= self.live
+    }
+
+    /// Write the journal again with only `entries`, the transactions
+    /// waiting in the mempool
+    pub fn compact(
+        &mut self,
+        entries: &[(&Transaction, u32)],
+    ) -> Result<(), String> {
+        *self = Self::create(&self.path, entries)?;
+        Ok(())
+    }
+
+    fn record(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut res = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        res.push(kind);
+        res.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        res.extend_from_slice(payload);
+        res
+    }
+
+    fn added_record(transaction: &Transaction, arrival: u32) -> Vec<u8> {
+        let mut payload = arrival.to_be_bytes().to_vec();
+        payload.extend(transaction.to_buffer());
+        Self::record(RECORD_ADDED, &payload)
+    }
+
+    /// Append `record`, synced to the disk if `sync` is set. A record that
+    /// can't be written is cut, so that the next ones can be replayed.
+    fn append(&mut self, record: &[u8], sync: bool) -> Result<(), String> {
+        let mut res = self.file.write_all(record);
+        if sync && res.is_ok() {
+            res = self.file.sync_data();
+        }
+        match res {
+            Ok(_) => {
+                self.size += record.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.file.set_len(self.size);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// Record that `transaction` entered the mempool at `arrival`. The
+    /// record is on the disk when this returns, so that an accepted
+    /// transaction survives a crash.
+    pub fn added(
+        &mut self,
+        transaction: &Transaction,
+        arrival: u32,
+    ) -> Result<(), String> {
+        let record = Self::added_record(transaction, arrival);
+        self.append(&record, true)?;
+        self.live += 1;
+        Ok(())
+    }
+
+    /// Record that `txid` left the mempool. The record is on the disk when
+    /// this returns, so that a mined transaction doesn't come back after a
+    /// crash.
+    pub fn removed(&mut self, txid: &TxId) -> Result<(), String> {
+        let record = Self::record(RECORD_REMOVED, txid.as_bytes());
+        self.append(&record, true)?;
+        self.live = self.live.saturating_sub(1);
+        self.removed += 1;
+        Ok(())
+    }
+}
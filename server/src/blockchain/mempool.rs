@@ -1,40 +1,451 @@
+//! Transactions waiting to be mined
+//!
+//! The mempool indexes its transactions by txid, by fees per byte and by
+//! emitter and nonce. Its size is capped: once full, a new transaction
+//! takes the place of the ones paying the least per byte, or is refused if
+//! it doesn't pay more than them. Transactions waiting for too long are
+//! dropped. A dropped transaction takes the later transactions of its
+//! emitter with it, since their nonces can no longer follow.
+//! Once a journal is opened, every change is appended to it.
+
+use super::journal::Journal;
 use nexium::{
-    blockchain::transaction::Transaction, defaults::TRANSACTION_COUNT,
+    blockchain::{amount::Amount, transaction::Transaction, txid::TxId},
+    utils::time::current_time,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    path::Path,
 };
 
+/// Maximum total size of the waiting transactions, in bytes
+pub const MAX_MEMPOOL_SIZE: usize = 8 * 1024 * 1024;
+/// Time after which a waiting transaction is dropped, in seconds
+pub const MEMPOOL_EXPIRY: u32 = 3 * 24 * 60 * 60;
+
+/// Reasons for refusing a new transaction in the mempool
+#[derive(Debug)]
+pub enum MempoolError {
+    /// Coinbases are only created by the miners, in their blocks
+    Coinbase,
+    /// The transaction has no nonce
+    MissingNonce,
+    /// The transaction is signed without padding
+    RawSignature,
+    /// The nonce was already used by a confirmed transaction
+    NonceAlreadyUsed,
+    /// The nonce is used by a transaction waiting in the mempool
+    NoncePending,
+    /// The nonce is ahead of the next one of the emitter
+    NonceGap { expected: u64 },
+    /// The same transaction is already waiting
+    Duplicate,
+    /// The emitter can't pay the transaction on top of its waiting ones
+    InsufficientBalance { available: Amount },
+    /// The mempool is full of transactions paying at least `min_fees` per
+    /// byte
+    Full { min_fees: u16 },
+    /// The account of the emitter could not be computed
+    AccountUnavailable(String),
+    /// The transaction could not be written to the journal
+    Journal(String),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Coinbase => {
+                write!(f, "Coinbase transactions are only valid in blocks")
+            }
+            Self::MissingNonce => write!(f, "Transaction has no nonce"),
+            Self::RawSignature => {
+                write!(f, "Transaction must be signed with PKCS#1 v1.5 padding")
+            }
+            Self::NonceAlreadyUsed => write!(f, "Nonce already used"),
+            Self::NoncePending => {
+                write!(f, "Nonce already used by a pending transaction")
+            }
+            Self::NonceGap { expected } => {
+                write!(f, "Invalid nonce, expected {}", expected)
+            }
+            Self::Duplicate => write!(f, "Transaction already in mempool"),
+            Self::InsufficientBalance { available } => write!(
+                f,
+                "Insufficient balance, {} available after pending transactions",
+                available
+            ),
+            Self::Full { min_fees } => write!(
+                f,
+                "Mempool is full, fees must be above {} per byte",
+                min_fees
+            ),
+            Self::AccountUnavailable(e) => write!(f, "{}", e),
+            Self::Journal(e) => {
+                write!(f, "Failed to write mempool journal: {}", e)
+            }
+        }
+    }
+}
+
+/// Position of a transaction in the eviction order: the lowest fees per
+/// byte first, then the newest
+type FeeKey = (u16, Reverse<u32>, TxId);
+
+struct Entry {
+    transaction: Transaction,
+    login: String,
+    size: usize,
+    /// Amount and fees taken from the emitter
+    spend: Amount,
+    /// Time the transaction entered the mempool
+    arrival: u32,
+}
+
+impl Entry {
+    fn key(&self, txid: TxId) -> FeeKey {
+        (self.transaction.header.fees, Reverse(self.arrival), txid)
+    }
+}
+
 pub struct Mempool {
-    data: Vec<Transaction>,
+    entries: HashMap<TxId, Entry>,
+    /// Transactions in eviction order
+    by_fees: BTreeSet<FeeKey>,
+    /// Transactions of each emitter by nonce
+    by_login: HashMap<String, BTreeMap<u64, TxId>>,
+    /// Amount spent by the waiting transactions of each emitter
+    spends: HashMap<String, Amount>,
+    /// Total size of the transactions, in bytes
+    size: usize,
+    journal: Option<Journal>,
 }
 
 impl Mempool {
     pub fn new() -> Self {
-        Self { data: vec![] }
+        Self {
+            entries: HashMap::new(),
+            by_fees: BTreeSet::new(),
+            by_login: HashMap::new(),
+            spends: HashMap::new(),
+            size: 0,
+            journal: None,
+        }
+    }
+
+    /// Write the waiting transactions to a new journal at `path`, then
+    /// record every change in it
+    pub fn open_journal(&mut self, path: &Path) -> Result<(), String> {
+        let entries = Self::journal_entries(&self.entries);
+        self.journal = Some(Journal::create(path, &entries)?);
+        Ok(())
+    }
+
+    /// Transactions of `entries` with their arrival time, in the order
+    /// they are written to a new journal
+    fn journal_entries(
+        entries: &HashMap<TxId, Entry>,
+    ) -> Vec<(&Transaction, u32)> {
+        let mut res: Vec<(&Transaction, u32)> = entries
+            .values()
+            .map(|e| (&e.transaction, e.arrival))
+            .collect();
+        // Replayed in this order, the nonces of each emitter follow
+        res.sort_by_key(|(t, arrival)| (*arrival, t.header.nonce));
+        res
+    }
+
+    /// Write the journal again once most of its transactions left the
+    /// mempool, so that it doesn't grow forever
+    fn compact_journal(&mut self) {
+        let journal = match self.journal.as_mut() {
+            Some(j) if j.needs_compaction() => j,
+            _ => return,
+        };
+        let entries = Self::journal_entries(&self.entries);
+        if let Err(e) = journal.compact(&entries) {
+            Self::journal_error(e);
+        }
     }
 
-    pub fn add(&mut self, transaction: Transaction) {
-        self.data.push(transaction);
+    fn journal_error(e: String) {
+        eprintln!("Failed to write mempool journal: {}", e);
     }
 
-    pub fn is_full(&self) -> bool {
-        self.data.len() >= TRANSACTION_COUNT
+    /// Add a transaction, unless it or another transaction with the same
+    /// emitter and nonce is already waiting. Cheaper transactions are
+    /// evicted if the mempool is full.
+    pub fn add(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), MempoolError> {
+        self.add_at(transaction, current_time())
     }
 
-    pub fn dump(&mut self) -> Vec<Transaction> {
-        self.data.drain(0..TRANSACTION_COUNT).collect()
+    /// Add a transaction that entered the mempool at `arrival`. It is
+    /// written to the journal first, and not added if that fails.
+    pub fn add_at(
+        &mut self,
+        transaction: Transaction,
+        arrival: u32,
+    ) -> Result<(), MempoolError> {
+        let txid = transaction.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(MempoolError::Duplicate);
+        }
+
+        let login = transaction.header.get_login();
+        let nonce = transaction.header.nonce;
+        if self
+            .by_login
+            .get(&login)
+            .is_some_and(|nonces| nonces.contains_key(&nonce))
+        {
+            return Err(MempoolError::NoncePending);
+        }
+
+        let size = transaction.size() as usize;
+        let evicted =
+            self.evictions_for(&login, transaction.header.fees, size)?;
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.added(&transaction, arrival) {
+                return Err(MempoolError::Journal(e));
+            }
+        }
+        for id in evicted {
+            self.remove_entry(&id);
+        }
+
+        let spend = transaction.total_cost().unwrap_or(Amount::ZERO);
+        let entry = Entry {
+            transaction,
+            login: login.clone(),
+            size,
+            spend,
+            arrival,
+        };
+        self.by_fees.insert(entry.key(txid));
+        self.by_login
+            .entry(login.clone())
+            .or_default()
+            .insert(nonce, txid);
+        let total = self.spends.entry(login).or_default();
+        *total = total.saturating_add(spend);
+        self.size += size;
+        self.entries.insert(txid, entry);
+        Ok(())
     }
 
-    /// Remove transactions that are included in a synced block
+    /// Check that `transaction` would find a place in the mempool
+    pub fn check_room(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(), MempoolError> {
+        self.evictions_for(
+            &transaction.header.get_login(),
+            transaction.header.fees,
+            transaction.size() as usize,
+        )
+        .map(|_| ())
+    }
+
+    /// Transactions to evict to make room for `size` bytes paying `fees`
+    /// per byte, emitted by `login`. Only transactions paying less can be
+    /// evicted, and never the ones of the same emitter since the new one
+    /// would follow them.
+    fn evictions_for(
+        &self,
+        login: &str,
+        fees: u16,
+        size: usize,
+    ) -> Result<Vec<TxId>, MempoolError> {
+        let mut evicted: HashSet<TxId> = HashSet::new();
+        let mut freed = 0;
+        let mut cheapest = self.by_fees.iter();
+
+        while self.size - freed + size > MAX_MEMPOOL_SIZE {
+            let (entry_fees, _, txid) = match cheapest.next() {
+                Some(k) => k,
+                None => return Err(MempoolError::Full { min_fees: fees }),
+            };
+            if evicted.contains(txid) {
+                continue;
+            }
+            let entry = &self.entries[txid];
+            if *entry_fees >= fees || entry.login == login {
+                return Err(MempoolError::Full {
+                    min_fees: *entry_fees,
+                });
+            }
+
+            for id in self.descendants(txid) {
+                if evicted.insert(id) {
+                    freed += self.entries[&id].size;
+                }
+            }
+        }
+        Ok(evicted.into_iter().collect())
+    }
+
+    /// Transaction `txid` and the later transactions of its emitter
+    fn descendants(&self, txid: &TxId) -> Vec<TxId> {
+        let entry = match self.entries.get(txid) {
+            Some(e) => e,
+            None => return vec![],
+        };
+        match self.by_login.get(&entry.login) {
+            Some(nonces) => nonces
+                .range(entry.transaction.header.nonce..)
+                .map(|(_, id)| *id)
+                .collect(),
+            None => vec![*txid],
+        }
+    }
+
+    fn remove_entry(&mut self, txid: &TxId) -> Option<Entry> {
+        let entry = self.entries.remove(txid)?;
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.removed(txid) {
+                Self::journal_error(e);
+            }
+        }
+        self.by_fees.remove(&entry.key(*txid));
+        self.size -= entry.size;
+
+        let emptied = match self.by_login.get_mut(&entry.login) {
+            Some(nonces) => {
+                nonces.remove(&entry.transaction.header.nonce);
+                nonces.is_empty()
+            }
+            None => false,
+        };
+        if emptied {
+            self.by_login.remove(&entry.login);
+            self.spends.remove(&entry.login);
+        } else if let Some(total) = self.spends.get_mut(&entry.login) {
+            *total = total.checked_sub(entry.spend).unwrap_or(Amount::ZERO);
+        }
+        self.compact_journal();
+        Some(entry)
+    }
+
+    /// Number of waiting transactions emitted by `login`
+    pub fn pending_count(&self, login: &str) -> u64 {
+        match self.by_login.get(login) {
+            Some(nonces) => nonces.len() as u64,
+            None => 0,
+        }
+    }
+
+    /// Amount and fees that the waiting transactions of `login` will take
+    /// from its balance
+    pub fn pending_spend(&self, login: &str) -> Amount {
+        match self.spends.get(login) {
+            Some(s) => *s,
+            None => Amount::ZERO,
+        }
+    }
+
+    /// Waiting transactions emitted by `login`, in nonce order
+    pub fn pending_for(&self, login: &str) -> Vec<&Transaction> {
+        match self.by_login.get(login) {
+            Some(nonces) => nonces
+                .values()
+                .filter_map(|id| self.entries.get(id))
+                .map(|e| &e.transaction)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Remove the waiting transactions of `login` and return them with
+    /// their arrival time, in nonce order
+    pub fn take_pending(&mut self, login: &str) -> Vec<(Transaction, u32)> {
+        let txids: Vec<TxId> = match self.by_login.get(login) {
+            Some(nonces) => nonces.values().copied().collect(),
+            None => return vec![],
+        };
+        txids
+            .iter()
+            .filter_map(|id| self.remove_entry(id))
+            .map(|e| (e.transaction, e.arrival))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total size of the waiting transactions, in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Waiting transactions, paying the most per byte first and the oldest
+    /// first on a tie
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.by_fees
+            .iter()
+            .rev()
+            .filter_map(|(_, _, id)| self.entries.get(id))
+            .map(|e| &e.transaction)
+    }
+
+    /// Remove transactions that are included in a synced block, and the
+    /// waiting ones that used the same nonces
     pub fn remove_transactions(&mut self, transactions: &[Transaction]) {
-        // Remove transactions by matching their signature (unique identifier)
-        self.data.retain(|t| {
-            !transactions.iter().any(|bt| bt.signature == t.signature)
-        });
-    }
-
-    // Commented because unused for now
-    //
-    // /// Clear all transactions from mempool
-    // pub fn clear(&mut self) {
-    //     self.data.clear();
-    // }
+        for tr in transactions {
+            if self.remove_entry(&tr.txid()).is_some() {
+                continue;
+            }
+
+            let conflict = self
+                .by_login
+                .get(&tr.header.get_login())
+                .and_then(|nonces| nonces.get(&tr.header.nonce))
+                .copied();
+            if let Some(id) = conflict {
+                self.remove_entry(&id);
+            }
+        }
+    }
+
+    /// Drop transactions that can't be mined, with the later transactions
+    /// of their emitters
+    pub fn drop_transactions(&mut self, transactions: &[Transaction]) {
+        for tr in transactions {
+            for id in self.descendants(&tr.txid()) {
+                self.remove_entry(&id);
+            }
+        }
+    }
+
+    /// Drop the transactions that entered the mempool more than
+    /// `MEMPOOL_EXPIRY` seconds before `now`. Returns the number of
+    /// transactions dropped.
+    pub fn expire(&mut self, now: u32) -> usize {
+        let expired: Vec<TxId> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.arrival.saturating_add(MEMPOOL_EXPIRY) <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let count = self.entries.len();
+        for txid in expired {
+            for id in self.descendants(&txid) {
+                self.remove_entry(&id);
+            }
+        }
+        count - self.entries.len()
+    }
+
+    pub fn get(&self, txid: &TxId) -> Option<&Transaction> {
+        self.entries.get(txid).map(|e| &e.transaction)
+    }
 }
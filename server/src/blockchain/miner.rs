@@ -0,0 +1,225 @@
+//! Background miner
+//!
+//! The miner waits for the mempool to fill a block, or for the block
+//! interval to pass with transactions waiting. It then takes a block
+//! template built from the mempool and searches its nonce on several
+//! threads, without holding the lock of the blockchain.
+//! The search is dropped as soon as the tip of the chain changes, since a
+//! block received from a peer makes the template stale. A block found is
+//! submitted like the blocks of the peers, then broadcast.
+
+use super::{
+    blockchain::{BlockOutcome, Blockchain},
+    cache::cache::Cache,
+    difficulty::meets_difficulty,
+    structure::{
+        block::Block, block_header::HeaderPreviousBlockHash,
+        consts::BLOCK_HEADER_SIZE,
+    },
+};
+use crate::{
+    config::BlockProduction, network::router::context::State, peers::PeerList,
+};
+use colored::Colorize;
+use nexium::rsa::KeyPair;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+use tokio::{
+    sync::{watch, Mutex},
+    task::{self, JoinHandle},
+    time,
+};
+
+/// Number of nonces tried by a thread between two checks of the tip
+const CHECK_INTERVAL: usize = 4096;
+
+pub struct Miner {
+    blockchain: Arc<Mutex<Blockchain>>,
+    cache: Arc<Mutex<Cache>>,
+    peer_list: Arc<Mutex<PeerList>>,
+    self_address: String,
+    self_port: u16,
+    /// Login credited with the rewards of the blocks
+    login: String,
+    key: KeyPair,
+    production: BlockProduction,
+    /// Number of threads searching the nonce
+    threads: usize,
+}
+
+impl Miner {
+    pub fn new(state: &State, production: BlockProduction) -> Self {
+        let threads = match thread::available_parallelism() {
+            Ok(n) => n.get(),
+            Err(_) => 1,
+        };
+
+        Self {
+            blockchain: state.blockchain.clone(),
+            cache: state.cache.clone(),
+            peer_list: state.peer_list.clone(),
+            self_address: state.self_address.clone(),
+            self_port: state.self_port,
+            login: state.login.clone(),
+            key: state.key.clone(),
+            production,
+            threads,
+        }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        let pending = self.blockchain.lock().await.pending();
+
+        loop {
+            let wait =
+                self.blockchain.lock().await.next_block_in(&self.production);
+            match wait {
+                None => {
+                    pending.notified().await;
+                    continue;
+                }
+                Some(w) if !w.is_zero() => {
+                    // New transactions may fill the block before
+                    let _ = time::timeout(w, pending.notified()).await;
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            let (template, tip) = {
+                let mut bc = self.blockchain.lock().await;
+                let template = bc
+                    .block_template(&self.production, &self.login, &self.key)
+                    .await;
+                (template, bc.watch_tip())
+            };
+            let template = match template {
+                Some(t) => t,
+                None => {
+                    // The transactions left may only become valid later
+                    let interval = self.production.block_interval;
+                    let _ = time::timeout(interval, pending.notified()).await;
+                    continue;
+                }
+            };
+
+            let threads = self.threads;
+            let found =
+                task::spawn_blocking(move || search(template, threads, tip))
+                    .await;
+            match found {
+                Ok(Some(block)) => self.submit(block).await,
+                Ok(None) => {}
+                Err(e) => eprintln!("Mining task failed: {}", e),
+            }
+        }
+    }
+
+    /// Add a mined block to the chain and broadcast it
+    async fn submit(&self, block: Block) {
+        let block_data = block.to_buffer();
+        let count = block.transactions.len();
+
+        let mut cache = self.cache.lock().await;
+        let mut bc = self.blockchain.lock().await;
+        match bc.submit_block(block, &mut cache).await {
+            Ok(BlockOutcome::Connected(_))
+            | Ok(BlockOutcome::Reorganised { .. }) => {
+                println!("New block created with {} transaction(s)", count);
+            }
+            Ok(_) => {
+                println!(
+                    "{} Mined block is not on the main chain",
+                    "MINER".yellow().bold()
+                );
+                return;
+            }
+            Err(e) => {
+                println!(
+                    "{} Mined block rejected: {}",
+                    "MINER".red().bold(),
+                    e
+                );
+                return;
+            }
+        }
+        drop(bc);
+        drop(cache);
+
+        // Broadcast block to all peers
+        let peers = self.peer_list.lock().await;
+        peers
+            .broadcast_block(block_data, &self.self_address, self.self_port)
+            .await;
+    }
+}
+
+/// Search a nonce meeting the difficulty of `block` on `threads` threads.
+/// Returns `None` if the tip changed or every nonce was tried, a new
+/// template with a new timestamp is then needed.
+fn search(
+    mut block: Block,
+    threads: usize,
+    tip: watch::Receiver<HeaderPreviousBlockHash>,
+) -> Option<Block> {
+    let found = AtomicBool::new(false);
+
+    let nonce = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads as u64)
+            .map(|first| {
+                let (block, tip, found) = (&block, tip.clone(), &found);
+                s.spawn(move || search_from(block, first, threads, &tip, found))
+            })
+            .collect();
+
+        let mut nonce = None;
+        for handle in handles {
+            if let Ok(Some(n)) = handle.join() {
+                nonce = Some(n);
+            }
+        }
+        nonce
+    })?;
+
+    block.header.nonce = nonce;
+    Some(block)
+}
+
+/// Try the nonces from `first` by steps of `step` until one meets the
+/// difficulty, another thread found one or the tip changed
+fn search_from(
+    block: &Block,
+    first: u64,
+    step: usize,
+    tip: &watch::Receiver<HeaderPreviousBlockHash>,
+    found: &AtomicBool,
+) -> Option<u32> {
+    let mut header = block.header;
+    let mut buff = block.to_buffer();
+
+    for (tries, nonce) in (first..=u32::MAX as u64).step_by(step).enumerate() {
+        if tries.is_multiple_of(CHECK_INTERVAL)
+            && (found.load(Ordering::Relaxed)
+                || tip.has_changed().unwrap_or(true))
+        {
+            return None;
+        }
+
+        header.nonce = nonce as u32;
+        buff[..BLOCK_HEADER_SIZE].copy_from_slice(&header.to_buffer());
+        if meets_difficulty(&header, &Block::double_hash_(&buff)) {
+            found.store(true, Ordering::Relaxed);
+            return Some(header.nonce);
+        }
+    }
+    None
+}
@@ -1,5 +1,19 @@
+pub mod account;
+pub mod account_store;
+pub mod atomic_file;
+pub mod block_file;
+pub mod block_index;
 pub mod blockchain;
 pub mod cache;
-mod mempool;
+pub mod difficulty;
+pub mod history;
+pub mod journal;
+pub mod mempool;
+pub mod miner;
+pub mod reward;
+pub mod side_chain;
+pub mod staging;
 pub mod structure;
+pub mod sync;
+pub mod validator;
 // pub mod test;
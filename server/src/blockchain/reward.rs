@@ -0,0 +1,34 @@
+//! Reward of the miners
+//!
+//! From `COINBASE_BLOCK_VERSION`, the first transaction of a block is its
+//! coinbase, emitted and signed by its miner. It credits the miner with at
+//! most the fees paid by the other transactions of the block and the
+//! subsidy of its height, halved every `SUBSIDY_HALVING_INTERVAL` blocks.
+
+use nexium::{
+    blockchain::{amount::Amount, transaction::Transaction},
+    defaults::{INITIAL_BLOCK_SUBSIDY, SUBSIDY_HALVING_INTERVAL},
+};
+
+/// Amount created by the block at `height`
+pub fn block_subsidy(height: u64) -> Amount {
+    let halvings = height / SUBSIDY_HALVING_INTERVAL;
+    if halvings >= u64::BITS as u64 {
+        return Amount::ZERO;
+    }
+    Amount::from_micro(INITIAL_BLOCK_SUBSIDY >> halvings)
+}
+
+/// Fees paid by `transactions`, `None` on overflow
+pub fn block_fees(transactions: &[Transaction]) -> Option<Amount> {
+    transactions
+        .iter()
+        .filter(|t| t.header.data_type.is_classic())
+        .try_fold(Amount::ZERO, |acc, t| acc.checked_add(t.fee_cost()))
+}
+
+/// Largest reward of the coinbase of the block at `height` whose other
+/// transactions are `transactions`
+pub fn max_reward(height: u64, transactions: &[Transaction]) -> Option<Amount> {
+    block_fees(transactions)?.checked_add(block_subsidy(height))
+}
@@ -0,0 +1,108 @@
+use super::{
+    difficulty::block_work,
+    structure::{block::Block, block_header::HeaderPreviousBlockHash},
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Maximum number of blocks kept off the main chain
+pub const MAX_SIDE_BLOCKS: usize = 256;
+
+/// Blocks that are not on the main chain: the blocks of competing branches
+/// and the orphans, whose parent is still unknown
+pub struct SideChain {
+    blocks: HashMap<HeaderPreviousBlockHash, Block>,
+    /// Insertion order, the oldest blocks are dropped first
+    order: VecDeque<HeaderPreviousBlockHash>,
+}
+
+impl SideChain {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn contains(&self, hash: &HeaderPreviousBlockHash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn insert(&mut self, hash: HeaderPreviousBlockHash, block: Block) {
+        if self.blocks.insert(hash, block).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+
+        while self.blocks.len() > MAX_SIDE_BLOCKS {
+            match self.order.pop_front() {
+                Some(old) => {
+                    self.blocks.remove(&old);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn remove(&mut self, hash: &HeaderPreviousBlockHash) -> Option<Block> {
+        let block = self.blocks.remove(hash)?;
+        self.order.retain(|h| h != hash);
+        Some(block)
+    }
+
+    /// Remove `hash` and every block built on it
+    pub fn remove_descendants(&mut self, hash: &HeaderPreviousBlockHash) {
+        let mut pending = vec![*hash];
+        while let Some(h) = pending.pop() {
+            pending.extend(self.children(&h));
+            self.remove(&h);
+        }
+    }
+
+    fn children(
+        &self,
+        hash: &HeaderPreviousBlockHash,
+    ) -> Vec<HeaderPreviousBlockHash> {
+        self.blocks
+            .iter()
+            .filter(|(_, b)| b.header.previous_block_hash == *hash)
+            .map(|(h, _)| *h)
+            .collect()
+    }
+
+    /// Last block of the branch with the most work built on the block
+    /// `hash`, with the work of the branch after `hash`
+    pub fn best_tip(
+        &self,
+        hash: &HeaderPreviousBlockHash,
+    ) -> (HeaderPreviousBlockHash, u128) {
+        let mut best = (*hash, 0);
+        for child in self.children(hash) {
+            let (tip, work) = self.best_tip(&child);
+            let work = work + block_work(&self.blocks[&child].header);
+            if work > best.1 {
+                best = (tip, work);
+            }
+        }
+        best
+    }
+
+    /// Blocks of the pool leading to `tip`, oldest first. The parent of the
+    /// first one is not in the pool.
+    pub fn branch(
+        &self,
+        tip: &HeaderPreviousBlockHash,
+    ) -> Vec<HeaderPreviousBlockHash> {
+        let mut res = vec![];
+        let mut hash = *tip;
+        while let Some(block) = self.blocks.get(&hash) {
+            res.push(hash);
+            hash = block.header.previous_block_hash;
+        }
+        res.reverse();
+        res
+    }
+
+    pub fn get(&self, hash: &HeaderPreviousBlockHash) -> Option<&Block> {
+        self.blocks.get(hash)
+    }
+}
@@ -0,0 +1,87 @@
+//! Blocks kept in a file while the main chain switches to another branch
+//!
+//! A switch may move many blocks: the ones downloaded from a peer before
+//! they are connected, and the ones disconnected from the main chain until
+//! they are dropped or put back. They are written as records of the
+//! blockchain file to a staging file next to it, so that they never have to
+//! fit in memory. The file is removed once the staged blocks are dropped.
+
+use super::{block_file, structure::block::Block};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+pub struct StagedBlocks {
+    file: File,
+    path: PathBuf,
+    /// Offset of the record of each block, in staging order
+    offsets: Vec<u64>,
+    size: u64,
+}
+
+impl StagedBlocks {
+    /// Create an empty staging file at `path`, replacing an older one
+    pub fn create(path: PathBuf) -> Result<Self, String> {
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+        {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(format!(
+                    "Failed to open {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        };
+        Ok(Self {
+            file,
+            path,
+            offsets: vec![],
+            size: 0,
+        })
+    }
+
+    /// Stage the block `buff`
+    pub fn push(&mut self, buff: &[u8]) -> Result<(), String> {
+        if let Err(e) = self.file.write_all(&block_file::record(buff)) {
+            return Err(format!(
+                "Failed to write {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+        self.offsets.push(self.size);
+        self.size += block_file::record_size(buff.len() as u64);
+        Ok(())
+    }
+
+    pub fn len(&self) -> u64 {
+        self.offsets.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Block staged at `index`
+    pub fn get(&mut self, index: u64) -> Result<Block, String> {
+        let offset = match self.offsets.get(index as usize) {
+            Some(o) => *o,
+            None => return Err(format!("No staged block {}", index)),
+        };
+        Block::from_buffer(&block_file::read_block(&mut self.file, offset)?)
+    }
+}
+
+impl Drop for StagedBlocks {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -3,8 +3,11 @@ use super::{
     consts::BLOCK_HEADER_SIZE,
 };
 use nexium::{
-    blockchain::transaction::{transaction_vec_size, Transaction},
-    defaults::{BLOCK_VERSION, DIFFICULTY_TARGET},
+    blockchain::{
+        merkle::{self, MerkleProof},
+        transaction::{transaction_vec_size, Transaction},
+    },
+    defaults::{BLOCK_VERSION, MERKLE_TREE_BLOCK_VERSION},
     sha256::sha256,
 };
 
@@ -42,7 +45,10 @@ impl Block {
     //     Block::merkle_root_rec(res)
     // }
 
-    fn merkle_root(transactions: &Vec<Transaction>) -> HeaderMerkleRoot {
+    /// Root of the blocks written before MERKLE_TREE_BLOCK_VERSION. It is not
+    /// a real tree (only the last level is hashed), so no proof can be
+    /// built from it. Kept to verify the blocks already on disk.
+    fn legacy_merkle_root(transactions: &Vec<Transaction>) -> HeaderMerkleRoot {
         let mut trs: Vec<Vec<u8>> =
             transactions.iter().map(|tr| tr.to_buffer()).collect();
 
@@ -65,44 +71,64 @@ impl Block {
         sha256(&trs[0])
     }
 
-    pub fn new(
-        previous_block_hash: HeaderPreviousBlockHash,
+    /// Merkle root of `transactions` for a block of version `version`
+    pub fn merkle_root(
+        version: u16,
         transactions: &Vec<Transaction>,
-    ) -> Self {
-        let size = transaction_vec_size(&transactions);
-        let merkle_root = Block::merkle_root(&transactions);
-        let mut b;
-        let mut nonce = 0;
-        let mut target = String::new();
-        for _ in 0..DIFFICULTY_TARGET {
-            target.push('0');
+    ) -> HeaderMerkleRoot {
+        if version < MERKLE_TREE_BLOCK_VERSION {
+            Block::legacy_merkle_root(transactions)
+        } else {
+            merkle::merkle_root(transactions)
         }
+    }
 
-        loop {
-            b = Self {
-                header: BlockHeader::new(
-                    BLOCK_VERSION,
-                    previous_block_hash,
-                    merkle_root,
-                    DIFFICULTY_TARGET,
-                    nonce,
-                    size,
-                ),
-                transactions: transactions.clone(),
-            };
+    /// Check that the header merkle root matches the transactions
+    pub fn check_merkle_root(&self) -> bool {
+        if self.transactions.is_empty() {
+            return self.header.version >= MERKLE_TREE_BLOCK_VERSION
+                && self.header.merkle_root == merkle::merkle_root(&[]);
+        }
+        self.header.merkle_root
+            == Block::merkle_root(self.header.version, &self.transactions)
+    }
 
-            let hash = b.double_hash();
-            // if hash[0..DIFFICULTY_TARGET as usize].iter().all(|&a| a == 0) {
-            //     break;
-            // }
-            let h = hex::encode(&hash);
-            if h.starts_with(&target) {
-                break;
-            }
-            nonce += 1;
+    /// Inclusion proof of the transaction at `tx_index`, to be checked
+    /// against the merkle root of the header with `merkle::verify_proof`
+    pub fn prove(&self, tx_index: usize) -> Result<MerkleProof, String> {
+        if self.header.version < MERKLE_TREE_BLOCK_VERSION {
+            return Err(format!(
+                "Block version {} has no merkle tree",
+                self.header.version
+            ));
         }
 
-        return b;
+        match MerkleProof::build(&self.transactions, tx_index) {
+            Some(proof) => Ok(proof),
+            None => Err(format!("No transaction at index {}", tx_index)),
+        }
+    }
+
+    /// Block to be mined, its nonce still has to be found
+    pub fn new(
+        previous_block_hash: HeaderPreviousBlockHash,
+        transactions: &Vec<Transaction>,
+        difficulty_target: u32,
+    ) -> Self {
+        let size = transaction_vec_size(&transactions);
+        let merkle_root = Block::merkle_root(BLOCK_VERSION, &transactions);
+
+        Self {
+            header: BlockHeader::new(
+                BLOCK_VERSION,
+                previous_block_hash,
+                merkle_root,
+                difficulty_target,
+                0,
+                size,
+            ),
+            transactions: transactions.clone(),
+        }
     }
 
     pub fn double_hash(&self) -> HeaderPreviousBlockHash {
@@ -118,6 +144,9 @@ impl Block {
     }
 
     pub fn from_buffer(buff: &[u8]) -> Result<Self, String> {
+        if buff.len() < BLOCK_HEADER_SIZE {
+            return Err("Failed to read block header".to_string());
+        }
         let header_buff: [u8; BLOCK_HEADER_SIZE] =
             match buff[0..BLOCK_HEADER_SIZE].try_into() {
                 Ok(h) => h,
@@ -127,19 +156,16 @@ impl Block {
             };
         let header = BlockHeader::from_buff(&header_buff);
 
-        let transactions_size = header.transactions_size as usize;
-
-        // check buffer size
-        // dbg!(buff.len(), transactions_size + BLOCK_HEADER_SIZE);
-        // if buff.len() != transactions_size + BLOCK_HEADER_SIZE {
-        //     panic!("Buffer size is not correct");
-        // }
+        let end = BLOCK_HEADER_SIZE + header.transactions_size as usize;
+        if buff.len() < end {
+            return Err("Block is truncated".to_string());
+        }
 
         let mut transaction;
         let mut offset = BLOCK_HEADER_SIZE;
         let mut transactions = vec![];
-        while offset < transactions_size {
-            transaction = match Transaction::from_buffer(&buff[offset..]) {
+        while offset < end {
+            transaction = match Transaction::from_buffer(&buff[offset..end]) {
                 Ok(t) => t,
                 Err(_) => {
                     return Err(format!(
@@ -152,6 +178,10 @@ impl Block {
             transactions.push(transaction);
         }
 
+        if offset != end {
+            return Err("Transactions overflow the block size".to_string());
+        }
+
         Ok(Self {
             header,
             transactions,
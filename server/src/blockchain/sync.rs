@@ -0,0 +1,317 @@
+//! Headers-first synchronisation with a peer
+//!
+//! The headers are fetched first, from the last block both chains share,
+//! to check that the chain of the peer has more work. Only then are the
+//! missing blocks downloaded, in batches, so that neither the headers nor
+//! the blocks of the chain ever have to fit in memory. The downloaded
+//! blocks and the blocks they replace are staged in files.
+//!
+//! The hash of a block covers its transactions, so the hashes sent with
+//! the headers are only trusted to link them. They are checked for real
+//! once the blocks are downloaded and validated.
+
+use super::{
+    account::Account,
+    blockchain::Blockchain,
+    cache::cache::Cache,
+    difficulty::block_work,
+    staging::StagedBlocks,
+    structure::{
+        block::Block,
+        block_header::{BlockHeader, HeaderPreviousBlockHash},
+        consts::{BLOCK_HEADER_SIZE, HEADER_PREVIOUS_BLOCK_HASH_SIZE},
+    },
+    validator::{check_header_link, BlockValidator},
+};
+use crate::peers::Peer;
+use nexium::defaults::BLOCKCHAIN_FILE;
+use std::{collections::HashMap, path::PathBuf};
+
+/// Maximum number of headers sent in one response
+pub const MAX_HEADERS: usize = 2000;
+/// Maximum number of blocks sent in one response
+pub const MAX_BLOCKS: usize = 500;
+/// Maximum size of the blocks sent in one response, a larger block is
+/// still sent alone
+pub const MAX_BLOCKS_SIZE: usize = 4 * 1024 * 1024;
+/// Number of most recent blocks in a locator before the steps double
+const LOCATOR_DENSE: u64 = 10;
+const HEADER_ENTRY_SIZE: usize =
+    HEADER_PREVIOUS_BLOCK_HASH_SIZE + BLOCK_HEADER_SIZE;
+
+/// A block header and the hash of its block
+pub struct HeaderEntry {
+    pub hash: HeaderPreviousBlockHash,
+    pub header: BlockHeader,
+}
+
+pub fn encode_headers(entries: &[HeaderEntry]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(entries.len() * HEADER_ENTRY_SIZE);
+    for e in entries {
+        res.extend_from_slice(&e.hash);
+        res.extend_from_slice(&e.header.to_buffer());
+    }
+    res
+}
+
+pub fn decode_headers(data: &[u8]) -> Result<Vec<HeaderEntry>, String> {
+    if !data.len().is_multiple_of(HEADER_ENTRY_SIZE) {
+        return Err("Truncated headers".to_string());
+    }
+
+    let entries = data
+        .chunks(HEADER_ENTRY_SIZE)
+        .map(|chunk| {
+            let (hash, header) =
+                chunk.split_at(HEADER_PREVIOUS_BLOCK_HASH_SIZE);
+            HeaderEntry {
+                hash: hash.try_into().unwrap(),
+                header: BlockHeader::from_buff(header.try_into().unwrap()),
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+pub fn decode_blocks(data: &[u8]) -> Result<Vec<Block>, String> {
+    let mut blocks = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let block = Block::from_buffer(&data[offset..])?;
+        offset += block.size() as usize;
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Heights and hashes of local blocks to ask the peer about, from the tip
+/// down to the first block, the steps doubling after the most recent ones
+fn locator(bc: &Blockchain) -> Vec<(u64, HeaderPreviousBlockHash)> {
+    let mut res = vec![];
+    let mut height = match bc.hashes.len() {
+        0 => return res,
+        n => n as u64 - 1,
+    };
+
+    let mut step = 1;
+    loop {
+        res.push((height, bc.hashes[height as usize]));
+        if height == 0 {
+            return res;
+        }
+        if res.len() as u64 >= LOCATOR_DENSE {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+}
+
+/// Number of blocks shared with the peer and the first headers following
+/// them
+async fn find_fork(
+    peer: &Peer,
+    bc: &Blockchain,
+) -> Result<(u64, Vec<HeaderEntry>), String> {
+    for (height, hash) in locator(bc) {
+        if let Some(headers) = peer.get_headers(Some(&hash)).await? {
+            return Ok((height + 1, headers));
+        }
+    }
+
+    match peer.get_headers(None).await? {
+        Some(headers) => Ok((0, headers)),
+        None => Err("Peer has no chain".to_string()),
+    }
+}
+
+/// Cumulative work of the chain of the peer, made of the first `common`
+/// local blocks followed by `headers` and the headers after them
+async fn peer_chain_work(
+    peer: &Peer,
+    bc: &mut Blockchain,
+    common: u64,
+    mut headers: Vec<HeaderEntry>,
+) -> Result<u128, String> {
+    let (mut last_hash, mut last_header) = bc.block_before(common)?;
+    let mut window = bc.window_at(common)?;
+    let mut height = common;
+    let mut work = bc.work_at(common);
+
+    loop {
+        for e in headers.iter() {
+            if let Err(e) = check_header_link(
+                &last_hash,
+                last_header.as_ref(),
+                &window,
+                &e.header,
+                &e.hash,
+            ) {
+                return Err(format!("Invalid header {}: {}", height, e));
+            }
+            window.push(&e.header);
+            last_hash = e.hash;
+            last_header = Some(e.header);
+            height += 1;
+            work += block_work(&e.header);
+        }
+
+        if headers.len() < MAX_HEADERS {
+            return Ok(work);
+        }
+        headers = match peer.get_headers(Some(&last_hash)).await? {
+            Some(h) => h,
+            None => return Err("Peer chain changed during sync".to_string()),
+        };
+    }
+}
+
+/// Next batch of blocks of the peer after the block `from`
+async fn next_blocks(
+    peer: &Peer,
+    from: Option<HeaderPreviousBlockHash>,
+) -> Result<Vec<Block>, String> {
+    match peer.get_blocks(from.as_ref()).await? {
+        Some(blocks) => Ok(blocks),
+        None => Err("Peer chain changed during sync".to_string()),
+    }
+}
+
+/// Append the blocks of the peer following the local tip
+async fn extend(
+    peer: &Peer,
+    bc: &mut Blockchain,
+    cache: &mut Cache,
+) -> Result<u64, String> {
+    let mut ledger: HashMap<String, Account> = HashMap::new();
+    let mut added = 0;
+
+    loop {
+        let blocks = next_blocks(peer, bc.tip()).await?;
+        if blocks.is_empty() {
+            return Ok(added);
+        }
+
+        for block in blocks.iter() {
+            let height = bc.hashes.len();
+            let mut validator = BlockValidator::new(
+                cache,
+                bc.last_hash,
+                bc.last_header,
+                bc.window.clone(),
+            );
+            let accounts = match validator
+                .validate(block, |login| match ledger.get(login) {
+                    Some(a) => Ok(*a),
+                    None => bc.get_account(login),
+                })
+                .await
+            {
+                Ok(a) => a,
+                Err(e) => {
+                    return Err(format!("Invalid block {}: {}", height, e))
+                }
+            };
+            ledger.extend(accounts);
+            bc.append_synced_block(block)?;
+            added += 1;
+        }
+    }
+}
+
+/// Replace the local blocks after the first `common` ones with the branch
+/// of the peer. The branch is validated and written to a staging file
+/// first, the local chain is only touched if the whole branch is valid and
+/// has more work.
+async fn reorganise(
+    peer: &Peer,
+    bc: &mut Blockchain,
+    cache: &mut Cache,
+    common: u64,
+) -> Result<u64, String> {
+    let path = format!("{}.sync", BLOCKCHAIN_FILE);
+    let mut staging = StagedBlocks::create(PathBuf::from(path))?;
+
+    let (last_hash, last_header) = bc.block_before(common)?;
+    let window = bc.window_at(common)?;
+    let mut validator =
+        BlockValidator::new(cache, last_hash, last_header, window);
+    let mut ledger: HashMap<String, Account> = HashMap::new();
+    // The local blocks after the fork are undone once for every account
+    let fork_accounts = bc.accounts_at(common)?;
+    let mut count = 0;
+    let mut work = bc.work_at(common);
+
+    loop {
+        let from = match common + count {
+            0 => None,
+            _ => Some(validator.last_hash),
+        };
+        let blocks = next_blocks(peer, from).await?;
+        if blocks.is_empty() {
+            break;
+        }
+
+        for block in blocks.iter() {
+            let accounts = match validator
+                .validate(block, |login| {
+                    match ledger.get(login).or(fork_accounts.get(login)) {
+                        Some(a) => Ok(*a),
+                        None => bc.get_account(login),
+                    }
+                })
+                .await
+            {
+                Ok(a) => a,
+                Err(e) => {
+                    return Err(format!(
+                        "Invalid block {}: {}",
+                        common + count,
+                        e
+                    ));
+                }
+            };
+            ledger.extend(accounts);
+
+            staging.push(&block.to_buffer())?;
+            count += 1;
+            work += block_work(&block.header);
+        }
+    }
+
+    if work <= bc.chain_work() {
+        return Ok(0);
+    }
+
+    let mut disconnected = bc.disconnect(common)?;
+    for i in 0..count {
+        let res = staging
+            .get(i)
+            .and_then(|block| bc.append_synced_block(&block));
+        if let Err(e) = res {
+            bc.restore(common, &mut disconnected)?;
+            return Err(e);
+        }
+    }
+    bc.reorganised(common, disconnected, count);
+    Ok(count)
+}
+
+/// Bring the local chain up to date with the chain of `peer`, if it has
+/// more work. Returns the number of blocks added.
+pub async fn sync_with_peer(
+    peer: &Peer,
+    bc: &mut Blockchain,
+    cache: &mut Cache,
+) -> Result<u64, String> {
+    let (common, headers) = find_fork(peer, bc).await?;
+    let work = peer_chain_work(peer, bc, common, headers).await?;
+    if work <= bc.chain_work() {
+        return Ok(0);
+    }
+
+    if common == bc.hashes.len() as u64 {
+        extend(peer, bc, cache).await
+    } else {
+        reorganise(peer, bc, cache, common).await
+    }
+}
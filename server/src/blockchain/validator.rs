@@ -0,0 +1,531 @@
+use super::{
+    account::{allows_overdraft, Account},
+    cache::cache::{Cache, TransactionCheckError},
+    difficulty::{is_valid_compact, meets_difficulty, DifficultyWindow},
+    reward::max_reward,
+    structure::{
+        block::Block,
+        block_header::{BlockHeader, HeaderPreviousBlockHash},
+    },
+};
+use nexium::{
+    blockchain::{
+        amount::Amount, consts::TRANSACTION_HEADER_VERSION,
+        transaction::transaction_vec_size, transaction_data::TransactionData,
+    },
+    defaults::{
+        BLOCK_VERSION, COINBASE_BLOCK_VERSION, COMPACT_TARGET_BLOCK_VERSION,
+        LEGACY_DIFFICULTY, NONCE_BLOCK_VERSION, PKCS1_SIGNATURE_BLOCK_VERSION,
+    },
+    rsa::signature::SignatureScheme,
+    utils::time::current_time,
+};
+use std::{collections::HashMap, fmt};
+
+/// How far in the future (in seconds) a block or transaction timestamp
+/// can be, to tolerate clock differences between nodes
+pub const MAX_FUTURE_DRIFT: u32 = 2 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockValidationError {
+    PreviousHashMismatch,
+    UnsupportedVersion(u16),
+    VersionDowngrade {
+        previous: u16,
+        block: u16,
+    },
+    InvalidDifficulty(u32),
+    InsufficientProofOfWork,
+    InvalidMerkleRoot,
+    InvalidTransactionsSize {
+        header: u32,
+        actual: u32,
+    },
+    EmptyBlock,
+    MissingCoinbase,
+    TimestampInFuture(u32),
+    TimestampBeforePrevious {
+        previous: u32,
+        block: u32,
+    },
+    DuplicateTransaction(usize),
+    InvalidTransactionData(usize),
+    InvalidTransactionTimestamp(usize),
+    UnsupportedTransactionVersion(usize),
+    InvalidSignature(usize),
+    RawSignature(usize),
+    /// The keys of the emitter could not be fetched, the block may be valid
+    KeysUnavailable(usize, String),
+    MissingNonce(usize),
+    ReplayedTransaction(usize),
+    OutOfOrderNonce(usize),
+    InvalidAmount(usize),
+    SelfTransfer(usize),
+    InsufficientBalance(usize),
+    BalanceOverflow(usize),
+    BalanceUnavailable(usize, String),
+    MisplacedCoinbase(usize),
+    InvalidCoinbaseHeight(usize),
+    ExcessiveReward {
+        max: Amount,
+        reward: Amount,
+    },
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreviousHashMismatch => {
+                write!(f, "Block does not connect to chain")
+            }
+            Self::UnsupportedVersion(v) => {
+                write!(f, "Unsupported block version {}", v)
+            }
+            Self::VersionDowngrade { previous, block } => write!(
+                f,
+                "Block version {} is lower than previous block version {}",
+                block, previous
+            ),
+            Self::InvalidDifficulty(d) => {
+                write!(f, "Invalid difficulty target {}", d)
+            }
+            Self::InsufficientProofOfWork => {
+                write!(f, "Block hash does not meet the difficulty target")
+            }
+            Self::InvalidMerkleRoot => write!(f, "Invalid merkle root"),
+            Self::InvalidTransactionsSize { header, actual } => write!(
+                f,
+                "Invalid transactions size (header {}, actual {})",
+                header, actual
+            ),
+            Self::EmptyBlock => write!(f, "Block has no transaction"),
+            Self::MissingCoinbase => {
+                write!(f, "Block does not start with its coinbase")
+            }
+            Self::TimestampInFuture(t) => {
+                write!(f, "Block timestamp {} is in the future", t)
+            }
+            Self::TimestampBeforePrevious { previous, block } => write!(
+                f,
+                "Block timestamp {} is before previous block timestamp {}",
+                block, previous
+            ),
+            Self::DuplicateTransaction(i) => {
+                write!(f, "Transaction {} is duplicated", i)
+            }
+            Self::InvalidTransactionData(i) => {
+                write!(f, "Transaction {} has invalid data", i)
+            }
+            Self::InvalidTransactionTimestamp(i) => {
+                write!(f, "Transaction {} has an invalid timestamp", i)
+            }
+            Self::UnsupportedTransactionVersion(i) => {
+                write!(f, "Transaction {} has an unsupported version", i)
+            }
+            Self::InvalidSignature(i) => {
+                write!(f, "Transaction {} has an invalid signature", i)
+            }
+            Self::RawSignature(i) => {
+                write!(f, "Transaction {} is signed without padding", i)
+            }
+            Self::KeysUnavailable(i, e) => write!(
+                f,
+                "Failed to get the keys of the emitter of transaction {}: {}",
+                i, e
+            ),
+            Self::MissingNonce(i) => {
+                write!(f, "Transaction {} has no nonce", i)
+            }
+            Self::ReplayedTransaction(i) => {
+                write!(f, "Transaction {} reuses a nonce", i)
+            }
+            Self::OutOfOrderNonce(i) => {
+                write!(f, "Transaction {} has an out of order nonce", i)
+            }
+            Self::InvalidAmount(i) => {
+                write!(f, "Transaction {} has an invalid amount", i)
+            }
+            Self::SelfTransfer(i) => {
+                write!(f, "Transaction {} sends money to its emitter", i)
+            }
+            Self::InsufficientBalance(i) => {
+                write!(f, "Transaction {} emitter has insufficient balance", i)
+            }
+            Self::BalanceOverflow(i) => {
+                write!(f, "Transaction {} overflows a balance", i)
+            }
+            Self::BalanceUnavailable(i, e) => {
+                write!(f, "Failed to get balances of transaction {}: {}", i, e)
+            }
+            Self::MisplacedCoinbase(i) => {
+                write!(f, "Transaction {} is a misplaced coinbase", i)
+            }
+            Self::InvalidCoinbaseHeight(i) => {
+                write!(f, "Coinbase {} has an invalid height", i)
+            }
+            Self::ExcessiveReward { max, reward } => write!(
+                f,
+                "Coinbase reward {} is more than the allowed {}",
+                reward, max
+            ),
+        }
+    }
+}
+
+impl BlockValidationError {
+    /// Whether the check failed because of the node rather than the block,
+    /// which must then be checked again later instead of being dropped
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::KeysUnavailable(..) | Self::BalanceUnavailable(..)
+        )
+    }
+}
+
+/// Latest version of the transaction headers allowed in a block of
+/// `version`: the headers with a nonce came with NONCE_BLOCK_VERSION
+pub fn max_transaction_version(version: u16) -> u8 {
+    if version < NONCE_BLOCK_VERSION {
+        0
+    } else {
+        TRANSACTION_HEADER_VERSION
+    }
+}
+
+/// Check that the block of hash `hash` has a valid difficulty and meets
+/// it, so that a block can't be stored before being validated without some
+/// work
+pub fn check_proof_of_work(
+    header: &BlockHeader,
+    hash: &HeaderPreviousBlockHash,
+) -> Result<(), BlockValidationError> {
+    let valid = if header.version < COMPACT_TARGET_BLOCK_VERSION {
+        header.difficulty_target == LEGACY_DIFFICULTY
+    } else {
+        is_valid_compact(header.difficulty_target)
+    };
+    if !valid {
+        return Err(BlockValidationError::InvalidDifficulty(
+            header.difficulty_target,
+        ));
+    }
+
+    if !meets_difficulty(header, hash) {
+        return Err(BlockValidationError::InsufficientProofOfWork);
+    }
+    Ok(())
+}
+
+/// Check the rules of a header that don't need the transactions, for a
+/// block of hash `hash` appended after `last_hash`, whose header is
+/// `last_header`, `window` being the window of the chain up to it
+pub fn check_header_link(
+    last_hash: &HeaderPreviousBlockHash,
+    last_header: Option<&BlockHeader>,
+    window: &DifficultyWindow,
+    header: &BlockHeader,
+    hash: &HeaderPreviousBlockHash,
+) -> Result<(), BlockValidationError> {
+    if header.previous_block_hash != *last_hash {
+        return Err(BlockValidationError::PreviousHashMismatch);
+    }
+
+    if header.version == 0 || header.version > BLOCK_VERSION {
+        return Err(BlockValidationError::UnsupportedVersion(header.version));
+    }
+
+    if header.difficulty_target != window.expected(last_header, header.version)
+    {
+        return Err(BlockValidationError::InvalidDifficulty(
+            header.difficulty_target,
+        ));
+    }
+    check_proof_of_work(header, hash)?;
+
+    if header.timestamp > current_time().saturating_add(MAX_FUTURE_DRIFT) {
+        return Err(BlockValidationError::TimestampInFuture(header.timestamp));
+    }
+
+    if let Some(last) = last_header {
+        if header.timestamp < last.timestamp {
+            return Err(BlockValidationError::TimestampBeforePrevious {
+                previous: last.timestamp,
+                block: header.timestamp,
+            });
+        }
+
+        // Otherwise transactions without nonce could be replayed in
+        // blocks of an older version
+        if header.version < last.version {
+            return Err(BlockValidationError::VersionDowngrade {
+                previous: last.version,
+                block: header.version,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every consensus rule on blocks appended after `last_hash`, whose
+/// header is `last_header` (None for an empty chain), and `window` the
+/// difficulty window of the chain up to it. On success the
+/// validator moves to the validated block, so a whole chain can be
+/// validated by feeding its blocks in order.
+pub struct BlockValidator<'a> {
+    cache: &'a mut Cache,
+    pub last_hash: HeaderPreviousBlockHash,
+    pub last_header: Option<BlockHeader>,
+    pub window: DifficultyWindow,
+    /// Whether the signatures of the transactions are checked
+    check_signatures: bool,
+}
+
+impl<'a> BlockValidator<'a> {
+    pub fn new(
+        cache: &'a mut Cache,
+        last_hash: HeaderPreviousBlockHash,
+        last_header: Option<BlockHeader>,
+        window: DifficultyWindow,
+    ) -> Self {
+        Self {
+            cache,
+            last_hash,
+            last_header,
+            window,
+            check_signatures: true,
+        }
+    }
+
+    /// Don't check the signatures of the transactions, for the blocks of
+    /// the local chain, whose signatures were checked when they were added
+    pub fn without_signatures(mut self) -> Self {
+        self.check_signatures = false;
+        self
+    }
+
+    fn check_header(&self, block: &Block) -> Result<(), BlockValidationError> {
+        check_header_link(
+            &self.last_hash,
+            self.last_header.as_ref(),
+            &self.window,
+            &block.header,
+            &block.double_hash(),
+        )?;
+
+        if block.transactions.is_empty() {
+            return Err(BlockValidationError::EmptyBlock);
+        }
+        if block.header.version >= COINBASE_BLOCK_VERSION {
+            if !block.transactions[0].is_coinbase() {
+                return Err(BlockValidationError::MissingCoinbase);
+            }
+            // Blocks are only mined for the transactions of the users
+            if block.transactions.len() == 1 {
+                return Err(BlockValidationError::EmptyBlock);
+            }
+        }
+
+        let actual = transaction_vec_size(&block.transactions);
+        if block.header.transactions_size != actual {
+            return Err(BlockValidationError::InvalidTransactionsSize {
+                header: block.header.transactions_size,
+                actual,
+            });
+        }
+
+        if !block.check_merkle_root() {
+            return Err(BlockValidationError::InvalidMerkleRoot);
+        }
+
+        Ok(())
+    }
+
+    /// Check the coinbase of `block` at `index`, claiming `reward` for the
+    /// block at `height`
+    fn check_coinbase(
+        &self,
+        block: &Block,
+        index: usize,
+        height: u64,
+        reward: Amount,
+    ) -> Result<(), BlockValidationError> {
+        if index != 0 || block.header.version < COINBASE_BLOCK_VERSION {
+            return Err(BlockValidationError::MisplacedCoinbase(index));
+        }
+        if height != self.window.height() {
+            return Err(BlockValidationError::InvalidCoinbaseHeight(index));
+        }
+
+        let max = match max_reward(height, &block.transactions[1..]) {
+            Some(m) => m,
+            None => return Err(BlockValidationError::InvalidAmount(index)),
+        };
+        if reward > max {
+            return Err(BlockValidationError::ExcessiveReward { max, reward });
+        }
+        Ok(())
+    }
+
+    /// Validate `block`, reading the accounts before the block with
+    /// `account_of`. Returns the state of the accounts touched by the
+    /// block once it is applied.
+    pub async fn validate<F>(
+        &mut self,
+        block: &Block,
+        mut account_of: F,
+    ) -> Result<HashMap<String, Account>, BlockValidationError>
+    where
+        F: FnMut(&str) -> Result<Account, String>,
+    {
+        self.check_header(block)?;
+
+        let max_tr_timestamp =
+            block.header.timestamp.saturating_add(MAX_FUTURE_DRIFT);
+        let mut accounts: HashMap<String, Account> = HashMap::new();
+
+        for (i, tr) in block.transactions.iter().enumerate() {
+            if block.transactions[..i]
+                .iter()
+                .any(|t| t.signature == tr.signature)
+            {
+                return Err(BlockValidationError::DuplicateTransaction(i));
+            }
+
+            if tr.header.timestamp > max_tr_timestamp {
+                return Err(BlockValidationError::InvalidTransactionTimestamp(
+                    i,
+                ));
+            }
+
+            if tr.header.version > max_transaction_version(block.header.version)
+            {
+                return Err(
+                    BlockValidationError::UnsupportedTransactionVersion(i),
+                );
+            }
+            if block.header.version >= PKCS1_SIGNATURE_BLOCK_VERSION
+                && tr.header.signature_scheme != SignatureScheme::Pkcs1v15Sha256
+            {
+                return Err(BlockValidationError::RawSignature(i));
+            }
+            let checked = if self.check_signatures {
+                self.cache.check_transaction(tr).await.map(|_| ())
+            } else {
+                Ok(())
+            };
+            match checked {
+                Ok(_) => (),
+                Err(TransactionCheckError::MalformedData) => {
+                    return Err(BlockValidationError::InvalidTransactionData(i))
+                }
+                Err(TransactionCheckError::KeysUnavailable(e)) => {
+                    return Err(BlockValidationError::KeysUnavailable(i, e))
+                }
+                Err(_) => {
+                    return Err(BlockValidationError::InvalidSignature(i))
+                }
+            };
+
+            let data = match tr.get_data() {
+                Ok(d) => d,
+                Err(_) => {
+                    return Err(BlockValidationError::InvalidTransactionData(i))
+                }
+            };
+
+            let mut account = |login: &String| match accounts.get(login) {
+                Some(a) => Ok(*a),
+                None => account_of(login).map_err(|e| {
+                    BlockValidationError::BalanceUnavailable(i, e)
+                }),
+            };
+
+            let em = tr.header.get_login();
+            let mut ae = account(&em)?;
+
+            // Headers without nonce are only valid in the older blocks, even
+            // for the coinbase which doesn't use it
+            if !tr.header.has_nonce()
+                && block.header.version >= NONCE_BLOCK_VERSION
+            {
+                return Err(BlockValidationError::MissingNonce(i));
+            }
+            if tr.is_coinbase() {
+                // The coinbase uses no nonce of the miner
+            } else if tr.header.has_nonce() {
+                if tr.header.nonce < ae.nonce {
+                    return Err(BlockValidationError::ReplayedTransaction(i));
+                }
+                if tr.header.nonce > ae.nonce {
+                    return Err(BlockValidationError::OutOfOrderNonce(i));
+                }
+                ae.nonce += 1;
+            }
+
+            let (receiver, amount) = match data {
+                TransactionData::ClassicTransaction {
+                    receiver,
+                    amount,
+                    ..
+                } => (receiver, amount),
+                TransactionData::Coinbase { height, reward } => {
+                    self.check_coinbase(block, i, height, reward)?;
+                    ae = match ae.credited(reward) {
+                        Some(a) => a,
+                        None => {
+                            return Err(BlockValidationError::BalanceOverflow(
+                                i,
+                            ))
+                        }
+                    };
+                    accounts.insert(em, ae);
+                    continue;
+                }
+                // Other transaction types don't move money
+                TransactionData::Unknown { .. } => {
+                    accounts.insert(em, ae);
+                    continue;
+                }
+            };
+
+            if amount.is_zero() {
+                return Err(BlockValidationError::InvalidAmount(i));
+            }
+
+            let r = String::from_utf8_lossy(&receiver)
+                .trim_end_matches('\0')
+                .to_string();
+
+            if em == r {
+                return Err(BlockValidationError::SelfTransfer(i));
+            }
+
+            let mut ar = account(&r)?;
+
+            let total_cost = match tr.total_cost() {
+                Some(c) => c,
+                None => return Err(BlockValidationError::InvalidAmount(i)),
+            };
+            let overdraft = allows_overdraft(block.header.version);
+            ae = match ae.debited(total_cost, overdraft) {
+                Some(a) => a,
+                None => {
+                    return Err(BlockValidationError::InsufficientBalance(i))
+                }
+            };
+            ar = match ar.credited(amount) {
+                Some(a) => a,
+                None => return Err(BlockValidationError::BalanceOverflow(i)),
+            };
+            accounts.insert(em, ae);
+            accounts.insert(r, ar);
+        }
+
+        self.last_hash = block.double_hash();
+        self.last_header = Some(block.header);
+        self.window.push(&block.header);
+        Ok(accounts)
+    }
+}
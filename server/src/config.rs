@@ -5,6 +5,134 @@ use std::fs;
 use std::io;
 use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+const DEFAULT_MAX_HEADERS: usize = 64;
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_REQUEST_TIMEOUT: u64 = 30;
+const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+const DEFAULT_MAX_BLOCK_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_BLOCK_TRANSACTIONS: usize = 1000;
+
+/// Limits applied by the server to the incoming HTTP requests
+#[derive(Debug, Clone, Copy)]
+pub struct HttpLimits {
+    /// Maximum size of the request line and headers, in bytes
+    pub max_header_size: usize,
+    /// Maximum number of header lines
+    pub max_headers: usize,
+    /// Maximum size of the decoded body, in bytes
+    pub max_body_size: usize,
+    /// Time allowed to receive a whole request once it started
+    pub request_timeout: Duration,
+    /// Time an idle connection is kept open waiting for another request
+    pub keep_alive_timeout: Duration,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        Self {
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT),
+            keep_alive_timeout: Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT),
+        }
+    }
+}
+
+impl HttpLimits {
+    /// Read the limits from the `http` object of the config file, missing
+    /// fields keep their default value
+    fn from_json(value: &json::JsonValue) -> Self {
+        let default = Self::default();
+        let secs = |v: &json::JsonValue, d: Duration| match v.as_u64() {
+            Some(s) => Duration::from_secs(s),
+            None => d,
+        };
+
+        Self {
+            max_header_size: value["max_header_size"]
+                .as_usize()
+                .unwrap_or(default.max_header_size),
+            max_headers: value["max_headers"]
+                .as_usize()
+                .unwrap_or(default.max_headers),
+            max_body_size: value["max_body_size"]
+                .as_usize()
+                .unwrap_or(default.max_body_size),
+            request_timeout: secs(
+                &value["request_timeout"],
+                default.request_timeout,
+            ),
+            keep_alive_timeout: secs(
+                &value["keep_alive_timeout"],
+                default.keep_alive_timeout,
+            ),
+        }
+    }
+
+    fn to_json(self) -> json::JsonValue {
+        let mut obj = json::JsonValue::new_object();
+        obj["max_header_size"] = self.max_header_size.into();
+        obj["max_headers"] = self.max_headers.into();
+        obj["max_body_size"] = self.max_body_size.into();
+        obj["request_timeout"] = self.request_timeout.as_secs().into();
+        obj["keep_alive_timeout"] = self.keep_alive_timeout.as_secs().into();
+        obj
+    }
+}
+
+/// How the miner builds blocks
+#[derive(Debug, Clone, Copy)]
+pub struct BlockProduction {
+    /// Maximum size of a block, header included, in bytes
+    pub max_block_size: usize,
+    /// Maximum number of transactions in a block
+    pub max_transactions: usize,
+    /// Time after the last block before a block that is not full is mined
+    pub block_interval: Duration,
+}
+
+impl Default for BlockProduction {
+    fn default() -> Self {
+        Self {
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            max_transactions: DEFAULT_MAX_BLOCK_TRANSACTIONS,
+            block_interval: Duration::from_secs(TARGET_BLOCK_TIME as u64),
+        }
+    }
+}
+
+impl BlockProduction {
+    /// Read the settings from the `mining` object of the config file,
+    /// missing fields keep their default value
+    fn from_json(value: &json::JsonValue) -> Self {
+        let default = Self::default();
+
+        Self {
+            max_block_size: value["max_block_size"]
+                .as_usize()
+                .unwrap_or(default.max_block_size),
+            max_transactions: value["max_transactions"]
+                .as_usize()
+                .unwrap_or(default.max_transactions),
+            block_interval: match value["block_interval"].as_u64() {
+                Some(s) => Duration::from_secs(s),
+                None => default.block_interval,
+            },
+        }
+    }
+
+    fn to_json(self) -> json::JsonValue {
+        let mut obj = json::JsonValue::new_object();
+        obj["max_block_size"] = self.max_block_size.into();
+        obj["max_transactions"] = self.max_transactions.into();
+        obj["block_interval"] = self.block_interval.as_secs().into();
+        obj
+    }
+}
 
 /// Config struct to hold the configuration of the server
 
@@ -22,6 +150,10 @@ pub struct Config {
     pub user_login: String,
     /// Gitlab Token for the user
     pub gitlab_token: String,
+    /// Limits of the HTTP server
+    pub http: HttpLimits,
+    /// Settings of the miner
+    pub mining: BlockProduction,
 }
 
 impl Config {
@@ -104,6 +236,8 @@ impl Config {
             port,
             user_login,
             gitlab_token,
+            http: HttpLimits::default(),
+            mining: BlockProduction::default(),
         };
 
         res.to_file(path);
@@ -145,6 +279,8 @@ impl Config {
                 .expect("Config read: Port is not a number"),
             user_login: parsed["user_id"].to_string(),
             gitlab_token,
+            http: HttpLimits::from_json(&parsed["http"]),
+            mining: BlockProduction::from_json(&parsed["mining"]),
         }
     }
 
@@ -157,6 +293,8 @@ impl Config {
         config_obj["port"] = self.port.into();
         config_obj["user_id"] = self.user_login.to_string().into();
         config_obj["gitlab_token"] = self.gitlab_token.to_string().into();
+        config_obj["http"] = self.http.to_json();
+        config_obj["mining"] = self.mining.to_json();
         fs::write(path, config_obj.pretty(4).as_bytes())
             .expect("Error writing config file");
     }
@@ -3,7 +3,7 @@ mod config;
 mod network;
 mod peers;
 
-use blockchain::blockchain::Blockchain;
+use blockchain::{blockchain::Blockchain, cache::cache::Cache, sync};
 use colored::Colorize;
 use config::Config;
 use network::server::Server;
@@ -19,6 +19,7 @@ use tokio;
 const HELP_ARG: &str = "--help";
 const GEN_CONFIG_ARG: &str = "--generate-config";
 const GEN_KEY_ARG: &str = "--generate-key";
+const REPAIR_ARG: &str = "--repair";
 const DEFAULT_CONFIG_NAME: &str = "config.json";
 
 #[tokio::main]
@@ -44,11 +45,12 @@ async fn main() {
         match args[1].as_str() {
             HELP_ARG | "-h" => {
                 println!(
-                    "Usage: {} [options]\n\nOptions:\n  {}: Show this help message\n  {}: Generate the config file\n  {}: Generate a new key",
+                    "Usage: {} [options]\n\nOptions:\n  {}: Show this help message\n  {}: Generate the config file\n  {}: Generate a new key\n  {}: Cut the blockchain file before its first damaged or invalid block and start",
                     args[0].cyan().bold(),
                     HELP_ARG.cyan().bold(),
                     GEN_CONFIG_ARG.cyan().bold(),
-                    GEN_KEY_ARG.cyan().bold()
+                    GEN_KEY_ARG.cyan().bold(),
+                    REPAIR_ARG.cyan().bold()
                 );
                 return;
             }
@@ -183,6 +185,7 @@ async fn main() {
                 println!("New key generated successfully");
                 return;
             }
+            REPAIR_ARG => {}
             _ => {
                 eprintln!(
                     "Unknown argument: {}. Use {} for help.",
@@ -212,7 +215,7 @@ async fn main() {
         }
     }
 
-    let key = match KeyPair::priv_from_file(
+    let mut key = match KeyPair::priv_from_file(
         &config.key_filepath,
         &config.user_login,
         &config.key_password,
@@ -223,8 +226,13 @@ async fn main() {
             return;
         }
     };
+    // Peers and clients choose the envelopes the server decrypts
+    key.set_blinding(true);
 
-    let mut blockchain = match Blockchain::init(gitlab.clone()) {
+    let mut cache = Cache::new(gitlab);
+
+    let repair = args.get(1).is_some_and(|a| a == REPAIR_ARG);
+    let mut blockchain = match Blockchain::init(&mut cache, repair).await {
         Ok(b) => b,
         Err(e) => {
             eprintln!("Failed to create blockchain: {}", e);
@@ -235,6 +243,7 @@ async fn main() {
     // Load and discover peers
     let mut peer_list = PeerList::load();
     let our_block_count = blockchain.cache.len() as u64;
+    let our_work = blockchain.chain_work();
 
     if !peer_list.peers.is_empty() {
         let (discovered, best_peer) = peer_list
@@ -247,46 +256,21 @@ async fn main() {
 
         // Sync blockchain if needed
         if let Some((peer, peer_info)) = best_peer {
-            if our_block_count == 0 && peer_info.block_count > 0 {
-                // We have no blockchain, download from peer
-                println!(
-                    "Downloading blockchain from {} ({} blocks)...",
-                    peer.url().cyan(),
-                    peer_info.block_count
-                );
-                match peer.download_blockchain().await {
-                    Ok(data) => {
-                        match blockchain.replace_from_data(&data) {
-                            Ok(_) => println!(
-                                "{} Blockchain synchronized ({} blocks)",
-                                "OK".green(),
-                                blockchain.cache.len()
-                            ),
-                            Err(e) => eprintln!("{} Failed to load blockchain: {}", "ERROR".red(), e),
-                        }
-                    }
-                    Err(e) => eprintln!("{} Failed to download blockchain: {}", "ERROR".red(), e),
-                }
-            } else if peer_info.block_count > our_block_count {
-                // Peer has longer chain, adopt it
+            if peer_info.work() > our_work {
                 println!(
-                    "Peer {} has longer chain ({} vs {}), syncing...",
+                    "Syncing blockchain with {} ({} vs {} blocks)...",
                     peer.url().cyan(),
                     peer_info.block_count,
                     our_block_count
                 );
-                match peer.download_blockchain().await {
-                    Ok(data) => {
-                        match blockchain.replace_from_data(&data) {
-                            Ok(_) => println!(
-                                "{} Blockchain synchronized ({} blocks)",
-                                "OK".green(),
-                                blockchain.cache.len()
-                            ),
-                            Err(e) => eprintln!("{} Failed to load blockchain: {}", "ERROR".red(), e),
-                        }
-                    }
-                    Err(e) => eprintln!("{} Failed to download blockchain: {}", "ERROR".red(), e),
+                match sync::sync_with_peer(&peer, &mut blockchain, &mut cache).await {
+                    Ok(added) => println!(
+                        "{} Blockchain synchronized ({} blocks, {} new)",
+                        "OK".green(),
+                        blockchain.hashes.len(),
+                        added
+                    ),
+                    Err(e) => eprintln!("{} Failed to sync blockchain: {}", "ERROR".red(), e),
                 }
             } else if our_block_count > 0 {
                 println!(
@@ -297,7 +281,10 @@ async fn main() {
         }
     }
 
-    let server = match Server::new(&config, gitlab, key, blockchain, peer_list) {
+    // Transactions still waiting when the server stopped
+    blockchain.restore_mempool(&mut cache).await;
+
+    let server = match Server::new(&config, cache, key, blockchain, peer_list) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Failed to create server: {}", e);
@@ -1,2 +1,2 @@
-mod router;
+pub mod router;
 pub mod server;
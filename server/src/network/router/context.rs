@@ -0,0 +1,106 @@
+use super::http::{error::HttpError, request::Request, response::Response};
+use crate::{
+    blockchain::{blockchain::Blockchain, cache::cache::Cache},
+    peers::PeerList,
+};
+use nexium::{api::error::ErrorCode, rsa::KeyPair};
+use serde_json::json;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Server state shared by all the routes
+#[derive(Clone)]
+pub struct State {
+    pub cache: Arc<Mutex<Cache>>,
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub peer_list: Arc<Mutex<PeerList>>,
+    /// Login of the server owner
+    pub login: String,
+    /// Key of the server
+    pub key: KeyPair,
+    pub self_address: String,
+    pub self_port: u16,
+}
+
+pub type HandlerResult = Result<Response, HttpError>;
+
+/// Everything a route gets to handle a request
+pub struct Context {
+    pub req: Request,
+    pub state: State,
+    /// Key of the client, set by the `Authenticate` middleware
+    pub client_key: Option<KeyPair>,
+    params: HashMap<String, String>,
+}
+
+impl Context {
+    pub fn new(
+        req: Request,
+        state: State,
+        params: HashMap<String, String>,
+    ) -> Self {
+        Self {
+            req,
+            state,
+            client_key: None,
+            params,
+        }
+    }
+
+    /// Path parameter `{name}` of the route
+    pub fn param<T>(&self, name: &str) -> Result<T, HttpError>
+    where
+        T: FromStr,
+    {
+        let value = match self.params.get(name) {
+            Some(v) => v,
+            None => {
+                return Err(HttpError::internal(format!(
+                    "Missing path parameter {}",
+                    name
+                )))
+            }
+        };
+
+        match value.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(HttpError::new(
+                ErrorCode::InvalidParameter,
+                format!("Invalid {}", name),
+            )
+            .with_details(json!({ "name": name }))),
+        }
+    }
+
+    /// Query parameter, `None` if it is missing or can't be parsed
+    pub fn query<T>(&self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+    {
+        match self.req.query.get(name) {
+            Some(v) => v.parse().ok(),
+            None => None,
+        }
+    }
+
+    /// Query parameter, `None` if it is missing, an error if it can't be
+    /// parsed
+    pub fn try_query<T>(&self, name: &str) -> Result<Option<T>, HttpError>
+    where
+        T: FromStr,
+    {
+        let value = match self.req.query.get(name) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        match value.parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(HttpError::new(
+                ErrorCode::InvalidParameter,
+                format!("Invalid {}", name),
+            )
+            .with_details(json!({ "name": name }))),
+        }
+    }
+}
@@ -1,79 +1,88 @@
-use crate::blockchain::{blockchain::Blockchain, cache::cache::Cache};
-use crate::peers::PeerList;
+use crate::config::HttpLimits;
 
 use super::{
-    http::{request::Request, response::Response, status::Status},
+    context::State,
+    http::{connection::Connection, request::Request, response::Response},
+    middleware::{Authenticate, Encrypt, Logger, MapErrors},
     routes::{
-        blockchain_download, blockchain_info, check_nexium, get_balance, get_peers, 
-        get_transactions, get_user_stats, new_transaction, register_peer, sync_block, 
-        sync_transaction
+        blockchain_info, check_nexium, get_balance, get_blocks, get_challenge,
+        get_headers, get_peers, get_pending, get_transaction, get_transactions,
+        get_user_stats, new_transaction, register_peer, sync_block,
+        sync_transaction,
     },
+    routing::{Route, Router},
 };
-use nexium::rsa::KeyPair;
 use std::sync::Arc;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
 
+/// Routes of the server, requests matching none of them get a 404
+pub fn router() -> Router {
+    Router::new()
+        .layer(Logger)
+        .layer(MapErrors)
+        .route(Route::get("/challenge", get_challenge::handler))
+        .route(
+            Route::get("/nexium", check_nexium::handler)
+                .layer(Authenticate)
+                .layer(Encrypt),
+        )
+        .route(Route::get("/peers", get_peers::handler))
+        .route(Route::get("/blockchain_info", blockchain_info::handler))
+        .route(Route::get("/headers", get_headers::handler))
+        .route(Route::get("/blocks", get_blocks::handler))
+        .route(Route::post("/register_peer", register_peer::handler))
+        .route(Route::post("/sync_transaction", sync_transaction::handler))
+        .route(Route::post("/sync_block", sync_block::handler))
+        .route(
+            Route::get("/balance/{login}", get_balance::handler)
+                .layer(Authenticate)
+                .layer(Encrypt),
+        )
+        .route(
+            Route::get("/transactions/{login}", get_transactions::handler)
+                .layer(Authenticate)
+                .layer(Encrypt),
+        )
+        .route(
+            Route::get("/pending/{login}", get_pending::handler)
+                .layer(Authenticate)
+                .layer(Encrypt),
+        )
+        .route(Route::get("/tx/{id}", get_transaction::handler))
+        .route(
+            Route::get("/stats/{login}", get_user_stats::handler)
+                .layer(Authenticate)
+                .layer(Encrypt),
+        )
+        .route(Route::post("/new_transaction", new_transaction::handler))
+}
+
+/// Serve the requests of a connection until it is closed
 pub async fn handler(
     stream: TcpStream,
-    cache: Arc<Mutex<Cache>>,
-    blockchain: Arc<Mutex<Blockchain>>,
-    peer_list: Arc<Mutex<PeerList>>,
-    login: String,
-    key: KeyPair,
-    self_address: String,
-    self_port: u16,
+    limits: HttpLimits,
+    router: Arc<Router>,
+    state: State,
 ) {
-    let req = match Request::from_stream(stream).await {
-        Ok(r) => r,
-        Err((e, stream)) => {
-            let res = Response::new(Status::BadRequest, e);
-            let _ = Request::_send(stream, &res);
-            return;
-        }
-    };
+    let mut connection = Connection::new(stream, limits);
 
-    match (req.method.as_str(), req.path.as_str()) {
-        ("GET", "/nexium") => {
-            check_nexium::handler(req, cache, login, key).await;
-        }
-        ("GET", "/peers") => {
-            get_peers::handler(req, peer_list).await;
-        }
-        ("GET", "/blockchain_info") => {
-            blockchain_info::handler(req, blockchain).await;
-        }
-        ("GET", "/blockchain_download") => {
-            blockchain_download::handler(req, blockchain).await;
-        }
-        ("POST", "/register_peer") => {
-            register_peer::handler(req, peer_list, login, self_address.clone(), self_port).await;
-        }
-        ("POST", "/sync_transaction") => {
-            sync_transaction::handler(req, blockchain, peer_list, self_address, self_port).await;
-        }
-        ("POST", "/sync_block") => {
-            sync_block::handler(req, blockchain).await;
-        }
-        (method, path) if method == "GET" && path.starts_with("/balance/") => {
-            get_balance::handler(req, cache, blockchain).await;
-        }
-        (method, path)
-            if method == "GET" && path.starts_with("/transactions/") =>
-        {
-            get_transactions::handler(req, cache, blockchain).await;
-        }
-        (method, path)
-            if method == "GET" && path.starts_with("/stats/") =>
-        {
-            get_user_stats::handler(req, cache, blockchain).await;
-        }
-        ("POST", "/new_transaction") => {
-            new_transaction::handler(req, cache, blockchain, peer_list, key, self_address, self_port).await;
-        }
-        _ => {
-            let res = Response::new(Status::NotFound, "");
-            let _ = req.send(&res).await;
+    loop {
+        let req = match Request::from_connection(&mut connection).await {
+            Ok(r) => r,
+            Err(e) => {
+                if let Some(error) = e.to_http() {
+                    let res = Response::from(error);
+                    let _ = connection.write_response(&res, false).await;
+                }
+                return;
+            }
+        };
+        let keep_alive = req.keep_alive;
+
+        let res = router.dispatch(req, state.clone()).await;
+        let sent = connection.write_response(&res, keep_alive).await;
+        if sent.is_err() || !keep_alive {
+            return;
         }
-    };
+    }
 }
@@ -0,0 +1,522 @@
+use super::{error::ParseError, response::Response};
+use crate::config::HttpLimits;
+use std::collections::HashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{self, Instant},
+};
+
+const READ_SIZE: usize = 8192;
+/// Maximum length of a chunk size line, extensions included
+const MAX_CHUNK_LINE: usize = 1024;
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Request line and headers of a request
+pub struct RequestHead {
+    pub method: String,
+    pub path_query: String,
+    /// Minor version of HTTP/1.x
+    pub version: u8,
+    /// Header values by lowercased name, repeated headers are joined
+    /// with ", "
+    pub headers: HashMap<String, String>,
+}
+
+impl RequestHead {
+    /// Whether the connection can be reused after the response
+    pub fn keep_alive(&self) -> bool {
+        let connection = match self.headers.get("connection") {
+            Some(c) => c.to_lowercase(),
+            None => return self.version >= 1,
+        };
+        let mut tokens = connection.split(',').map(|t| t.trim());
+        if tokens.clone().any(|t| t == "close") {
+            false
+        } else {
+            self.version >= 1 || tokens.any(|t| t == "keep-alive")
+        }
+    }
+}
+
+/// Client connection, reads the requests one after the other and writes
+/// the responses
+pub struct Connection {
+    stream: TcpStream,
+    /// Bytes received but not parsed yet
+    buffer: Vec<u8>,
+    limits: HttpLimits,
+    /// Deadline of the request being read
+    deadline: Instant,
+    /// IP address of the remote end of the connection
+    pub remote_address: String,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, limits: HttpLimits) -> Self {
+        let remote_address = match stream.peer_addr() {
+            Ok(a) => a.ip().to_string(),
+            Err(_) => String::new(),
+        };
+
+        Self {
+            stream,
+            buffer: Vec::new(),
+            limits,
+            deadline: Instant::now() + limits.request_timeout,
+            remote_address,
+        }
+    }
+
+    /// Read more bytes into the buffer before `deadline`
+    async fn fill(&mut self, deadline: Instant) -> Result<(), ParseError> {
+        let mut chunk = [0; READ_SIZE];
+        let read = time::timeout_at(deadline, self.stream.read(&mut chunk));
+
+        match read.await {
+            Ok(Ok(0)) => Err(ParseError::Closed),
+            Ok(Ok(n)) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(ParseError::Io(e.to_string())),
+            Err(_) => Err(ParseError::Timeout),
+        }
+    }
+
+    /// Wait for the first bytes of the next request, blank lines sent
+    /// between requests are ignored
+    async fn wait_request(&mut self) -> Result<(), ParseError> {
+        let idle = Instant::now() + self.limits.keep_alive_timeout;
+        loop {
+            let blank = self
+                .buffer
+                .iter()
+                .take_while(|b| **b == b'\r' || **b == b'\n')
+                .count();
+            self.buffer.drain(..blank);
+            if !self.buffer.is_empty() {
+                return Ok(());
+            }
+
+            match self.fill(idle).await {
+                Ok(()) => {}
+                Err(ParseError::Timeout) => return Err(ParseError::Closed),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Take `n` bytes from the connection
+    async fn take(&mut self, n: usize) -> Result<Vec<u8>, ParseError> {
+        while self.buffer.len() < n {
+            self.fill(self.deadline).await?;
+        }
+        Ok(self.buffer.drain(..n).collect())
+    }
+
+    /// Take a line from the connection, without its line ending
+    async fn take_line(&mut self, max: usize) -> Result<String, ParseError> {
+        let end = loop {
+            if let Some(i) = self.buffer.iter().position(|b| *b == b'\n') {
+                break i;
+            }
+            if self.buffer.len() > max {
+                return Err(ParseError::BadRequest(String::from(
+                    "Line too long",
+                )));
+            }
+            self.fill(self.deadline).await?;
+        };
+
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        match String::from_utf8(line) {
+            Ok(l) => Ok(l.trim_end_matches(['\r', '\n']).to_string()),
+            Err(_) => Err(ParseError::BadRequest(String::from(
+                "Invalid characters in line",
+            ))),
+        }
+    }
+
+    /// Position of the empty line ending the headers, and the length of
+    /// the head including it
+    fn head_end(&self) -> Option<(usize, usize)> {
+        let buf = &self.buffer;
+        for (i, b) in buf.iter().enumerate() {
+            if *b != b'\n' {
+                continue;
+            }
+            match buf.get(i + 1) {
+                Some(b'\n') => return Some((i, i + 2)),
+                Some(b'\r') if buf.get(i + 2) == Some(&b'\n') => {
+                    return Some((i, i + 3))
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn parse_request_line(line: &str) -> Result<(String, String, u8), String> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        if parts.len() != 3 || parts[0].is_empty() || parts[1].is_empty() {
+            return Err(String::from("Invalid request line"));
+        }
+        if !parts[0].bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(String::from("Invalid method"));
+        }
+
+        let version = match parts[2] {
+            "HTTP/1.0" => 0,
+            "HTTP/1.1" => 1,
+            _ => return Err(String::from("Unsupported HTTP version")),
+        };
+        Ok((parts[0].to_string(), parts[1].to_string(), version))
+    }
+
+    fn parse_header(line: &str) -> Result<(String, String), String> {
+        let (name, value) = match line.split_once(':') {
+            Some(h) => h,
+            None => return Err(format!("Invalid header line: {}", line)),
+        };
+
+        // No whitespace is allowed in the name, nor folded lines
+        if name.is_empty() || name.bytes().any(|b| b <= b' ' || b == 0x7f) {
+            return Err(format!("Invalid header name: {}", name));
+        }
+        Ok((name.to_lowercase(), value.trim().to_string()))
+    }
+
+    /// Read the request line and the headers of the next request
+    pub async fn read_head(&mut self) -> Result<RequestHead, ParseError> {
+        self.wait_request().await?;
+        self.deadline = Instant::now() + self.limits.request_timeout;
+
+        let max = self.limits.max_header_size;
+        let (end, len) = loop {
+            match self.head_end() {
+                Some((_, len)) if len > max => {
+                    return Err(ParseError::HeadersTooLarge)
+                }
+                Some(e) => break e,
+                None if self.buffer.len() > max => {
+                    return Err(ParseError::HeadersTooLarge)
+                }
+                None => self.fill(self.deadline).await?,
+            }
+        };
+
+        let raw: Vec<u8> = self.buffer.drain(..len).collect();
+        let text = match std::str::from_utf8(&raw[..end]) {
+            Ok(t) => t,
+            Err(_) => {
+                return Err(ParseError::BadRequest(String::from(
+                    "Invalid characters in headers",
+                )))
+            }
+        };
+
+        let mut lines = text.split('\n').map(|l| l.trim_end_matches('\r'));
+        let (method, path_query, version) =
+            match Connection::parse_request_line(lines.next().unwrap_or("")) {
+                Ok(r) => r,
+                Err(e) => return Err(ParseError::BadRequest(e)),
+            };
+
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (i, line) in lines.enumerate() {
+            if i >= self.limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = match Connection::parse_header(line) {
+                Ok(h) => h,
+                Err(e) => return Err(ParseError::BadRequest(e)),
+            };
+            headers
+                .entry(name)
+                .and_modify(|v| {
+                    v.push_str(", ");
+                    v.push_str(&value);
+                })
+                .or_insert(value);
+        }
+
+        Ok(RequestHead {
+            method,
+            path_query,
+            version,
+            headers,
+        })
+    }
+
+    /// Parse a Content-Length value, repeated identical values are
+    /// accepted
+    fn content_length(value: &str) -> Result<usize, ParseError> {
+        let mut length = None;
+        for v in value.split(',').map(|v| v.trim()) {
+            let parsed = match v.bytes().all(|b| b.is_ascii_digit()) {
+                true => v.parse::<usize>().ok(),
+                false => None,
+            };
+            match (parsed, length) {
+                (Some(p), None) => length = Some(p),
+                (Some(p), Some(l)) if p == l => {}
+                _ => {
+                    return Err(ParseError::BadRequest(String::from(
+                        "Invalid Content-Length",
+                    )))
+                }
+            }
+        }
+        length.ok_or(ParseError::BadRequest(String::from(
+            "Invalid Content-Length",
+        )))
+    }
+
+    async fn read_chunked(&mut self) -> Result<Vec<u8>, ParseError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.take_line(MAX_CHUNK_LINE).await?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = match size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                true => usize::from_str_radix(size, 16).ok(),
+                false => None,
+            };
+            let size = match size {
+                Some(0) => break,
+                Some(s) => s,
+                None => {
+                    return Err(ParseError::BadRequest(String::from(
+                        "Invalid chunk size",
+                    )))
+                }
+            };
+
+            if size > self.limits.max_body_size - body.len() {
+                return Err(ParseError::PayloadTooLarge);
+            }
+            body.extend(self.take(size).await?);
+            if !self.take_line(2).await?.is_empty() {
+                return Err(ParseError::BadRequest(String::from(
+                    "Invalid chunk end",
+                )));
+            }
+        }
+
+        // Trailers are read and ignored
+        let mut trailers = 0;
+        loop {
+            let line = self.take_line(self.limits.max_header_size).await?;
+            if line.is_empty() {
+                return Ok(body);
+            }
+            trailers += line.len();
+            if trailers > self.limits.max_header_size {
+                return Err(ParseError::HeadersTooLarge);
+            }
+        }
+    }
+
+    /// Read the body of the request, with the length given by its
+    /// Content-Length or chunked Transfer-Encoding
+    pub async fn read_body(
+        &mut self,
+        head: &RequestHead,
+    ) -> Result<Vec<u8>, ParseError> {
+        let transfer_encoding = head.headers.get("transfer-encoding");
+        let content_length = head.headers.get("content-length");
+
+        let length = match (transfer_encoding, content_length) {
+            (Some(_), Some(_)) => {
+                return Err(ParseError::BadRequest(String::from(
+                    "Both Content-Length and Transfer-Encoding are set",
+                )))
+            }
+            (Some(te), None) if te.eq_ignore_ascii_case("chunked") => None,
+            (Some(_), None) => {
+                return Err(ParseError::BadRequest(String::from(
+                    "Unsupported Transfer-Encoding",
+                )))
+            }
+            (None, Some(cl)) => Some(Connection::content_length(cl)?),
+            (None, None) => Some(0),
+        };
+
+        if let Some(l) = length {
+            if l > self.limits.max_body_size {
+                return Err(ParseError::PayloadTooLarge);
+            }
+        }
+
+        let expect = head.headers.get("expect");
+        if expect.is_some_and(|e| e.eq_ignore_ascii_case("100-continue"))
+            && length != Some(0)
+            && self.buffer.is_empty()
+        {
+            if let Err(e) = self.stream.write_all(CONTINUE).await {
+                return Err(ParseError::Io(e.to_string()));
+            }
+        }
+
+        match length {
+            Some(l) => self.take(l).await,
+            None => self.read_chunked().await,
+        }
+    }
+
+    /// Send the response to the last request, `keep_alive` tells the
+    /// client whether the connection stays open
+    pub async fn write_response(
+        &mut self,
+        res: &Response,
+        keep_alive: bool,
+    ) -> Result<(), String> {
+        let buf = res.serialize(keep_alive);
+        let deadline = Instant::now() + self.limits.request_timeout;
+
+        let write = async {
+            self.stream.write_all(buf.as_bytes()).await?;
+            self.stream.flush().await
+        };
+        match time::timeout_at(deadline, write).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(String::from("Timed out sending the response")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn limits() -> HttpLimits {
+        HttpLimits {
+            max_header_size: 256,
+            max_headers: 4,
+            max_body_size: 64,
+            request_timeout: Duration::from_secs(5),
+            keep_alive_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Server end of a connection whose client sends `parts` one after the
+    /// other, each one in its own read
+    async fn connection(parts: &[&[u8]]) -> Connection {
+        let parts: Vec<Vec<u8>> = parts.iter().map(|p| p.to_vec()).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut client = TcpStream::connect(address).await.unwrap();
+            for part in parts {
+                // The server may stop reading on an invalid request
+                if client.write_all(&part).await.is_err() {
+                    return;
+                }
+                let _ = client.flush().await;
+                time::sleep(Duration::from_millis(20)).await;
+            }
+            time::sleep(Duration::from_secs(1)).await;
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        Connection::new(stream, limits())
+    }
+
+    #[tokio::test]
+    async fn rejects_header_without_colon() {
+        let mut conn =
+            connection(&[b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n"]).await;
+        let res = conn.read_head().await;
+        assert!(matches!(res, Err(ParseError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn missing_content_length_is_an_empty_body() {
+        let mut conn = connection(&[b"POST / HTTP/1.1\r\n\r\n"]).await;
+        let head = conn.read_head().await.unwrap();
+        assert!(conn.read_body(&head).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_content_length() {
+        let mut conn =
+            connection(&[b"POST / HTTP/1.1\r\nContent-Length: 1a\r\n\r\n"])
+                .await;
+        let head = conn.read_head().await.unwrap();
+        let res = conn.read_body(&head).await;
+        assert!(matches!(res, Err(ParseError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_content_length() {
+        let mut conn =
+            connection(&[b"POST / HTTP/1.1\r\nContent-Length: 65\r\n\r\n"])
+                .await;
+        let head = conn.read_head().await.unwrap();
+        let res = conn.read_body(&head).await;
+        assert!(matches!(res, Err(ParseError::PayloadTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn reads_body_split_across_reads() {
+        let mut conn = connection(&[
+            b"POST /tx HTTP/1.1\r\nContent-",
+            b"Length: 11\r\n\r\nhel",
+            b"lo wor",
+            b"ld",
+        ])
+        .await;
+        let head = conn.read_head().await.unwrap();
+        assert_eq!(head.path_query, "/tx");
+        assert_eq!(conn.read_body(&head).await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn reads_pipelined_requests() {
+        let mut conn =
+            connection(&[b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+              GET /b HTTP/1.1\r\nConnection: close\r\n\r\n"])
+            .await;
+
+        let head = conn.read_head().await.unwrap();
+        assert_eq!(head.path_query, "/a");
+        assert!(head.keep_alive());
+        assert_eq!(conn.read_body(&head).await.unwrap(), b"abc");
+
+        let head = conn.read_head().await.unwrap();
+        assert_eq!(head.path_query, "/b");
+        assert!(!head.keep_alive());
+        assert!(conn.read_body(&head).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_over_the_limits() {
+        let long =
+            format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(300));
+        let mut conn = connection(&[long.as_bytes()]).await;
+        let res = conn.read_head().await;
+        assert!(matches!(res, Err(ParseError::HeadersTooLarge)));
+
+        let mut conn = connection(&[
+            b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nE: 5\r\n\r\n",
+        ])
+        .await;
+        let res = conn.read_head().await;
+        assert!(matches!(res, Err(ParseError::HeadersTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn rejects_chunked_body_over_the_limit() {
+        let mut conn = connection(&[
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              20\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n\
+              21\r\n",
+        ])
+        .await;
+        let head = conn.read_head().await.unwrap();
+        let res = conn.read_body(&head).await;
+        assert!(matches!(res, Err(ParseError::PayloadTooLarge)));
+    }
+}
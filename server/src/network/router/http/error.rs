@@ -0,0 +1,231 @@
+use super::{response::Response, status::Status};
+use crate::blockchain::{
+    blockchain::SubmitError, cache::cache::TransactionCheckError,
+    mempool::MempoolError, validator::BlockValidationError,
+};
+use core::fmt;
+use nexium::api::error::{ApiError, ErrorCode};
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+/// Errors raised while reading a request from a connection
+#[derive(Debug)]
+pub enum ParseError {
+    /// The peer closed the connection, or it stayed idle between two
+    /// requests
+    Closed,
+    /// The connection failed
+    Io(String),
+    /// The request was not received in time
+    Timeout,
+    /// The request is not valid HTTP/1.x
+    BadRequest(String),
+    /// The request line and headers exceed the configured limits
+    HeadersTooLarge,
+    /// The body exceeds the configured limit
+    PayloadTooLarge,
+}
+
+impl ParseError {
+    /// Error to send back, if a response can be sent
+    pub fn to_http(&self) -> Option<HttpError> {
+        let code = match self {
+            Self::Closed | Self::Io(_) => return None,
+            Self::Timeout => ErrorCode::RequestTimeout,
+            Self::BadRequest(_) => ErrorCode::MalformedRequest,
+            Self::HeadersTooLarge => ErrorCode::HeadersTooLarge,
+            Self::PayloadTooLarge => ErrorCode::PayloadTooLarge,
+        };
+        Some(HttpError::new(code, self.to_string()))
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "Connection closed"),
+            Self::Io(e) => write!(f, "Failed to read request: {}", e),
+            Self::Timeout => write!(f, "Request timed out"),
+            Self::BadRequest(e) => write!(f, "{}", e),
+            Self::HeadersTooLarge => write!(f, "Request headers too large"),
+            Self::PayloadTooLarge => write!(f, "Request body too large"),
+        }
+    }
+}
+
+/// Status of the responses carrying `code`
+fn status_of(code: ErrorCode) -> Status {
+    match code {
+        ErrorCode::MalformedRequest
+        | ErrorCode::InvalidParameter
+        | ErrorCode::InvalidTimestamp
+        | ErrorCode::InvalidEnvelope
+        | ErrorCode::MalformedTransaction
+        | ErrorCode::UnknownEmitter
+        | ErrorCode::InvalidTransactionSignature
+        | ErrorCode::MissingNonce
+        | ErrorCode::InvalidTransactionId
+        | ErrorCode::InvalidPeer
+        | ErrorCode::MalformedBlock
+        | ErrorCode::BlockRejected => Status::BadRequest,
+        ErrorCode::MissingHeader
+        | ErrorCode::RequestExpired
+        | ErrorCode::InvalidNonce
+        | ErrorCode::InvalidSignature => Status::Unauthorized,
+        ErrorCode::PeerBanned => Status::Forbidden,
+        ErrorCode::NotFound
+        | ErrorCode::TransactionNotFound
+        | ErrorCode::UnknownBlock => Status::NotFound,
+        ErrorCode::RequestTimeout => Status::RequestTimeout,
+        ErrorCode::NonceAlreadyUsed
+        | ErrorCode::NonceGap
+        | ErrorCode::DuplicateTransaction
+        | ErrorCode::InsufficientBalance
+        | ErrorCode::BlockDoesNotConnect => Status::Conflict,
+        ErrorCode::PayloadTooLarge => Status::PayloadTooLarge,
+        ErrorCode::TooManyChallenges => Status::TooManyRequests,
+        ErrorCode::HeadersTooLarge => Status::RequestHeaderFieldsTooLarge,
+        ErrorCode::InternalError
+        | ErrorCode::AccountUnavailable
+        | ErrorCode::ChainUnavailable
+        | ErrorCode::Unknown => Status::InternalError,
+        ErrorCode::KeysUnavailable | ErrorCode::MempoolFull => {
+            Status::ServiceUnavailable
+        }
+    }
+}
+
+/// Error returned by a route or a middleware, sent as an `ApiError` body
+pub struct HttpError {
+    pub status: Status,
+    pub error: ApiError,
+}
+
+impl HttpError {
+    pub fn new<T>(code: ErrorCode, message: T) -> Self
+    where
+        T: Into<String>,
+    {
+        Self {
+            status: status_of(code),
+            error: ApiError::new(code, message),
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.error = self.error.with_details(details);
+        self
+    }
+
+    pub fn internal<T>(message: T) -> Self
+    where
+        T: Into<String>,
+    {
+        Self::new(ErrorCode::InternalError, message)
+    }
+}
+
+impl From<HttpError> for Response {
+    fn from(e: HttpError) -> Self {
+        let mut res = Response::new(e.status, e.error.to_json());
+        res.set_header("content-type", "application/json");
+        res
+    }
+}
+
+impl From<MempoolError> for HttpError {
+    fn from(e: MempoolError) -> Self {
+        let code = match e {
+            MempoolError::Coinbase => ErrorCode::MalformedTransaction,
+            MempoolError::MissingNonce => ErrorCode::MissingNonce,
+            MempoolError::RawSignature => {
+                ErrorCode::InvalidTransactionSignature
+            }
+            MempoolError::NonceAlreadyUsed | MempoolError::NoncePending => {
+                ErrorCode::NonceAlreadyUsed
+            }
+            MempoolError::NonceGap { expected } => {
+                return HttpError::new(ErrorCode::NonceGap, e.to_string())
+                    .with_details(json!({ "expected": expected }))
+            }
+            MempoolError::Duplicate => ErrorCode::DuplicateTransaction,
+            MempoolError::InsufficientBalance { available } => {
+                return HttpError::new(
+                    ErrorCode::InsufficientBalance,
+                    e.to_string(),
+                )
+                .with_details(json!({ "available": available.as_micro() }))
+            }
+            MempoolError::Full { min_fees } => {
+                return HttpError::new(ErrorCode::MempoolFull, e.to_string())
+                    .with_details(json!({ "min_fees": min_fees }))
+            }
+            MempoolError::AccountUnavailable(_) => {
+                ErrorCode::AccountUnavailable
+            }
+            MempoolError::Journal(_) => ErrorCode::InternalError,
+        };
+        HttpError::new(code, e.to_string())
+    }
+}
+
+impl From<TransactionCheckError> for HttpError {
+    fn from(e: TransactionCheckError) -> Self {
+        let code = match e {
+            TransactionCheckError::MalformedData => {
+                ErrorCode::MalformedTransaction
+            }
+            TransactionCheckError::UnknownEmitter => ErrorCode::UnknownEmitter,
+            TransactionCheckError::BadSignature => {
+                ErrorCode::InvalidTransactionSignature
+            }
+            TransactionCheckError::KeysUnavailable(_) => {
+                ErrorCode::KeysUnavailable
+            }
+        };
+        HttpError::new(code, e.to_string())
+    }
+}
+
+impl From<BlockValidationError> for HttpError {
+    fn from(e: BlockValidationError) -> Self {
+        if e == BlockValidationError::PreviousHashMismatch {
+            return HttpError::new(
+                ErrorCode::BlockDoesNotConnect,
+                e.to_string(),
+            );
+        }
+        match e {
+            BlockValidationError::KeysUnavailable(..) => {
+                return HttpError::new(
+                    ErrorCode::KeysUnavailable,
+                    e.to_string(),
+                )
+            }
+            BlockValidationError::BalanceUnavailable(..) => {
+                return HttpError::new(
+                    ErrorCode::AccountUnavailable,
+                    e.to_string(),
+                )
+            }
+            _ => {}
+        }
+
+        // Name of the broken rule, without the values of the variant
+        let debug = format!("{:?}", e);
+        let reason = debug.split(['(', ' ']).next().unwrap_or("");
+        HttpError::new(ErrorCode::BlockRejected, e.to_string())
+            .with_details(json!({ "reason": reason }))
+    }
+}
+
+impl From<SubmitError> for HttpError {
+    fn from(e: SubmitError) -> Self {
+        match e {
+            SubmitError::Invalid(e) => e.into(),
+            SubmitError::Storage(e) => {
+                HttpError::new(ErrorCode::ChainUnavailable, e)
+            }
+        }
+    }
+}
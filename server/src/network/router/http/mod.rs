@@ -1,3 +1,5 @@
+pub mod connection;
+pub mod error;
 pub mod request;
 pub mod response;
 pub mod status;
@@ -1,25 +1,36 @@
-use nexium::rsa::KeyPair;
+use nexium::{
+    api::error::ErrorCode,
+    rsa::{
+        request::{
+            request_message, LOGIN_HEADER, NONCE_HEADER, SIGNATURE_HEADER,
+            TIMESTAMP_HEADER,
+        },
+        signature::SignatureScheme,
+        KeyPair,
+    },
+    utils::time::current_time,
+};
 
-use crate::blockchain::cache::cache::Cache;
+use crate::blockchain::cache::{cache::Cache, challenges::CHALLENGE_TTL};
 
-use super::response::Response;
-use std::collections::HashMap;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+use super::{
+    connection::Connection,
+    error::{HttpError, ParseError},
 };
-
-const READ_SIZE: usize = 32768;
+use serde_json::json;
+use std::collections::HashMap;
 
 pub struct Request {
     pub method: String,
-    #[allow(dead_code)]
     pub path_query: String,
     pub path: String,
     pub query: HashMap<String, String>,
     pub headers: HashMap<String, String>,
     pub body: String,
-    stream: TcpStream,
+    /// IP address of the remote end of the connection
+    pub remote_address: String,
+    /// Whether the connection stays open after the response
+    pub keep_alive: bool,
 }
 
 impl Request {
@@ -32,59 +43,23 @@ impl Request {
         }
     }
 
-    fn parse_info(line: &String) -> (String, String) {
-        let info: Vec<&str> = line.split_ascii_whitespace().collect();
-        let method = info[0].to_string();
-        let path_query = if info.len() > 1 {
-            info[1].to_string()
-        } else {
-            String::new()
-        };
-        (method, path_query)
-    }
-
-    fn parse_header(line: &String) -> (String, String) {
-        let v: Vec<_> = line.split(": ").collect();
-        (v[0].to_string(), v[1].to_string())
-    }
-
-    async fn read_req(stream: &mut TcpStream) -> Result<String, String> {
-        let mut buff = [0; READ_SIZE];
-
-        let r = match stream.read(&mut buff).await {
-            Ok(r) => r,
-            Err(e) => {
-                return Err(format!("Failed to read request: {}", e));
+    /// Read the next request sent on the connection
+    pub async fn from_connection(
+        connection: &mut Connection,
+    ) -> Result<Self, ParseError> {
+        let head = connection.read_head().await?;
+        let body = connection.read_body(&head).await?;
+
+        let body = match String::from_utf8(body) {
+            Ok(b) => b,
+            Err(_) => {
+                return Err(ParseError::BadRequest(String::from(
+                    "Invalid characters in body",
+                )))
             }
         };
 
-        if r == READ_SIZE {
-            return Err(String::from("Request too long"));
-        }
-
-        let s = match String::from_utf8(buff.to_vec()) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(format!("Failed to convert request: {}", e));
-            }
-        };
-
-        return Ok(s);
-    }
-
-    pub async fn from_stream(
-        mut stream: TcpStream,
-    ) -> Result<Self, (String, TcpStream)> {
-        let raw = match Request::read_req(&mut stream).await {
-            Ok(r) => r,
-            Err(e) => return Err((e, stream)),
-        };
-
-        let mut lines: Vec<_> = raw.lines().map(|l| l.to_string()).collect();
-        let (method, path_query) = Request::parse_info(&lines[0]);
-        lines.rotate_left(1);
-
-        let pq: Vec<&str> = path_query.split("?").collect();
+        let pq: Vec<&str> = head.path_query.split("?").collect();
         let path = pq[0].to_string();
         let mut query_map: HashMap<String, String> = HashMap::new();
 
@@ -93,66 +68,85 @@ impl Request {
             Request::parse_path_query(&mut query_map, &query);
         }
 
-        let mut req = Self {
-            method,
-            path_query,
+        return Ok(Self {
+            keep_alive: head.keep_alive(),
+            method: head.method,
+            path_query: head.path_query,
             path,
             query: query_map,
-            headers: HashMap::new(),
-            body: String::new(),
-            stream,
-        };
+            headers: head.headers,
+            body,
+            remote_address: connection.remote_address.clone(),
+        });
+    }
 
-        while lines[0] != "" {
-            let line = &lines[0];
-            let (key, value) = Request::parse_header(line);
-            req.headers.insert(key.to_lowercase(), value);
-            lines.rotate_left(1);
+    fn header(&self, name: &str) -> Result<&String, HttpError> {
+        match self.headers.get(&name.to_lowercase()) {
+            Some(h) => Ok(h),
+            None => Err(HttpError::new(
+                ErrorCode::MissingHeader,
+                format!("Missing {} header", name),
+            )
+            .with_details(json!({ "header": name }))),
         }
-        lines.rotate_left(1);
-
-        req.body = match lines[0].find("\0") {
-            Some(i) => lines[0].drain(0..i).collect(),
-            None => lines[0].to_string(),
-        };
-
-        return Ok(req);
     }
 
-    pub async fn check(&self, cache: &mut Cache) -> Result<KeyPair, String> {
-        let login = match self.headers.get("login") {
-            Some(l) => l,
-            None => return Err(String::from("Missing Login header")),
+    /// Authenticate the client with the signature of the request, see
+    /// `nexium::rsa::request`
+    pub async fn check(&self, cache: &mut Cache) -> Result<KeyPair, HttpError> {
+        let login = self.header(LOGIN_HEADER)?;
+        let nonce = self.header(NONCE_HEADER)?;
+        let sig = self.header(SIGNATURE_HEADER)?;
+        let timestamp: u32 = match self.header(TIMESTAMP_HEADER)?.parse() {
+            Ok(t) => t,
+            Err(_) => {
+                return Err(HttpError::new(
+                    ErrorCode::InvalidTimestamp,
+                    "Invalid Timestamp header",
+                ))
+            }
         };
 
-        let sig = match self.headers.get("sig-sample") {
-            Some(s) => s,
-            None => return Err(String::from("Missing Sig-Sample header")),
-        };
+        if current_time().abs_diff(timestamp) > CHALLENGE_TTL {
+            return Err(HttpError::new(
+                ErrorCode::RequestExpired,
+                "Request expired",
+            ));
+        }
+
+        // Checked before the signature to avoid fetching keys for nothing,
+        // and consumed after so that others can't burn a client nonce
+        if !cache.challenges.is_valid(nonce) {
+            return Err(HttpError::new(
+                ErrorCode::InvalidNonce,
+                "Invalid or already used nonce",
+            ));
+        }
 
-        match cache.get_key(login, sig, None).await {
-            Some(k) => Ok(k),
+        let message = request_message(
+            &self.method,
+            &self.path_query,
+            timestamp,
+            nonce,
+            self.body.as_bytes(),
+        );
+        let scheme = SignatureScheme::Pkcs1v15Sha256;
+        let key = match cache.get_key(login, scheme, sig, &message).await {
+            Some(k) => k,
             None => {
-                return Err(String::from("Invalid signature"));
+                return Err(HttpError::new(
+                    ErrorCode::InvalidSignature,
+                    "Invalid signature",
+                ))
             }
-        }
-    }
+        };
 
-    pub async fn _send(
-        mut stream: TcpStream,
-        res: &Response,
-    ) -> Result<(), String> {
-        let buf = res.to_string();
-        match stream.write_all(buf.as_bytes()).await {
-            Ok(()) => match stream.flush().await {
-                Ok(()) => Ok(()),
-                Err(e) => Err(e.to_string()),
-            },
-            Err(e) => Err(e.to_string()),
+        if !cache.challenges.consume(nonce) {
+            return Err(HttpError::new(
+                ErrorCode::InvalidNonce,
+                "Invalid or already used nonce",
+            ));
         }
-    }
-
-    pub async fn send(self, res: &Response) -> Result<(), String> {
-        Request::_send(self.stream, res).await
+        Ok(key)
     }
 }
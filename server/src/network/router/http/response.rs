@@ -18,6 +18,14 @@ impl Response {
         }
     }
 
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
     // pub fn set_status(&mut self, status: Status) {
     //     self.status = status;
     // }
@@ -26,25 +34,20 @@ impl Response {
         self.headers.push(format!("{name}:{value}"));
     }
 
-    pub fn to_string(&self) -> String {
-        if self.headers.len() == 0 {
-            format!("HTTP/1.1 {}\r\n\r\n{}", self.status, self.body)
-        } else {
-            let headers = self.headers.join("\r\n");
-            format!(
-                "HTTP/1.1 {}\r\n{headers}\r\n\r\n{}",
-                self.status, self.body
-            )
-        }
-
-        //
-        // same
+    /// HTTP/1.1 message of the response, with the framing headers
+    pub fn serialize(&self, keep_alive: bool) -> String {
+        let mut headers = self.headers.clone();
+        headers.push(format!("content-length:{}", self.body.len()));
+        headers.push(match keep_alive {
+            true => String::from("connection:keep-alive"),
+            false => String::from("connection:close"),
+        });
 
-        // let mut headers = String::new();
-        // if self.headers.len() > 0 {
-        //     headers.push_str("\r\n");
-        //     headers.push_str(self.headers.join("\r\n").as_str());
-        // }
-        // format!("HTTP/1.1 {}{headers}\r\n\r\n{}", self.status, self.body)
+        format!(
+            "HTTP/1.1 {}\r\n{}\r\n\r\n{}",
+            self.status,
+            headers.join("\r\n"),
+            self.body
+        )
     }
 }
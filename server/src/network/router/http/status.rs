@@ -4,8 +4,16 @@ use std::fmt::Display;
 pub enum Status {
     Ok,
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    RequestTimeout,
+    Conflict,
+    PayloadTooLarge,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalError,
+    ServiceUnavailable,
 }
 
 impl Status {
@@ -13,8 +21,16 @@ impl Status {
         match self {
             Self::Ok => 200,
             Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::Forbidden => 403,
             Self::NotFound => 404,
+            Self::RequestTimeout => 408,
+            Self::Conflict => 409,
+            Self::PayloadTooLarge => 413,
+            Self::TooManyRequests => 429,
+            Self::RequestHeaderFieldsTooLarge => 431,
             Self::InternalError => 500,
+            Self::ServiceUnavailable => 503,
         }
     }
 
@@ -22,8 +38,18 @@ impl Status {
         match self {
             Self::Ok => "OK",
             Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
+            Self::Forbidden => "Forbidden",
             Self::NotFound => "Not Found",
+            Self::RequestTimeout => "Request Timeout",
+            Self::Conflict => "Conflict",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => {
+                "Request Header Fields Too Large"
+            }
             Self::InternalError => "Internal Server Error",
+            Self::ServiceUnavailable => "Service Unavailable",
         }
     }
 }
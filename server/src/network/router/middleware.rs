@@ -0,0 +1,156 @@
+use super::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
+    routing::Handler,
+};
+use colored::Colorize;
+use futures::future::BoxFuture;
+use std::{ops::DerefMut, sync::Arc, time::Instant};
+
+/// Layer wrapped around the routes, it can act on the request before
+/// calling `next` and on the response after
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(
+        &'a self,
+        ctx: Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, HandlerResult>;
+}
+
+/// Remaining middlewares and the route handler
+pub struct Next<'a> {
+    handler: &'a Handler,
+    middlewares: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub fn new(
+        handler: &'a Handler,
+        middlewares: &'a [Arc<dyn Middleware>],
+    ) -> Self {
+        Self {
+            handler,
+            middlewares,
+        }
+    }
+
+    pub fn run(self, ctx: Context) -> BoxFuture<'a, HandlerResult> {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => {
+                first.handle(ctx, Next::new(self.handler, rest))
+            }
+            None => (self.handler)(ctx),
+        }
+    }
+}
+
+/// Print the method, path, status and duration of each request
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle<'a>(
+        &'a self,
+        ctx: Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let method = ctx.req.method.clone();
+            let path = ctx.req.path.clone();
+
+            let res = next.run(ctx).await;
+            let code = match &res {
+                Ok(r) => r.status().code(),
+                Err(e) => e.status.code(),
+            };
+            let code = match code {
+                200..=399 => code.to_string().green(),
+                _ => code.to_string().red(),
+            };
+            println!(
+                "{} {} {} {} ({:.2?})",
+                "HTTP".blue().bold(),
+                method,
+                path,
+                code,
+                start.elapsed()
+            );
+            res
+        })
+    }
+}
+
+/// Turn the errors of the inner layers into responses, so that the outer
+/// layers only see responses
+pub struct MapErrors;
+
+impl Middleware for MapErrors {
+    fn handle<'a>(
+        &'a self,
+        ctx: Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(async move {
+            match next.run(ctx).await {
+                Ok(res) => Ok(res),
+                Err(e) => Ok(e.into()),
+            }
+        })
+    }
+}
+
+/// Check the signature of the request, see `Request::check`, and give the
+/// key of the client to the next layers
+pub struct Authenticate;
+
+impl Middleware for Authenticate {
+    fn handle<'a>(
+        &'a self,
+        mut ctx: Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(async move {
+            let cache = ctx.state.cache.clone();
+            let key = ctx.req.check(cache.lock().await.deref_mut()).await?;
+            ctx.client_key = Some(key);
+            next.run(ctx).await
+        })
+    }
+}
+
+/// Seal the body of successful responses for the authenticated client,
+/// must come after `Authenticate`
+pub struct Encrypt;
+
+impl Middleware for Encrypt {
+    fn handle<'a>(
+        &'a self,
+        ctx: Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(async move {
+            let key = match ctx.client_key.clone() {
+                Some(k) => k,
+                None => {
+                    return Err(HttpError::internal("Unauthenticated client"))
+                }
+            };
+
+            let res = next.run(ctx).await?;
+            if !matches!(res.status(), Status::Ok) {
+                return Ok(res);
+            }
+
+            let crypted = match key.seal(res.body()) {
+                Ok(c) => c,
+                Err(_) => {
+                    return Err(HttpError::internal("Failed to seal response"))
+                }
+            };
+
+            let mut res = Response::new(Status::Ok, crypted);
+            res.set_header("content-type", "text/plain");
+            Ok(res)
+        })
+    }
+}
@@ -1,3 +1,6 @@
+pub mod context;
 pub mod handler;
 mod http;
+pub mod middleware;
 mod routes;
+pub mod routing;
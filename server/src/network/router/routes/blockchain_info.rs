@@ -1,28 +1,20 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-use crate::{
-    blockchain::blockchain::Blockchain,
-    network::router::http::{
-        request::Request, response::Response, status::Status,
-    },
+use crate::network::router::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
 };
 
 /// Handler for getting blockchain info (for sync decisions)
-pub async fn handler(req: Request, blockchain: Arc<Mutex<Blockchain>>) {
-    let bc = blockchain.lock().await;
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let bc = ctx.state.blockchain.lock().await;
     let info = bc.get_info();
     drop(bc);
 
     let json = match serde_json::to_string(&info) {
         Ok(j) => j,
         Err(_) => {
-            let res = Response::new(Status::InternalError, "");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::internal("Failed to encode chain info"))
         }
     };
 
-    let res = Response::new(Status::Ok, &json);
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, &json))
 }
@@ -0,0 +1,43 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use nexium::api::error::ErrorCode;
+
+use super::get_headers::first_height;
+use crate::{
+    blockchain::sync::{MAX_BLOCKS, MAX_BLOCKS_SIZE},
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
+    },
+};
+
+/// Handler giving the blocks following the block `from`, written one after
+/// the other, at most `MAX_BLOCKS_SIZE` bytes of them
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let limit = ctx.query("limit").unwrap_or(MAX_BLOCKS).min(MAX_BLOCKS);
+
+    let mut bc = ctx.state.blockchain.lock().await;
+    let start = first_height(&ctx, &bc)?;
+    let end = (start + limit as u64).min(bc.hashes.len() as u64);
+
+    let mut data = vec![];
+    for height in start..end {
+        let block = match bc.block_at(height) {
+            Ok(b) => b.to_buffer(),
+            Err(_) => {
+                return Err(HttpError::new(
+                    ErrorCode::ChainUnavailable,
+                    "Failed to read blockchain",
+                ))
+            }
+        };
+        if !data.is_empty() && data.len() + block.len() > MAX_BLOCKS_SIZE {
+            break;
+        }
+        data.extend_from_slice(&block);
+    }
+    drop(bc);
+
+    let mut res = Response::new(Status::Ok, STANDARD.encode(&data));
+    res.set_header("content-type", "text/plain");
+    Ok(res)
+}
@@ -0,0 +1,24 @@
+use nexium::api::error::ErrorCode;
+
+use crate::network::router::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
+};
+
+/// Handler giving a nonce to sign in the next authenticated request
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let address = &ctx.req.remote_address;
+    let nonce = match ctx.state.cache.lock().await.challenges.issue(address) {
+        Some(n) => n,
+        None => {
+            return Err(HttpError::new(
+                ErrorCode::TooManyChallenges,
+                "Too many unused challenges",
+            ))
+        }
+    };
+
+    let mut res = Response::new(Status::Ok, nonce);
+    res.set_header("content-type", "text/plain");
+    Ok(res)
+}
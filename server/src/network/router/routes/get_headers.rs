@@ -0,0 +1,75 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use nexium::api::error::ErrorCode;
+use serde_json::json;
+
+use crate::{
+    blockchain::{
+        blockchain::Blockchain,
+        sync::{encode_headers, HeaderEntry, MAX_HEADERS},
+    },
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
+    },
+};
+
+/// Height of the block following the block `from` of the query, 0 when
+/// `from` is missing
+pub fn first_height(ctx: &Context, bc: &Blockchain) -> Result<u64, HttpError> {
+    let from = match ctx.req.query.get("from") {
+        Some(f) => f,
+        None => return Ok(0),
+    };
+
+    let hash = match hex::decode(from).ok().and_then(|h| h.try_into().ok()) {
+        Some(h) => h,
+        None => {
+            return Err(HttpError::new(
+                ErrorCode::InvalidParameter,
+                "Invalid from",
+            )
+            .with_details(json!({ "name": "from" })))
+        }
+    };
+
+    match bc.height_of(&hash) {
+        Some(h) => Ok(h + 1),
+        None => Err(HttpError::new(
+            ErrorCode::UnknownBlock,
+            format!("Unknown block {}", from),
+        )),
+    }
+}
+
+/// Handler giving the headers following the block `from`, with the hash of
+/// their block, see `sync::encode_headers`
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let limit = ctx.query("limit").unwrap_or(MAX_HEADERS).min(MAX_HEADERS);
+
+    let mut bc = ctx.state.blockchain.lock().await;
+    let start = first_height(&ctx, &bc)?;
+    let end = (start + limit as u64).min(bc.hashes.len() as u64);
+
+    let mut entries = vec![];
+    for height in start..end {
+        let header = match bc.header_at(height) {
+            Ok(h) => h,
+            Err(_) => {
+                return Err(HttpError::new(
+                    ErrorCode::ChainUnavailable,
+                    "Failed to read blockchain",
+                ))
+            }
+        };
+        entries.push(HeaderEntry {
+            hash: bc.hashes[height as usize],
+            header,
+        });
+    }
+    drop(bc);
+
+    let mut res =
+        Response::new(Status::Ok, STANDARD.encode(encode_headers(&entries)));
+    res.set_header("content-type", "text/plain");
+    Ok(res)
+}
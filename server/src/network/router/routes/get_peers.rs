@@ -1,25 +1,15 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-use crate::{
-    network::router::http::{
-        request::Request, response::Response, status::Status,
-    },
-    peers::PeerList,
+use crate::network::router::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
 };
 
-pub async fn handler(req: Request, peer_list: Arc<Mutex<PeerList>>) {
-    let peers = peer_list.lock().await;
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let peers = ctx.state.peer_list.lock().await;
     
     let json = match serde_json::to_string(&peers.peers) {
         Ok(j) => j,
-        Err(_) => {
-            let res = Response::new(Status::InternalError, "");
-            let _ = req.send(&res).await;
-            return;
-        }
+        Err(_) => return Err(HttpError::internal("Failed to encode peers")),
     };
 
-    let res = Response::new(Status::Ok, &json);
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, &json))
 }
@@ -0,0 +1,42 @@
+use nexium::api::error::ErrorCode;
+use serde_json::json;
+
+use crate::network::router::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
+};
+
+/// Transactions of a login waiting in the mempool, in nonce order
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let login: String = ctx.param("login")?;
+
+    if login.is_empty() {
+        return Err(HttpError::new(ErrorCode::InvalidParameter, "Empty login")
+            .with_details(json!({ "name": "login" })));
+    }
+
+    let pending = ctx
+        .state
+        .blockchain
+        .lock()
+        .await
+        .pending_transactions(&login);
+
+    let mut arr = json::array![];
+    for tr in pending {
+        let obj = match serde_json::to_string(&tr) {
+            Ok(obj) => obj,
+            Err(_) => {
+                return Err(HttpError::internal("Failed to parse transaction"));
+            }
+        };
+
+        if arr.push(obj).is_err() {
+            return Err(HttpError::internal(
+                "Failed to add transaction object",
+            ));
+        }
+    }
+
+    Ok(Response::new(Status::Ok, arr.dump()))
+}
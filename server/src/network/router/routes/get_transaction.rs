@@ -0,0 +1,65 @@
+use crate::network::router::{
+    context::{Context, HandlerResult},
+    http::{error::HttpError, response::Response, status::Status},
+};
+use nexium::{api::error::ErrorCode, blockchain::txid::TxId};
+
+/// Handler for looking up a transaction by id, in the chain or in the
+/// mempool
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let txid: TxId = match ctx.param("id") {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(HttpError::new(
+                ErrorCode::InvalidTransactionId,
+                "Invalid transaction id",
+            ));
+        }
+    };
+
+    let mut bc = ctx.state.blockchain.lock().await;
+
+    let json = match bc.tx_index.get(&txid).copied() {
+        Some(location) => {
+            let block = match bc.get_block(&location.block_hash) {
+                Ok(b) => b,
+                Err(_) => {
+                    return Err(HttpError::new(
+                        ErrorCode::ChainUnavailable,
+                        "Failed to read block",
+                    ));
+                }
+            };
+            let confirmations = bc.cache.len() as u64 - location.height;
+            serde_json::json!({
+                "txid": txid,
+                "transaction": block.transactions[location.index],
+                "block_hash": hex::encode(location.block_hash),
+                "height": location.height,
+                "confirmations": confirmations,
+                "merkle_root": hex::encode(block.header.merkle_root),
+                "proof": block.prove(location.index).ok(),
+            })
+        }
+        None => match bc.pending_transaction(&txid) {
+            Some(tr) => serde_json::json!({
+                "txid": txid,
+                "transaction": tr,
+                "block_hash": null,
+                "height": null,
+                "confirmations": 0,
+                "merkle_root": null,
+                "proof": null,
+            }),
+            None => {
+                return Err(HttpError::new(
+                    ErrorCode::TransactionNotFound,
+                    "Transaction not found",
+                ));
+            }
+        },
+    };
+    drop(bc);
+
+    Ok(Response::new(Status::Ok, json.to_string()))
+}
@@ -1,58 +1,45 @@
-use std::{ops::DerefMut, sync::Arc};
-
 use crate::{
-    blockchain::{
-        blockchain::Blockchain, cache::cache::Cache,
-        structure::consts::HEADER_PREVIOUS_BLOCK_HASH_SIZE,
-    },
-    network::router::http::{
-        request::Request, response::Response, status::Status,
+    blockchain::structure::consts::HEADER_PREVIOUS_BLOCK_HASH_SIZE,
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
     },
 };
-use nexium::{
-    blockchain::{consts::TRANSACTION_RECEIVER, transaction_data::TransactionData},
-    utils::rand::create_noise,
+use nexium::api::error::ErrorCode;
+use nexium::blockchain::{
+    amount::Amount, consts::TRANSACTION_RECEIVER,
+    transaction_data::TransactionData,
 };
-use tokio::sync::Mutex;
+use serde_json::json;
 
-pub async fn handler(
-    req: Request,
-    cache: Arc<Mutex<Cache>>,
-    blockchain: Arc<Mutex<Blockchain>>,
-) {
-    let sp: Vec<String> = req.path.split("/").map(|e| e.to_string()).collect();
-    let user_login = &sp[2];
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let user_login: String = ctx.param("login")?;
 
     if user_login.is_empty() {
-        let res = Response::new(Status::BadRequest, "");
-        let _ = req.send(&res).await;
-        return;
+        return Err(HttpError::new(
+            ErrorCode::InvalidParameter,
+            "Empty login",
+        )
+        .with_details(json!({ "name": "login" })));
     }
 
-    let key = match req.check(cache.lock().await.deref_mut()).await {
-        Ok(data) => data,
-        Err(e) => {
-            let res = Response::new(Status::BadRequest, e);
-            let _ = req.send(&res).await;
-            return;
-        }
-    };
+    let blockchain = &ctx.state.blockchain;
 
-    // Get user balance
-    let balance = match blockchain.lock().await.get_user_balance(user_login) {
-        Ok(b) => b,
+    // Get user account
+    let account = match blockchain.lock().await.get_account(&user_login) {
+        Ok(a) => a,
         Err(e) => {
-            let res = Response::new(Status::BadRequest, e);
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(ErrorCode::AccountUnavailable, e));
         }
     };
 
     // Calculate stats by iterating through blockchain
     let mut sent_count: u64 = 0;
     let mut received_count: u64 = 0;
-    let mut total_sent: f64 = 0.0;
-    let mut total_received: f64 = 0.0;
+    let mut total_sent = Amount::ZERO;
+    let mut total_received = Amount::ZERO;
+    let mut blocks_mined: u64 = 0;
+    let mut total_rewards = Amount::ZERO;
 
     let mut hash = blockchain.lock().await.last_hash;
 
@@ -76,15 +63,21 @@ pub async fn handler(
                         .copy_from_slice(user_login.as_bytes());
 
                     // Check if user sent this transaction
-                    if emitter_login == *user_login {
+                    if emitter_login == user_login {
                         sent_count += 1;
-                        total_sent += amount as f64;
+                        total_sent = total_sent.saturating_add(amount);
                     }
 
                     // Check if user received this transaction
                     if receiver == login_bytes {
                         received_count += 1;
-                        total_received += amount as f64;
+                        total_received = total_received.saturating_add(amount);
+                    }
+                }
+                Ok(TransactionData::Coinbase { reward, .. }) => {
+                    if emitter_login == user_login {
+                        blocks_mined += 1;
+                        total_rewards = total_rewards.saturating_add(reward);
                     }
                 }
                 _ => continue,
@@ -94,34 +87,21 @@ pub async fn handler(
         hash = b.header.previous_block_hash;
 
         match blockchain.lock().await.cache.get(&hash) {
-            Some(0) => break,
             Some(_) => {}
             None => break,
         }
     }
 
     let json = json::object! {
-        "balance" => balance,
+        "balance" => account.balance.to_string(),
         "sent_count" => sent_count,
         "received_count" => received_count,
-        "total_sent" => total_sent,
-        "total_received" => total_received,
-        "total_transactions" => sent_count + received_count,
-        "noise" => create_noise(),
-    };
-
-    let data = json.dump();
-
-    let crypted = match key.crypt_split(&data) {
-        Ok(res) => res,
-        Err(_) => {
-            let res = Response::new(Status::InternalError, "");
-            let _ = req.send(&res).await;
-            return;
-        }
+        "total_sent" => total_sent.to_string(),
+        "total_received" => total_received.to_string(),
+        "total_transactions" => account.transactions,
+        "blocks_mined" => blocks_mined,
+        "total_rewards" => total_rewards.to_string(),
     };
 
-    let mut res = Response::new(Status::Ok, crypted);
-    res.set_header("content-type", "text/plain");
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, json.dump()))
 }
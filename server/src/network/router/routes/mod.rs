@@ -1,8 +1,12 @@
-pub mod blockchain_download;
 pub mod blockchain_info;
 pub mod check_nexium;
 pub mod get_balance;
+pub mod get_blocks;
+pub mod get_challenge;
+pub mod get_headers;
 pub mod get_peers;
+pub mod get_pending;
+pub mod get_transaction;
 pub mod get_transactions;
 pub mod get_user_stats;
 pub mod new_transaction;
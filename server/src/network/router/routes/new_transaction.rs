@@ -1,90 +1,57 @@
-use std::sync::Arc;
-
-use nexium::{blockchain::transaction::Transaction, rsa::KeyPair};
-use tokio::sync::Mutex;
+use nexium::{api::error::ErrorCode, blockchain::transaction::Transaction};
 
 use crate::{
-    blockchain::{blockchain::Blockchain, cache::cache::Cache},
-    network::router::http::{
-        request::Request, response::Response, status::Status,
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
     },
-    peers::PeerList,
 };
 
-pub async fn handler(
-    req: Request,
-    cache: Arc<Mutex<Cache>>,
-    blockchain: Arc<Mutex<Blockchain>>,
-    peer_list: Arc<Mutex<PeerList>>,
-    key: KeyPair,
-    self_address: String,
-    self_port: u16,
-) {
-    let data = match key.decrypt_split(&req.body) {
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let state = ctx.state;
+    let data = match state.key.open(&ctx.req.body) {
         Ok(res) => res,
         Err(_) => {
-            let res = Response::new(Status::BadRequest, "Invalid data");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(
+                ErrorCode::InvalidEnvelope,
+                "Invalid data",
+            ));
         }
     };
 
     let tr: Transaction = match serde_json::from_str(&data) {
         Ok(obj) => obj,
         Err(e) => {
-            let res = Response::new(Status::BadRequest, e.to_string());
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(
+                ErrorCode::MalformedTransaction,
+                e.to_string(),
+            ));
         }
     };
 
     // dbg!(&tr);
-    let mut message = tr.header.to_buffer().to_vec();
-    message.extend(&tr.data);
-
-    let key = match cache
-        .lock()
-        .await
-        .get_key(
-            &tr.header.get_login(),
-            &tr.signature.to_string(),
-            Some(&message),
-        )
-        .await
-    {
-        Some(k) => k,
-        None => {
-            let res = Response::new(Status::BadRequest, "Invalid key");
-            let _ = req.send(&res).await;
-            return;
-        }
-    };
-
-    match key.check_signature(&message, &tr.signature) {
-        Ok(res) => {
-            if !res {
-                let res =
-                    Response::new(Status::BadRequest, "Invalid signature");
-                let _ = req.send(&res).await;
-                return;
-            }
-        }
-        Err(_) => {
-            let res =
-                Response::new(Status::BadRequest, "Failed to check signature");
-            let _ = req.send(&res).await;
-            return;
-        }
+    let check = state.cache.lock().await.check_transaction(&tr).await;
+    if let Err(e) = check {
+        return Err(e.into());
     }
 
-    let res = Response::new(Status::Ok, "");
-    let _ = req.send(&res).await;
+    // The client is only answered once the transaction is in the mempool
+    // and its journal
+    let mut bc = state.blockchain.lock().await;
+    let added = bc.add_transaction(tr.clone()).await;
+    drop(bc);
+    if let Err(e) = added {
+        println!("Transaction rejected: {}", e);
+        return Err(e.into());
+    }
 
-    // Broadcast transaction to all peers
-    let peers = peer_list.lock().await;
-    peers.broadcast_transaction(&tr, &self_address, self_port).await;
-    drop(peers);
+    // Only accepted transactions are broadcast, in the background
+    tokio::spawn(async move {
+        let peers = state.peer_list.lock().await;
+        peers
+            .broadcast_transaction(&tr, &state.self_address, state.self_port)
+            .await;
+    });
 
-    // Add to local blockchain
-    blockchain.lock().await.add_transaction(tr, peer_list.clone(), self_address, self_port).await;
+    Ok(Response::new(Status::Ok, ""))
 }
@@ -1,32 +1,30 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use colored::Colorize;
+use nexium::api::error::ErrorCode;
 
 use crate::{
-    network::router::http::{
-        request::Request, response::Response, status::Status,
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
     },
-    peers::{Peer, PeerList},
+    peers::Peer,
 };
 
-pub async fn handler(
-    req: Request, 
-    peer_list: Arc<Mutex<PeerList>>, 
-    _self_login: String,
-    self_address: String,
-    self_port: u16,
-) {
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let self_address = &ctx.state.self_address;
+    let self_port = ctx.state.self_port;
+
     // Parse the incoming peer registration
-    let new_peer: Peer = match serde_json::from_str(&req.body) {
+    let new_peer: Peer = match serde_json::from_str(&ctx.req.body) {
         Ok(p) => p,
         Err(_) => {
-            let res = Response::new(Status::BadRequest, "Invalid peer format");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(
+                ErrorCode::InvalidPeer,
+                "Invalid peer format",
+            ));
         }
     };
 
-    let mut peers = peer_list.lock().await;
+    let mut peers = ctx.state.peer_list.lock().await;
     
     let peer_url = new_peer.url();
     let is_new = peers.add_peer(new_peer.clone());
@@ -44,7 +42,7 @@ pub async fn handler(
             .filter(|p| {
                 // Skip the new peer itself and ourselves
                 !(p.address == new_peer.address && p.port == new_peer.port) &&
-                !(p.address == self_address && p.port == self_port)
+                !(p.address == *self_address && p.port == self_port)
             })
             .cloned()
             .collect();
@@ -62,12 +60,9 @@ pub async fn handler(
     let json = match serde_json::to_string(&peers.peers) {
         Ok(j) => j,
         Err(_) => {
-            let res = Response::new(Status::InternalError, "");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::internal("Failed to encode peers"));
         }
     };
 
-    let res = Response::new(Status::Ok, &json);
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, &json))
 }
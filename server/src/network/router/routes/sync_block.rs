@@ -1,27 +1,25 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use colored::Colorize;
+use nexium::api::error::ErrorCode;
 use base64::{engine::general_purpose::STANDARD, Engine};
 
 use crate::{
-    blockchain::{blockchain::Blockchain, structure::block::Block},
-    network::router::http::{
-        request::Request, response::Response, status::Status,
+    blockchain::{blockchain::BlockOutcome, structure::block::Block},
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
     },
 };
 
 /// Handler for receiving broadcasted blocks from peers
-pub async fn handler(
-    req: Request,
-    blockchain: Arc<Mutex<Blockchain>>,
-) {
+pub async fn handler(ctx: Context) -> HandlerResult {
     // Decode block from base64
-    let block_data = match STANDARD.decode(&req.body) {
+    let block_data = match STANDARD.decode(&ctx.req.body) {
         Ok(d) => d,
         Err(_) => {
-            let res = Response::new(Status::BadRequest, "Invalid block encoding");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(
+                ErrorCode::MalformedBlock,
+                "Invalid block encoding",
+            ));
         }
     };
 
@@ -29,9 +27,10 @@ pub async fn handler(
     let block = match Block::from_buffer(&block_data) {
         Ok(b) => b,
         Err(_) => {
-            let res = Response::new(Status::BadRequest, "Invalid block format");
-            let _ = req.send(&res).await;
-            return;
+            return Err(HttpError::new(
+                ErrorCode::MalformedBlock,
+                "Invalid block format",
+            ));
         }
     };
 
@@ -41,30 +40,50 @@ pub async fn handler(
         block.transactions.len()
     );
 
-    // Add block to blockchain
-    let mut bc = blockchain.lock().await;
-    
-    // Verify the block connects to our chain
-    if block.header.previous_block_hash != bc.last_hash {
-        // Block doesn't connect - might be a fork or we're behind
-        println!(
-            "{} Block rejected: does not connect to chain (expected {:?}, got {:?})",
-            "SYNC".red().bold(),
-            hex::encode(&bc.last_hash[..8]),
-            hex::encode(&block.header.previous_block_hash[..8])
-        );
-        let res = Response::new(Status::BadRequest, "Block does not connect to chain");
-        let _ = req.send(&res).await;
-        return;
-    }
+    let mut cache = ctx.state.cache.lock().await;
+    let mut bc = ctx.state.blockchain.lock().await;
 
-    bc.append_synced_block(&block);
-    println!(
-        "{} Block added to chain (now {} blocks)",
-        "SYNC".green().bold(),
-        bc.cache.len()
-    );
+    // The block is verified against every consensus rule once its branch
+    // becomes the main chain
+    match bc.submit_block(block, &mut cache).await {
+        Ok(BlockOutcome::Known) => {}
+        Ok(BlockOutcome::Connected(waiting)) => {
+            println!(
+                "{} Block added to chain with {} waiting block(s) (now {} blocks)",
+                "SYNC".green().bold(),
+                waiting,
+                bc.hashes.len()
+            );
+        }
+        Ok(BlockOutcome::Reorganised {
+            disconnected,
+            connected,
+        }) => {
+            println!(
+                "{} Switched to the branch of the block: {} block(s) replaced by {} (now {} blocks)",
+                "SYNC".green().bold(),
+                disconnected,
+                connected,
+                bc.hashes.len()
+            );
+        }
+        Ok(BlockOutcome::SideChain) => {
+            println!(
+                "{} Block kept on a side chain",
+                "SYNC".yellow().bold()
+            );
+        }
+        Ok(BlockOutcome::Orphan) => {
+            println!(
+                "{} Block kept until its parent is received",
+                "SYNC".yellow().bold()
+            );
+        }
+        Err(e) => {
+            println!("{} Block rejected: {}", "SYNC".red().bold(), e);
+            return Err(e.into());
+        }
+    }
 
-    let res = Response::new(Status::Ok, "");
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, ""))
 }
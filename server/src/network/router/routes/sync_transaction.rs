@@ -1,32 +1,40 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use colored::Colorize;
 
-use nexium::blockchain::transaction::Transaction;
+use nexium::{api::error::ErrorCode, blockchain::transaction::Transaction};
 
 use crate::{
-    blockchain::blockchain::Blockchain,
-    network::router::http::{
-        request::Request, response::Response, status::Status,
+    blockchain::cache::cache::TransactionCheckError,
+    network::router::{
+        context::{Context, HandlerResult},
+        http::{error::HttpError, response::Response, status::Status},
+    },
+    peers::{
+        PENALTY_BAD_SIGNATURE, PENALTY_MALFORMED,
+        PENALTY_UNKNOWN_EMITTER,
     },
-    peers::PeerList,
 };
 
 /// Handler for receiving broadcasted transactions from peers
-pub async fn handler(
-    req: Request,
-    blockchain: Arc<Mutex<Blockchain>>,
-    _peer_list: Arc<Mutex<PeerList>>,
-    _self_address: String,
-    _self_port: u16,
-) {
+pub async fn handler(ctx: Context) -> HandlerResult {
+    let (req, state) = (&ctx.req, &ctx.state);
+    let peer_list = &state.peer_list;
+
+    if peer_list.lock().await.is_banned(&req.remote_address) {
+        return Err(HttpError::new(ErrorCode::PeerBanned, "Peer is banned"));
+    }
+
     // Parse the transaction from JSON
     let transaction: Transaction = match serde_json::from_str(&req.body) {
         Ok(t) => t,
         Err(_) => {
-            let res = Response::new(Status::BadRequest, "Invalid transaction format");
-            let _ = req.send(&res).await;
-            return;
+            peer_list
+                .lock()
+                .await
+                .penalize(&req.remote_address, PENALTY_MALFORMED);
+            return Err(HttpError::new(
+                ErrorCode::MalformedTransaction,
+                "Invalid transaction format",
+            ));
         }
     };
 
@@ -37,13 +45,38 @@ pub async fn handler(
         emitter.yellow()
     );
 
+    // Same checks as a transaction coming from a client
+    let check = state.cache.lock().await.check_transaction(&transaction).await;
+    if let Err(e) = check {
+        let penalty = match e {
+            TransactionCheckError::MalformedData => PENALTY_MALFORMED,
+            TransactionCheckError::UnknownEmitter => PENALTY_UNKNOWN_EMITTER,
+            TransactionCheckError::BadSignature => PENALTY_BAD_SIGNATURE,
+            // Not the fault of the peer
+            TransactionCheckError::KeysUnavailable(_) => 0,
+        };
+        let banned =
+            peer_list.lock().await.penalize(&req.remote_address, penalty);
+        println!(
+            "{} Transaction rejected: {}{}",
+            "SYNC".red().bold(),
+            e,
+            if banned { " (peer banned)" } else { "" }
+        );
+
+        return Err(e.into());
+    }
+
     // Add to blockchain (this will NOT re-broadcast since it's already synced)
-    blockchain
+    let added = state
+        .blockchain
         .lock()
         .await
         .add_transaction_from_sync(transaction)
         .await;
+    if let Err(e) = added {
+        return Err(e.into());
+    }
 
-    let res = Response::new(Status::Ok, "");
-    let _ = req.send(&res).await;
+    Ok(Response::new(Status::Ok, ""))
 }
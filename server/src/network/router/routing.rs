@@ -0,0 +1,163 @@
+use super::{
+    context::{Context, HandlerResult, State},
+    http::{error::HttpError, request::Request, response::Response},
+    middleware::{Middleware, Next},
+};
+use futures::future::BoxFuture;
+use nexium::api::error::ErrorCode;
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+pub type Handler =
+    Arc<dyn Fn(Context) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+/// A method and a path pattern like `/balance/{login}`, with the
+/// middlewares specific to the route
+pub struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: Handler,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Route {
+    pub fn new<H, F>(method: &str, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Context) -> F + Send + Sync + 'static,
+        F: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let pattern = pattern
+            .split('/')
+            .map(|s| {
+                match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) => Segment::Param(name.to_string()),
+                    None => Segment::Static(s.to_string()),
+                }
+            })
+            .collect();
+
+        Self {
+            method: method.to_string(),
+            pattern,
+            handler: Arc::new(move |ctx| Box::pin(handler(ctx))),
+            middlewares: vec![],
+        }
+    }
+
+    pub fn get<H, F>(pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Context) -> F + Send + Sync + 'static,
+        F: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::new("GET", pattern, handler)
+    }
+
+    pub fn post<H, F>(pattern: &str, handler: H) -> Self
+    where
+        H: Fn(Context) -> F + Send + Sync + 'static,
+        F: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::new("POST", pattern, handler)
+    }
+
+    /// Add a middleware, run after the ones already added
+    pub fn layer<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Path parameters if the request matches the route
+    fn matches(&self, req: &Request) -> Option<HashMap<String, String>> {
+        if req.method != self.method {
+            return None;
+        }
+
+        let segments: Vec<&str> = req.path.split('/').collect();
+        if segments.len() != self.pattern.len() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        for (segment, pattern) in segments.iter().zip(self.pattern.iter()) {
+            match pattern {
+                Segment::Static(s) if s == segment => {}
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), segment.to_string());
+                }
+            }
+        }
+        Some(params)
+    }
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    /// Middlewares run for every request, before the ones of the route
+    middlewares: Vec<Arc<dyn Middleware>>,
+    not_found: Handler,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            routes: vec![],
+            middlewares: vec![],
+            not_found: Arc::new(|ctx| {
+                Box::pin(async move {
+                    Err(HttpError::new(
+                        ErrorCode::NotFound,
+                        format!(
+                            "No route for {} {}",
+                            ctx.req.method, ctx.req.path
+                        ),
+                    ))
+                })
+            }),
+        }
+    }
+
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Add a middleware to every route
+    pub fn layer<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Handle a request with the first matching route
+    pub async fn dispatch(&self, req: Request, state: State) -> Response {
+        let found = self
+            .routes
+            .iter()
+            .find_map(|r| r.matches(&req).map(|params| (r, params)));
+
+        let mut middlewares = self.middlewares.clone();
+        let (handler, params) = match found {
+            Some((route, params)) => {
+                middlewares.extend(route.middlewares.iter().cloned());
+                (&route.handler, params)
+            }
+            None => (&self.not_found, HashMap::new()),
+        };
+
+        let ctx = Context::new(req, state, params);
+        match Next::new(handler, &middlewares).run(ctx).await {
+            Ok(res) => res,
+            Err(e) => e.into(),
+        }
+    }
+}
@@ -1,10 +1,13 @@
-use super::router::handler::handler;
+use super::router::{
+    context::State,
+    handler::{handler, router},
+};
 use crate::{
-    blockchain::{blockchain::Blockchain, cache::cache::Cache},
-    config::Config,
+    blockchain::{blockchain::Blockchain, cache::cache::Cache, miner::Miner},
+    config::{BlockProduction, Config, HttpLimits},
     peers::PeerList,
 };
-use nexium::{gitlab::GitlabClient, rsa::KeyPair};
+use nexium::rsa::KeyPair;
 use std::{process, sync::Arc};
 use tokio::{net::TcpListener, sync::Mutex};
 
@@ -15,6 +18,8 @@ pub struct Server {
     pub login: String,
     address: String,
     port: u16,
+    http_limits: HttpLimits,
+    block_production: BlockProduction,
     pub key: KeyPair,
     pub peer_list: PeerList,
 }
@@ -22,18 +27,20 @@ pub struct Server {
 impl Server {
     pub fn new(
         config: &Config,
-        gitlab: GitlabClient,
+        cache: Cache,
         key: KeyPair,
         blockchain: Blockchain,
         peer_list: PeerList,
     ) -> Result<Self, String> {
         Ok(Self {
-            cache: Cache::new(gitlab.clone()),
+            cache,
             // gitlab: gitlab,
             blockchain: blockchain,
             login: config.user_login.clone(),
             address: config.listen.clone(),
             port: config.port,
+            http_limits: config.http,
+            block_production: config.mining,
             key,
             peer_list,
         })
@@ -52,38 +59,31 @@ impl Server {
 
         println!("Server started on {}:{}", self.address, self.port);
 
-        {
-            let blockchain_arc = Arc::new(Mutex::new(self.blockchain));
-            let cache_arc = Arc::new(Mutex::new(self.cache));
-            let peer_list_arc = Arc::new(Mutex::new(self.peer_list));
+        let router = Arc::new(router());
+        let state = State {
+            cache: Arc::new(Mutex::new(self.cache)),
+            blockchain: Arc::new(Mutex::new(self.blockchain)),
+            peer_list: Arc::new(Mutex::new(self.peer_list)),
+            login: self.login,
+            key: self.key,
+            self_address: self.address,
+            self_port: self.port,
+        };
+
+        Miner::new(&state, self.block_production).spawn();
 
-            loop {
-                match listener.accept().await {
-                    Ok((stream, _)) => {
-                        let blockchain_arc_clone = blockchain_arc.clone();
-                        let cache_arc_clone = cache_arc.clone();
-                        let peer_list_arc_clone = peer_list_arc.clone();
-                        let l = self.login.clone();
-                        let k = self.key.clone();
-                        let addr = self.address.clone();
-                        let port = self.port;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let limits = self.http_limits;
+                    let router = router.clone();
+                    let state = state.clone();
 
-                        tokio::spawn(async move {
-                            handler(
-                                stream,
-                                cache_arc_clone,
-                                blockchain_arc_clone,
-                                peer_list_arc_clone,
-                                l,
-                                k,
-                                addr,
-                                port,
-                            )
-                            .await;
-                        });
-                    }
-                    Err(_) => {}
+                    tokio::spawn(async move {
+                        handler(stream, limits, router, state).await;
+                    });
                 }
+                Err(_) => {}
             }
         }
     }
@@ -1,9 +1,14 @@
+use crate::blockchain::{
+    structure::{block::Block, block_header::HeaderPreviousBlockHash},
+    sync::{self, HeaderEntry, MAX_BLOCKS, MAX_HEADERS},
+};
 use colored::Colorize;
+use nexium::api::error::{ApiError, ErrorCode};
 use nexium::blockchain::transaction::Transaction;
 use nexium::defaults::NEXIUM_HOME;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::OpenOptions,
     io::{Read, Write},
     path::Path,
@@ -13,6 +18,16 @@ use std::{
 const PEERS_FILE: &str = "peers.json";
 const PEER_TIMEOUT_SECS: u64 = 5;
 const BROADCAST_TIMEOUT_SECS: u64 = 2;
+const SYNC_TIMEOUT_SECS: u64 = 30;
+
+/// Score from which a peer is banned
+pub const PEER_BAN_SCORE: u32 = 100;
+/// Penalty for relaying data that can't be decoded
+pub const PENALTY_MALFORMED: u32 = 10;
+/// Penalty for relaying a transaction of an emitter unknown to Gitlab
+pub const PENALTY_UNKNOWN_EMITTER: u32 = 20;
+/// Penalty for relaying a transaction with a forged signature
+pub const PENALTY_BAD_SIGNATURE: u32 = 50;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Peer {
@@ -181,27 +196,66 @@ impl Peer {
         Ok(info)
     }
 
-    /// Download the full blockchain from this peer
-    pub async fn download_blockchain(&self) -> Result<Vec<u8>, String> {
+    /// Headers of the blocks following the block `from`, or the first
+    /// blocks if `from` is `None`. `None` if the peer doesn't have `from`.
+    pub async fn get_headers(
+        &self,
+        from: Option<&HeaderPreviousBlockHash>,
+    ) -> Result<Option<Vec<HeaderEntry>>, String> {
+        match self.get_chain_data("headers", from, MAX_HEADERS).await? {
+            Some(data) => sync::decode_headers(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Blocks following the block `from`, or the first blocks if `from` is
+    /// `None`. `None` if the peer doesn't have `from`.
+    pub async fn get_blocks(
+        &self,
+        from: Option<&HeaderPreviousBlockHash>,
+    ) -> Result<Option<Vec<Block>>, String> {
+        match self.get_chain_data("blocks", from, MAX_BLOCKS).await? {
+            Some(data) => sync::decode_blocks(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Decoded body of `/<endpoint>?from=<hash>&limit=<limit>`, `None` if
+    /// the peer doesn't have the block `from`
+    async fn get_chain_data(
+        &self,
+        endpoint: &str,
+        from: Option<&HeaderPreviousBlockHash>,
+        limit: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
         let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30)) // Longer timeout for full download
+            .timeout(Duration::from_secs(SYNC_TIMEOUT_SECS))
             .build()
             .map_err(|e: reqwest::Error| e.to_string())?;
 
-        let url = format!("{}/blockchain_download", self.url());
+        let mut url = format!("{}/{}?limit={}", self.url(), endpoint, limit);
+        if let Some(hash) = from {
+            url.push_str(&format!("&from={}", hex::encode(hash)));
+        }
         let resp = client
             .get(&url)
             .send()
             .await
             .map_err(|e: reqwest::Error| e.to_string())?;
 
-        if !resp.status().is_success() {
-            return Err(format!("Failed to download blockchain: {}", resp.status()));
+        let status = resp.status();
+        let body = resp.text().await.map_err(|e: reqwest::Error| e.to_string())?;
+        if !status.is_success() {
+            if let Some(e) = ApiError::from_json(&body) {
+                if e.code == ErrorCode::UnknownBlock {
+                    return Ok(None);
+                }
+            }
+            return Err(format!("Failed to get {}: {}", endpoint, status));
         }
 
-        let body = resp.text().await.map_err(|e: reqwest::Error| e.to_string())?;
         use base64::{engine::general_purpose::STANDARD, Engine};
-        STANDARD.decode(&body).map_err(|e| e.to_string())
+        STANDARD.decode(&body).map(Some).map_err(|e| e.to_string())
     }
 }
 
@@ -209,18 +263,61 @@ impl Peer {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockchainInfo {
     pub block_count: u64,
+    /// Cumulative work of the chain, as a decimal string since it may not
+    /// fit in a JSON number
+    #[serde(default)]
+    pub chain_work: String,
     pub size: u64,
     pub last_hash: String,
 }
 
+impl BlockchainInfo {
+    /// Cumulative work of the chain, 0 for peers that don't send it
+    pub fn work(&self) -> u128 {
+        self.chain_work.parse().unwrap_or(0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerList {
     pub peers: Vec<Peer>,
+    /// Misbehavior score of each remote address, not persisted
+    #[serde(skip)]
+    pub scores: HashMap<String, u32>,
 }
 
 impl PeerList {
     pub fn new() -> Self {
-        Self { peers: vec![] }
+        Self {
+            peers: vec![],
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Check if the peers at `address` have been banned
+    pub fn is_banned(&self, address: &str) -> bool {
+        match self.scores.get(address) {
+            Some(s) => *s >= PEER_BAN_SCORE,
+            None => false,
+        }
+    }
+
+    /// Add `penalty` to the score of `address`. Once the ban score is
+    /// reached, the peers at this address are removed from the list.
+    /// Returns true if the address is banned.
+    pub fn penalize(&mut self, address: &str, penalty: u32) -> bool {
+        let score = self.scores.entry(address.to_string()).or_insert(0);
+        *score = score.saturating_add(penalty);
+        if *score < PEER_BAN_SCORE {
+            return false;
+        }
+
+        let count = self.peers.len();
+        self.peers.retain(|p| p.address != address);
+        if self.peers.len() != count {
+            let _ = self.save();
+        }
+        true
     }
 
     /// Get the path to the peers file
@@ -314,8 +411,11 @@ impl PeerList {
         Ok(())
     }
 
-    /// Add a peer to the list if not already present
+    /// Add a peer to the list if not already present nor banned
     pub fn add_peer(&mut self, peer: Peer) -> bool {
+        if self.is_banned(&peer.address) {
+            return false;
+        }
         if !self.peers.iter().any(|p| p.address == peer.address && p.port == peer.port) {
             self.peers.push(peer);
             true
@@ -326,7 +426,7 @@ impl PeerList {
 
     /// Discover peers by contacting all known peers, announcing ourselves, and exchanging peer lists
     /// Also announces to newly discovered peers (mesh propagation)
-    /// Returns (peers_added, best_peer_for_blockchain_sync)
+    /// Returns (peers_added, peer_with_the_most_chain_work)
     pub async fn discover(&mut self, self_address: &str, self_port: u16) -> (usize, Option<(Peer, BlockchainInfo)>) {
         let mut all_new_peers: HashSet<Peer> = HashSet::new();
         let self_peer = Peer::new(self_address.to_string(), self_port);
@@ -360,10 +460,10 @@ impl PeerList {
                             info.size
                         );
                         
-                        // Keep track of the peer with the longest chain
+                        // Keep track of the peer with the most work
                         let dominated = match &best_blockchain {
-                            Some((_, best_info)) => info.block_count > best_info.block_count,
-                            None => info.block_count > 0,
+                            Some((_, best_info)) => info.work() > best_info.work(),
+                            None => info.work() > 0,
                         };
                         if dominated {
                             best_blockchain = Some((peer.clone(), info));
@@ -410,8 +510,8 @@ impl PeerList {
                             );
                             // Update best blockchain if this one is better
                             let dominated = match &best_blockchain {
-                                Some((_, best_info)) => info.block_count > best_info.block_count,
-                                None => info.block_count > 0,
+                                Some((_, best_info)) => info.work() > best_info.work(),
+                                None => info.work() > 0,
                             };
                             if dominated {
                                 best_blockchain = Some((peer.clone(), info));